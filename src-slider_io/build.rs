@@ -0,0 +1,191 @@
+use flate2::{write::GzEncoder, Compression};
+use std::{
+  env, fs,
+  io::Write,
+  path::{Path, PathBuf},
+};
+
+const WEB_DIR: &str = "src/device/brokenithm-www";
+
+/// Replaced with the version of the embedded hand tracker files in every
+/// embedded file, for the `v` query of their URLs.
+const VERSION_PLACEHOLDER: &[u8] = b"@ASSET_VERSION@";
+
+/// Served path and source of each embedded web controller file.
+const WEB_FILES: &[(&str, &str)] = &[
+  ("app.js", "app.js"),
+  ("pair.js", "pair.js"),
+  ("index-ns.html", "index-ns.html"),
+  ("index-go.html", "index-go.html"),
+  ("index.html", "index.html"),
+  ("handtracker.html", "handtracker/index.html"),
+  ("styles.css", "handtracker/styles.css"),
+  (
+    "mediapipe-hands-min.js",
+    "handtracker/mediapipe-hands-min.js",
+  ),
+  ("favicon.ico", "favicon.ico"),
+  ("icon.png", "icon.png"),
+];
+
+/// Scripts of the MediaPipe tasks-vision library used by the hand tracker.
+const HAND_SCRIPT_FILES: &[(&str, &str)] = &[
+  (
+    "lib/tasks-vision/vision_bundle.mjs",
+    "handtracker/lib/tasks-vision/vision_bundle.mjs",
+  ),
+  (
+    "lib/tasks-vision/wasm/vision_wasm_internal.js",
+    "handtracker/lib/tasks-vision/wasm/vision_wasm_internal.js",
+  ),
+  (
+    "lib/tasks-vision/wasm/vision_wasm_nosimd_internal.js",
+    "handtracker/lib/tasks-vision/wasm/vision_wasm_nosimd_internal.js",
+  ),
+];
+
+/// The large wasm and model files, left out of the binary when built without
+/// the `embedded-hand-assets` feature.
+const HAND_BIN_FILES: &[(&str, &str)] = &[
+  (
+    "lib/tasks-vision/wasm/vision_wasm_internal.wasm",
+    "handtracker/lib/tasks-vision/wasm/vision_wasm_internal.wasm",
+  ),
+  (
+    "lib/tasks-vision/wasm/vision_wasm_nosimd_internal.wasm",
+    "handtracker/lib/tasks-vision/wasm/vision_wasm_nosimd_internal.wasm",
+  ),
+  (
+    "lib/models/hand_landmarker_full_float16.task",
+    "handtracker/lib/hand-landmarker_float_16/hand_landmarker.task",
+  ),
+];
+
+/// 64 bit FNV-1a, enough to tell file versions apart for caching.
+fn fnv1a(data: &[u8]) -> u64 {
+  data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+    (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
+/// Replaces every placeholder for the asset version in `data`.
+fn with_version(data: &[u8], version: &str) -> Vec<u8> {
+  let mut replaced = Vec::with_capacity(data.len());
+  let mut rest = data;
+  while let Some(idx) = rest
+    .windows(VERSION_PLACEHOLDER.len())
+    .position(|w| w == VERSION_PLACEHOLDER)
+  {
+    replaced.extend_from_slice(&rest[..idx]);
+    replaced.extend_from_slice(version.as_bytes());
+    rest = &rest[idx + VERSION_PLACEHOLDER.len()..];
+  }
+  replaced.extend_from_slice(rest);
+  replaced
+}
+
+fn source_path(source: &str) -> PathBuf {
+  Path::new(WEB_DIR).join(source).canonicalize().unwrap()
+}
+
+/// Version of a set of files, which changes whenever any of them does.
+fn files_version(files: &[(&str, &str)]) -> String {
+  let mut hashes = vec![];
+  for (served, source) in files {
+    let data = fs::read(source_path(source)).unwrap();
+    hashes.extend_from_slice(served.as_bytes());
+    hashes.extend_from_slice(&fnv1a(&data).to_le_bytes());
+  }
+  format!("{:016x}", fnv1a(&hashes))
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+  let mut encoder = GzEncoder::new(vec![], Compression::best());
+  encoder.write_all(data).unwrap();
+  encoder.finish().unwrap()
+}
+
+fn brotli(data: &[u8]) -> Vec<u8> {
+  // The best quality is too slow for the multi megabyte wasm and models
+  let quality = if data.len() > 1 << 20 { 9 } else { 11 };
+  let mut compressed = vec![];
+  {
+    let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, quality, 22);
+    writer.write_all(data).unwrap();
+  }
+  compressed
+}
+
+/// Writes a compressed variant to `OUT_DIR` when it saves enough to be worth
+/// sending, returning its path.
+fn variant(out_dir: &Path, name: &str, data: &[u8], compressed: Vec<u8>) -> Option<PathBuf> {
+  if compressed.len() as u64 * 100 > data.len() as u64 * 95 {
+    return None;
+  }
+  let path = out_dir.join(name);
+  fs::write(&path, compressed).unwrap();
+  Some(path)
+}
+
+fn file_map(name: &str, files: &[(&str, &str)], out_dir: &Path, version: &str) -> String {
+  let mut map = format!(
+    "static {}: phf::Map<&'static str, EmbeddedFile> = phf_map! {{\n",
+    name
+  );
+
+  for (served, source) in files {
+    let mut source = source_path(source);
+    let mut data = fs::read(&source).unwrap();
+    let stem = served.replace('/', "_");
+    let versioned = with_version(&data, version);
+    let has_version = versioned != data;
+    if has_version {
+      source = out_dir.join(&stem);
+      fs::write(&source, &versioned).unwrap();
+      data = versioned;
+    }
+    let hash = format!("{:016x}{:08x}", fnv1a(&data), data.len());
+
+    let encoded = |encoding: &str, path: Option<PathBuf>| match path {
+      Some(path) => format!(
+        "Some((include_bytes!({:?}), \"\\\"{}-{}\\\"\"))",
+        path, hash, encoding
+      ),
+      None => "None".to_string(),
+    };
+    let gzip = encoded(
+      "gz",
+      variant(out_dir, &format!("{}.gz", stem), &data, gzip(&data)),
+    );
+    let brotli = encoded(
+      "br",
+      variant(out_dir, &format!("{}.br", stem), &data, brotli(&data)),
+    );
+
+    map += &format!(
+      "  {:?} => EmbeddedFile {{ data: include_bytes!({:?}), etag: \"\\\"{}\\\"\", gzip: {}, brotli: {}, has_version: {} }},\n",
+      served, source, hash, gzip, brotli, has_version
+    );
+  }
+
+  map + "};\n"
+}
+
+/// Embeds the web controller files with a strong ETag and precompressed
+/// variants of each, and the version of the hand tracker files as
+/// `ASSET_VERSION`.
+fn main() {
+  println!("cargo:rerun-if-changed={}", WEB_DIR);
+
+  let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+  let mut hand_files = HAND_SCRIPT_FILES.to_vec();
+  if env::var("CARGO_FEATURE_EMBEDDED_HAND_ASSETS").is_ok() {
+    hand_files.extend_from_slice(HAND_BIN_FILES);
+  }
+
+  let version = files_version(&hand_files);
+  let generated = format!("const ASSET_VERSION: &str = {:?};\n", version)
+    + &file_map("WEB_FILES", WEB_FILES, &out_dir, &version)
+    + &file_map("HAND_FILES", &hand_files, &out_dir, &version);
+  fs::write(out_dir.join("embedded_files.rs"), generated).unwrap();
+}
@@ -0,0 +1,242 @@
+extern crate slider_io;
+
+use serde_json::Value;
+use std::{env, fs, path::Path, process};
+
+use slider_io::handtracking::{
+  config::{HandActionMode, HandTrackingConfig},
+  fusion::HandFusion,
+  recorder::{read_recording, RecordedSegment},
+  tracker::HandTracker,
+};
+
+/// Activations further apart than this, in milliseconds, are not matched.
+const MATCH_WINDOW: f64 = 250.0;
+
+const USAGE: &str = "Usage: replay_hands <recording.jsonl> [overrides...]
+
+Replays a hand tracking recording through the air zone logic and reports the
+air activations of each string, from 5 at the top to 0 at the bottom. Latency
+is measured against a reference that uses the raw hand positions, without
+smoothing or hysteresis.
+
+Each override is a JSON object of hand config keys, inline or in a file, for
+example '{\"handZoneBottom\": 0.5}'. Every override is replayed on top of the
+recorded calibration, so several settings can be compared in one run. Without
+overrides the recorded calibration is replayed. Calibration changes made while
+recording apply from where they were made, under the override.";
+
+/// Time spans in milliseconds during which one air string was pressed.
+type Activations = Vec<(f64, f64)>;
+
+/// Replays the frames of each segment with its calibration.
+fn replay(segments: &[(HandTrackingConfig, &RecordedSegment)]) -> [Activations; 6] {
+  let mut activations: [Activations; 6] = Default::default();
+  let mut since = [None; 6];
+  let mut end = 0.0;
+
+  for (config, segment) in segments {
+    // Like the server, sources start over with a new calibration
+    let mut fusion = HandFusion::new(config);
+    let mut tracker = HandTracker::new(&config.upright());
+    for recorded in segment.frames.iter() {
+      let fused = fusion.update(recorded.id, &recorded.frame, recorded.received);
+      let air = tracker.update(&fused).air;
+      for (idx, pressed) in air.iter().enumerate() {
+        match (*pressed > 0, since[idx]) {
+          (true, None) => since[idx] = Some(recorded.received),
+          (false, Some(start)) => {
+            activations[idx].push((start, recorded.received));
+            since[idx] = None;
+          }
+          _ => {}
+        }
+      }
+      end = recorded.received;
+    }
+  }
+
+  for (idx, start) in since.iter().enumerate() {
+    if let Some(start) = start {
+      activations[idx].push((*start, end));
+    }
+  }
+  activations
+}
+
+/// The same calibration without smoothing, hysteresis or actions.
+fn reference_config(config: &HandTrackingConfig) -> HandTrackingConfig {
+  HandTrackingConfig {
+    hysteresis: 0.0,
+    filter_min_cutoff: 1e9,
+    filter_beta: 0.0,
+    filter_d_cutoff: 1e9,
+    actions: HandActionMode::Off,
+    gestures: vec![],
+    ..config.clone()
+  }
+}
+
+struct StringReport {
+  activations: usize,
+  held: f64,
+  missed: usize,
+  extra: usize,
+  latencies: Vec<f64>,
+}
+
+fn compare(tuned: &Activations, reference: &Activations) -> StringReport {
+  let mut latencies = vec![];
+  let mut matched = vec![false; tuned.len()];
+  let mut missed = 0;
+
+  for (start, _) in reference.iter() {
+    let found = tuned
+      .iter()
+      .enumerate()
+      .find(|(idx, (t, _))| !matched[*idx] && (t - start).abs() <= MATCH_WINDOW);
+    match found {
+      Some((idx, (t, _))) => {
+        matched[idx] = true;
+        latencies.push(t - start);
+      }
+      None => missed += 1,
+    }
+  }
+
+  StringReport {
+    activations: tuned.len(),
+    held: tuned
+      .iter()
+      .fold(0.0, |acc, (start, end)| acc + end - start)
+      / 1000.0,
+    missed,
+    extra: matched.iter().filter(|m| !**m).count(),
+    latencies,
+  }
+}
+
+fn latency_summary(latencies: &[f64]) -> String {
+  if latencies.is_empty() {
+    return "-".to_string();
+  }
+  let mut sorted = latencies.to_vec();
+  sorted.sort_by(|a, b| a.total_cmp(b));
+  let mean = sorted.iter().sum::<f64>() / sorted.len() as f64;
+  let p95 = sorted[((sorted.len() as f64 * 0.95).ceil() as usize).max(1) - 1];
+  format!("{:.0} / {:.0}", mean, p95)
+}
+
+fn parse_override(arg: &str) -> Result<Value, String> {
+  let text = match arg.trim_start().starts_with('{') {
+    true => arg.to_string(),
+    false => fs::read_to_string(arg).map_err(|e| format!("{}: {}", arg, e))?,
+  };
+  match serde_json::from_str(&text) {
+    Ok(Value::Object(keys)) => Ok(Value::Object(keys)),
+    _ => Err(format!("{}: expected a JSON object", arg)),
+  }
+}
+
+fn main() {
+  let args = env::args().skip(1).collect::<Vec<String>>();
+  if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+    println!("{}", USAGE);
+    process::exit(if args.is_empty() { 1 } else { 0 });
+  }
+
+  let segments = match read_recording(Path::new(&args[0])) {
+    Ok(segments) => segments,
+    Err(e) => {
+      eprintln!("Could not read {}: {}", args[0], e);
+      process::exit(1);
+    }
+  };
+  let frames = segments
+    .iter()
+    .flat_map(|s| s.frames.iter())
+    .collect::<Vec<_>>();
+
+  let mut runs = vec![("recorded".to_string(), Value::Object(Default::default()))];
+  if args.len() > 1 {
+    runs.clear();
+    for arg in args[1..].iter() {
+      let overrides = match parse_override(arg) {
+        Ok(overrides) => overrides,
+        Err(e) => {
+          eprintln!("{}", e);
+          process::exit(1);
+        }
+      };
+      runs.push((overrides.to_string(), overrides));
+    }
+  }
+
+  let duration = match (frames.first(), frames.last()) {
+    (Some(first), Some(last)) => (last.received - first.received) / 1000.0,
+    _ => 0.0,
+  };
+  let mut clients = frames.iter().map(|f| f.id).collect::<Vec<usize>>();
+  clients.sort_unstable();
+  clients.dedup();
+  println!(
+    "{} frames over {:.1} s ({:.1} fps) from {} client(s), {} calibration change(s)",
+    frames.len(),
+    duration,
+    frames.len() as f64 / duration.max(1e-3),
+    clients.len(),
+    segments.len() - 1
+  );
+
+  for (name, overrides) in runs {
+    let configs = segments
+      .iter()
+      .map(|segment| {
+        let mut merged = segment.calibration.clone();
+        for (key, value) in overrides.as_object().unwrap() {
+          merged[key] = value.clone();
+        }
+        HandTrackingConfig::from_serde_value(&merged)
+      })
+      .collect::<Vec<_>>();
+    println!();
+    println!("Settings: {}", name);
+    if let Some(e) = configs.iter().find_map(|c| c.validate().err()) {
+      println!("  Invalid settings: {}", e);
+      continue;
+    }
+
+    let tuned = replay(
+      &configs
+        .iter()
+        .cloned()
+        .zip(segments.iter())
+        .collect::<Vec<_>>(),
+    );
+    let reference = replay(
+      &configs
+        .iter()
+        .map(reference_config)
+        .zip(segments.iter())
+        .collect::<Vec<_>>(),
+    );
+
+    println!("  string  activations  held s  reference  missed  extra  latency mean / p95 ms");
+    let mut all_latencies = vec![];
+    for idx in (0..6).rev() {
+      let report = compare(&tuned[idx], &reference[idx]);
+      println!(
+        "  {:>6}  {:>11}  {:>6.1}  {:>9}  {:>6}  {:>5}  {}",
+        idx,
+        report.activations,
+        report.held,
+        reference[idx].len(),
+        report.missed,
+        report.extra,
+        latency_summary(&report.latencies)
+      );
+      all_latencies.extend(report.latencies);
+    }
+    println!("  all latency {} ms", latency_summary(&all_latencies));
+  }
+}
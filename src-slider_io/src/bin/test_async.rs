@@ -5,7 +5,10 @@ use std::{future::Future, io, time::Duration};
 
 use tokio::{select, time::sleep};
 
-use slider_io::shared::worker::{AsyncHaltableJob, AsyncHaltableWorker};
+use slider_io::{
+  shared::worker::{AsyncHaltableJob, AsyncHaltableWorker},
+  state::SliderState,
+};
 
 struct CounterJob;
 
@@ -40,7 +43,8 @@ async fn main() {
     .filter_level(log::LevelFilter::Debug)
     .init();
 
-  let _worker = AsyncHaltableWorker::new("counter", CounterJob);
+  let state = SliderState::new();
+  let _worker = AsyncHaltableWorker::new("counter", CounterJob, &state);
   let mut input = String::new();
   io::stdin().read_line(&mut input).unwrap();
 }
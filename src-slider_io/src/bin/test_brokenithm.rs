@@ -1,10 +1,17 @@
 extern crate slider_io;
 
+use serde_json::Value;
 use std::io;
 
 use slider_io::{
-  device::{brokenithm::BrokenithmJob, config::BrokenithmSpec},
-  shared::worker::AsyncHaltableWorker,
+  device::{
+    brokenithm::BrokenithmJob,
+    config::{
+      BrokenithmMerge, BrokenithmOptions, BrokenithmSpec, BrokenithmTls, BrokenithmWebConfig,
+    },
+  },
+  handtracking::config::HandTrackingConfig,
+  shared::{access::ServerAccessConfig, worker::AsyncHaltableWorker},
   state::SliderState,
 };
 
@@ -18,7 +25,31 @@ async fn main() {
 
   let _worker = AsyncHaltableWorker::new(
     "brokenithm",
-    BrokenithmJob::new(&state, &BrokenithmSpec::Nostalgia, &false, &1606),
+    BrokenithmJob::new(
+      &state,
+      &BrokenithmSpec::Nostalgia,
+      &false,
+      &1606,
+      &BrokenithmOptions {
+        merge: BrokenithmMerge::Or,
+        tls: BrokenithmTls::Off,
+        tls_port: 1607,
+        tls_cert: None,
+        tls_key: None,
+        pairing: false,
+        allowed_origins: vec![],
+        access: ServerAccessConfig::from_serde_value(&Value::Null, "brokenithm"),
+        fallback_ports: 0,
+        latency_warn_ms: 50.0,
+        mdns_name: None,
+        skin: None,
+        web: BrokenithmWebConfig::default(),
+        hand_tracking: HandTrackingConfig::default(),
+        hand_asset_dir: None,
+        hand_record: false,
+      },
+    ),
+    &state,
   );
   let mut input = String::new();
   io::stdin().read_line(&mut input).unwrap();
@@ -1,8 +1,8 @@
 extern crate slider_io;
 
-use std::io;
+use std::{io, sync::Arc};
 
-use slider_io::{config::Config, context::Context};
+use slider_io::{config::Config, context::Context, state::SliderControl};
 
 #[tokio::main]
 async fn main() {
@@ -35,7 +35,7 @@ async fn main() {
   println!("{:?}", config);
 
   #[allow(unused_variables)]
-  let ctx = Context::new(config);
+  let ctx = Context::new(config, &Arc::new(SliderControl::new()));
 
   println!("Press enter to quit");
   let mut input = String::new();
@@ -2,9 +2,12 @@ extern crate slider_io;
 
 use std::{io, thread::sleep, time::Duration};
 
-use slider_io::shared::{
-  utils::LoopTimer,
-  worker::{ThreadJob, ThreadWorker},
+use slider_io::{
+  shared::{
+    utils::LoopTimer,
+    worker::{ThreadJob, ThreadWorker},
+  },
+  state::SliderState,
 };
 
 struct TestJob {
@@ -33,8 +36,9 @@ impl Drop for TestJob {
 }
 
 fn main() {
+  let state = SliderState::new();
   let timer = LoopTimer::new();
-  let _worker = ThreadWorker::new("j", TestJob { data: 1 }, timer);
+  let _worker = ThreadWorker::new("j", TestJob { data: 1 }, timer, &state);
 
   let mut input = String::new();
   io::stdin().read_line(&mut input).unwrap();
@@ -1,31 +1,73 @@
 use log::{info, warn};
 use serde_json::Value;
-use std::fs;
+use std::{fs, time::Duration};
 
 use crate::{
-  device::config::DeviceMode, lighting::config::LightsMode, output::config::OutputMode, system,
+  device::config::DeviceMode, lighting::config::LightsMode, meta::config::MetaConfig,
+  output::config::OutputMode, system,
 };
 
 #[derive(Debug, Clone)]
 pub struct Config {
   pub raw: String,
   pub device_mode: DeviceMode,
+  pub stale_timeout: Option<Duration>,
   pub output_mode: OutputMode,
   pub lights_mode: LightsMode,
+  pub meta: MetaConfig,
+  pub profiles: Vec<Value>,
 }
 
 impl Config {
   pub fn from_str(s: &str) -> Option<Config> {
     let v: Value = serde_json::from_str(s).ok()?;
+    let device_mode = DeviceMode::from_serde_value(&v)?;
 
     Some(Config {
       raw: s.to_string(),
-      device_mode: DeviceMode::from_serde_value(&v)?,
+      stale_timeout: device_mode.stale_timeout_from_serde_value(&v),
+      device_mode,
       output_mode: OutputMode::from_serde_value(&v)?,
       lights_mode: LightsMode::from_serde_value(&v)?,
+      meta: MetaConfig::from_serde_value(&v),
+      profiles: v["profiles"].as_array().cloned().unwrap_or_default(),
     })
   }
 
+  /// Builds the config for a profile. Profile 0 is this config, later profiles
+  /// override keys of this config with entries from `profiles`.
+  pub fn with_profile(&self, idx: usize) -> Option<Config> {
+    if idx == 0 {
+      return Some(self.clone());
+    }
+
+    let mut config = self.with_values(self.profiles.get(idx - 1)?)?;
+    // Keep the base config around so that profiles are never saved over it
+    config.raw = self.raw.clone();
+    Some(config)
+  }
+
+  /// Builds a config with the keys of `values` replaced.
+  pub fn with_values(&self, values: &Value) -> Option<Config> {
+    let mut v: Value = serde_json::from_str(&self.raw).ok()?;
+    let base = v.as_object_mut()?;
+    for (key, value) in values.as_object()?.iter() {
+      base.insert(key.clone(), value.clone());
+    }
+
+    Self::from_str(&v.to_string())
+  }
+
+  /// Replaces the keys of `values` in the saved config, for settings changed
+  /// from outside the UI.
+  pub fn update_saved(values: &Value) -> Option<Config> {
+    let config = Self::load_saved()
+      .unwrap_or_else(Self::default)
+      .with_values(values)?;
+    config.save()?;
+    Some(config)
+  }
+
   fn default() -> Self {
     Self::from_str(
       r##"{
@@ -36,6 +78,63 @@ impl Config {
       "divaSerialPort": "COM1",
       "divaBrightness": 63,
       "brokenithmPort": 1606,
+      "brokenithmMerge": "or",
+      "brokenithmTls": "off",
+      "brokenithmTlsPort": 1607,
+      "brokenithmTlsCert": "",
+      "brokenithmTlsKey": "",
+      "brokenithmPairing": false,
+      "brokenithmSkin": "",
+      "brokenithmInvert": false,
+      "brokenithmKeyColor": "#FF00FF",
+      "brokenithmAirColor": "#00FFFF",
+      "brokenithmBgColor": "#000000",
+      "brokenithmBgImage": "",
+      "brokenithmAirHeight": 1.0,
+      "brokenithmLedInterval": 50,
+      "brokenithmBindAddress": "0.0.0.0",
+      "brokenithmAllow": "",
+      "brokenithmDeny": "",
+      "brokenithmAllowedOrigins": "",
+      "brokenithmMaxConnections": 8,
+      "brokenithmMaxRequestRate": 20,
+      "brokenithmFallbackPorts": 0,
+      "brokenithmLatencyWarnMs": 50,
+      "handZoneTop": 0.0,
+      "handZoneBottom": 0.4,
+      "handZoneHeights": [1, 1, 1, 1, 1, 1],
+      "handHysteresis": 0.15,
+      "handMinConfidence": 0.5,
+      "handModel": "full-float16",
+      "handAssetDir": "",
+      "handDetectionConfidence": 0.25,
+      "handPresenceConfidence": 0.5,
+      "handTrackingConfidence": 0.1,
+      "handSourceTimeoutMs": 250,
+      "handCameraRotation": 0,
+      "handCameraMirror": false,
+      "handFilterMinCutoff": 1.5,
+      "handFilterBeta": 2.0,
+      "handFilterDCutoff": 1.0,
+      "handActions": "off",
+      "handFlickSpeed": 1.5,
+      "handSwipeSpeed": 1.5,
+      "handHoldSpeed": 0.2,
+      "handHoldMs": 250,
+      "handPulseMs": 80,
+      "handFlickAir": [0, 1, 2, 3, 4, 5],
+      "handSwipeAir": [0, 1, 2, 3, 4, 5],
+      "handHoldAir": [0, 1, 2, 3, 4, 5],
+      "handGestures": [],
+      "handRecord": false,
+      "handVideoSource": "",
+      "handVideoModel": "",
+      "handVideoPalmModel": "",
+      "handVideoFps": 30,
+      "staleTimeoutHardware": 0,
+      "staleTimeoutDiva": 500,
+      "staleTimeoutBrokenithm": 3000,
+      "staleTimeoutHandVideo": 1000,
       "keyboardSensitivity": 20,
       "keyboardDirectInput": false,
       "outputPolling": "100",
@@ -47,13 +146,21 @@ impl Config {
       "ledColorAirInactive": "#000000",
       "ledSensitivity": 20,
       "ledWebsocketUrl": "localhost:3001",
-      "ledSerialPort": "COM5"
+      "ledSerialPort": "COM5",
+      "ledUmgrBindAddress": "0.0.0.0",
+      "ledUmgrAllow": "",
+      "ledUmgrDeny": "",
+      "ledUmgrMaxConnections": 8,
+      "ledUmgrMaxRequestRate": 20,
+      "mdnsName": "slidershim",
+      "metaChords": [],
+      "profiles": []
     }"##,
     )
     .unwrap()
   }
 
-  fn load_saved() -> Option<Self> {
+  pub fn load_saved() -> Option<Self> {
     let config_path = system::get_config_path()?;
     if !config_path.exists() {
       return None;
@@ -4,14 +4,17 @@ use std::sync::{atomic::Ordering, Arc};
 
 use crate::{
   config::Config,
-  device::{brokenithm::BrokenithmJob, config::DeviceMode, diva::DivaSliderJob, hid::HidJob},
+  device::{
+    brokenithm::BrokenithmJob, config::DeviceMode, diva::DivaSliderJob, hand_video::HandVideoJob,
+    hid::HidJob, watchdog::StaleInputJob,
+  },
   lighting::{config::LightsMode, lighting::LightsJob, umgr_websocket::UmgrWebsocketJob},
   output::{config::OutputMode, output::OutputJob},
   shared::{
     utils::LoopTimer,
-    worker::{AsyncHaltableWorker, AsyncWorker, ThreadWorker},
+    worker::{AsyncHaltableWorker, AsyncWorker, StallJob, ThreadWorker},
   },
-  state::SliderState,
+  state::{SliderControl, SliderState},
 };
 
 #[allow(dead_code)]
@@ -21,20 +24,25 @@ pub struct Context {
   device_thread_worker: Option<ThreadWorker>,
   device_async_worker: Option<AsyncWorker>,
   device_async_haltable_worker: Option<AsyncHaltableWorker>,
+  device_watchdog_worker: Option<AsyncWorker>,
   output_worker: Option<AsyncWorker>,
   lights_worker: Option<AsyncWorker>,
   lights_haltable_worker: Option<AsyncHaltableWorker>,
+  stall_worker: Option<AsyncWorker>,
   timers: Vec<(&'static str, Arc<AtomicF64>)>,
 }
 
 impl Context {
-  pub fn new(config: Config) -> Self {
+  pub fn new(config: Config, control: &Arc<SliderControl>) -> Self {
     info!("Context creating");
     info!("Device config {:?}", config.device_mode);
     info!("Output config {:?}", config.output_mode);
     info!("Lights config {:?}", config.lights_mode);
 
-    let state = SliderState::new();
+    // Workers of the previous context no longer apply
+    control.worker_status.lock().clear();
+
+    let state = SliderState::with_control(control);
     let mut timers = vec![];
 
     let (device_thread_worker, device_async_worker, device_async_haltable_worker) =
@@ -44,12 +52,14 @@ impl Context {
           spec,
           lights_enabled,
           port,
+          options,
         } => (
           None,
           None,
           Some(AsyncHaltableWorker::new(
             "brokenithm",
-            BrokenithmJob::new(&state, spec, lights_enabled, port),
+            BrokenithmJob::new(&state, spec, lights_enabled, port, options),
+            &state,
           )),
         ),
         DeviceMode::Hardware { spec, disable_air } => (
@@ -60,6 +70,7 @@ impl Context {
               "device",
               HidJob::from_config(&state, spec, disable_air),
               timer,
+              &state,
             ))
           },
           None,
@@ -73,12 +84,35 @@ impl Context {
               "diva",
               DivaSliderJob::new(&state, port, *brightness),
               timer,
+              &state,
+            ))
+          },
+          None,
+          None,
+        ),
+        DeviceMode::HandVideo { options } => (
+          {
+            let timer = LoopTimer::new();
+            timers.push(("d", timer.fork()));
+            Some(ThreadWorker::new(
+              "hand-video",
+              HandVideoJob::new(&state, options),
+              timer,
+              &state,
             ))
           },
           None,
           None,
         ),
       };
+    let device_watchdog_worker = config.stale_timeout.as_ref().map(|timeout| {
+      AsyncWorker::new(
+        "watchdog",
+        StaleInputJob::new(&state, timeout),
+        LoopTimer::new(),
+        &state,
+      )
+    });
     let output_worker = match &config.output_mode {
       OutputMode::None => None,
       _ => {
@@ -86,18 +120,25 @@ impl Context {
         timers.push(("o", timer.fork()));
         Some(AsyncWorker::new(
           "output",
-          OutputJob::new(&state, &config.output_mode),
+          OutputJob::new(&state, &config.output_mode, &config.meta),
           timer,
+          &state,
         ))
       }
     };
     let (lights_worker, lights_haltable_worker) = match &config.lights_mode {
       LightsMode::None => (None, None),
-      LightsMode::UmgrWebsocket { faster, port } => (
+      LightsMode::UmgrWebsocket {
+        faster,
+        port,
+        access,
+        mdns_name,
+      } => (
         None,
         Some(AsyncHaltableWorker::new(
           "lights",
-          UmgrWebsocketJob::new(&state, faster, port),
+          UmgrWebsocketJob::new(&state, faster, port, access, mdns_name),
+          &state,
         )),
       ),
       _ => {
@@ -108,21 +149,42 @@ impl Context {
             "lights",
             LightsJob::new(&state, &config.lights_mode),
             timer,
+            &state,
           )),
           None,
         )
       }
     };
 
+    let heartbeats: Vec<_> = [
+      device_thread_worker.as_ref().map(|w| w.heartbeat()),
+      output_worker.as_ref().map(|w| w.heartbeat()),
+      lights_worker.as_ref().map(|w| w.heartbeat()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let stall_worker = match heartbeats.len() {
+      0 => None,
+      _ => Some(AsyncWorker::new(
+        "stall",
+        StallJob::new(&state, heartbeats),
+        LoopTimer::new(),
+        &state,
+      )),
+    };
+
     Self {
       state,
       config,
       device_thread_worker,
       device_async_worker,
       device_async_haltable_worker,
+      device_watchdog_worker,
       output_worker,
       lights_worker,
       lights_haltable_worker,
+      stall_worker,
       timers,
     }
   }
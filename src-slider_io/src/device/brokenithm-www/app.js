@@ -1 +1 @@
-"use strict";var throttle=function(e,t){var a=!0,n=null;return function o(){var s=this;a?(a=!1,setTimeout(function(){a=!0,n&&o.apply(s)},t),n?(e.apply(this,n),n=null):e.apply(this,arguments)):n=arguments}},keys=document.getElementsByClassName("key"),airKeys=[],midline=0,touchKeys=[],allKeys=[],topKeys=airKeys,bottomKeys=touchKeys,compileKey=function(e){var t=e.previousElementSibling,a=e.nextElementSibling;return{top:e.offsetTop,bottom:e.offsetTop+e.offsetHeight,left:e.offsetLeft,right:e.offsetLeft+e.offsetWidth,almostLeft:t?e.offsetLeft+e.offsetWidth/4:-99999,almostRight:a?e.offsetLeft+3*e.offsetWidth/4:99999,kflag:parseInt(e.dataset.kflag)+(parseInt(e.dataset.air)?32:0),isAir:!!parseInt(e.dataset.air)||(window.allAir||!1),prevKeyRef:t,prevKeyKflag:t?parseInt(t.dataset.kflag)+(parseInt(t.dataset.air)?32:0):null,nextKeyRef:a,nextKeyKflag:a?parseInt(a.dataset.kflag)+(parseInt(a.dataset.air)?32:0):null,ref:e}},isInside=function(e,t,a){return a.left<=e&&e<a.right&&a.top<=t&&t<a.bottom},compileKeys=function(){keys=document.getElementsByClassName("key"),airKeys=[],touchKeys=[];for(var e=0;e<keys.length;e++){var t=compileKey(keys[e]);t.kflag<32?touchKeys.push(t):airKeys.push(t),allKeys.push(t)}touchKeys.memo={},airKeys.memo={},touchKeys.getAxis=function(e,t){return e},airKeys.getAxis=function(e,t){return t};var a=function(e,t){var a=this.getAxis(e,t),n=this.memo[a];if(void 0===n){for(var o=0;o<this.length;o++)if(isInside(e,t,this[o])){n=this[o];break}this.memo[a]=n}return n};touchKeys.getKey=a,airKeys.getKey=a;for(e=0;e<window.outerWidth;e++)touchKeys.getKey(e,touchKeys[0].top);for(e=0;e<window.outerHeight;e++)airKeys.getKey(airKeys[0].left,e);config.invert?(topKeys=touchKeys,bottomKeys=airKeys,midline=touchKeys[0].bottom):(topKeys=airKeys,bottomKeys=touchKeys,midline=touchKeys[0].top)},getKey=function(e,t){return t<midline?topKeys.getKey(e,t):bottomKeys.getKey(e,t)},lastState=[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0];function updateTouches(e){try{e.preventDefault();var t=[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0];throttledRequestFullscreen();for(var a=0;a<e.touches.length;a++){var n=e.touches[a],o=n.clientX,s=n.clientY,r=getKey(o,s);r&&(setKey(t,r.kflag,r.isAir),r.isAir||(o<r.almostLeft&&setKey(t,r.prevKeyKflag,!1),r.almostRight<o&&setKey(t,r.nextKeyKflag,!1)))}for(a=0;a<allKeys.length;a++){var i=allKeys[a],c=i.kflag;t[c]!==lastState[c]&&(t[c]?i.ref.setAttribute("data-active",""):i.ref.removeAttribute("data-active"))}t!==lastState&&throttledSendKeys(t),lastState=t}catch(e){alert(e)}}var throttledUpdateTouches=throttle(updateTouches,10),setKey=function(e,t,a){var n=t;e[n]&&!a&&n++,e[n]=1},sendKeys=function(e){wsConnected&&ws.send("b"+e.join(""))},throttledSendKeys=throttle(sendKeys,10),ws=null,wsTimeout=0,wsConnected=!1,wsConnect=function(){(ws=new WebSocket((location.protocol=="https:"?"wss://":"ws://")+location.host+"/ws")).binaryType="arraybuffer",ws.onopen=function(){ws.send("alive?")},ws.onmessage=function(e){e.data.byteLength?updateLed(e.data):"alive"==e.data&&(wsTimeout=0,wsConnected=!0)}},wsWatch=function(){if(wsTimeout++>2)return wsTimeout=0,ws.close(),wsConnected=!1,void wsConnect();wsConnected&&ws.send("alive?")},canvas=document.getElementById("canvas"),canvasCtx=canvas.getContext("2d"),canvasData=canvasCtx.getImageData(0,0,33,1),setupLed=function(){for(var e=0;e<33;e++)canvasData.data[4*e+3]=255};setupLed();var updateLed=function(e){for(var t=new Uint8Array(e),a=0;a<31;a++)canvasData.data[4*a+4]=t[3*a],canvasData.data[4*a+5]=t[3*a+1],canvasData.data[4*a+6]=t[3*a+2];canvasData.data[0]=t[0],canvasData.data[1]=t[1],canvasData.data[2]=t[2],canvasData.data[128]=t[90],canvasData.data[129]=t[91],canvasData.data[130]=t[92],canvasCtx.putImageData(canvasData,0,0)},fs=document.getElementById("fullscreen"),requestFullscreen=function(){!document.fullscreenElement&&screen.height<=1024&&(fs.requestFullscreen?fs.requestFullscreen():fs.mozRequestFullScreen?fs.mozRequestFullScreen():fs.webkitRequestFullScreen&&fs.webkitRequestFullScreen())},throttledRequestFullscreen=throttle(requestFullscreen,3e3),cnt=document.getElementById("main");cnt.addEventListener("touchstart",updateTouches),cnt.addEventListener("touchmove",updateTouches),cnt.addEventListener("touchend",updateTouches);var readConfig=function(e){var t="";e.invert&&(t+=".container, .air-container {flex-flow: column-reverse nowrap;} ");var a=e.bgColor||"rbga(0, 0, 0, 0.9)";e.bgImage?t+="#fullscreen {background: ".concat(a,' url("').concat(e.bgImage,'") fixed center / cover!important; background-repeat: no-repeat;} '):t+="#fullscreen {background: ".concat(a,";} "),"number"==typeof e.ledOpacity&&(0===e.ledOpacity?t+="#canvas {display: none} ":t+="#canvas {opacity: ".concat(e.ledOpacity,"} ")),"string"==typeof e.keyColor&&(t+=".key[data-active] {background-color: ".concat(e.keyColor,";} ")),"string"==typeof e.keyColor&&(t+=".key.air[data-active] {background-color: ".concat(e.lkeyColor,";} ")),"string"==typeof e.keyBorderColor&&(t+=".key {border: 1px solid ".concat(e.keyBorderColor,";} ")),e.keyColorFade&&"number"==typeof e.keyColorFade&&(t+=".key:not([data-active]) {transition: background ".concat(e.keyColorFade,"ms ease-out;} ")),"number"==typeof e.keyHeight&&(0===e.keyHeight?t+=".touch-container {display: none;} ":t+=".touch-container {flex: ".concat(e.keyHeight,";} ")),"number"==typeof e.lkeyHeight&&(0===e.lkeyHeight?t+=".air-container {display: none;} ":t+=".air-container {flex: ".concat(e.keyHeight,";} "));var n=document.createElement("style");n.innerHTML=t,document.head.appendChild(n)},initialize=function(){readConfig(config),compileKeys(),wsConnect(),setInterval(wsWatch,1e3)};initialize(),window.onresize=compileKeys;
+"use strict";var throttle=function(e,t){var a=!0,n=null;return function o(){var s=this;a?(a=!1,setTimeout(function(){a=!0,n&&o.apply(s)},t),n?(e.apply(this,n),n=null):e.apply(this,arguments)):n=arguments}},keys=document.getElementsByClassName("key"),airKeys=[],midline=0,touchKeys=[],allKeys=[],topKeys=airKeys,bottomKeys=touchKeys,compileKey=function(e){var t=e.previousElementSibling,a=e.nextElementSibling;return{top:e.offsetTop,bottom:e.offsetTop+e.offsetHeight,left:e.offsetLeft,right:e.offsetLeft+e.offsetWidth,almostLeft:t?e.offsetLeft+e.offsetWidth/4:-99999,almostRight:a?e.offsetLeft+3*e.offsetWidth/4:99999,kflag:parseInt(e.dataset.kflag)+(parseInt(e.dataset.air)?32:0),isAir:!!parseInt(e.dataset.air)||(window.allAir||config.allAir||!1),prevKeyRef:t,prevKeyKflag:t?parseInt(t.dataset.kflag)+(parseInt(t.dataset.air)?32:0):null,nextKeyRef:a,nextKeyKflag:a?parseInt(a.dataset.kflag)+(parseInt(a.dataset.air)?32:0):null,ref:e}},isInside=function(e,t,a){return a.left<=e&&e<a.right&&a.top<=t&&t<a.bottom},compileKeys=function(){keys=document.getElementsByClassName("key"),airKeys=[],touchKeys=[];for(var e=0;e<keys.length;e++){var t=compileKey(keys[e]);t.kflag<32?touchKeys.push(t):airKeys.push(t),allKeys.push(t)}touchKeys.memo={},airKeys.memo={},touchKeys.getAxis=function(e,t){return e},airKeys.getAxis=function(e,t){return t};var a=function(e,t){var a=this.getAxis(e,t),n=this.memo[a];if(void 0===n){for(var o=0;o<this.length;o++)if(isInside(e,t,this[o])){n=this[o];break}this.memo[a]=n}return n};touchKeys.getKey=a,airKeys.getKey=a;for(e=0;e<window.outerWidth;e++)touchKeys.getKey(e,touchKeys[0].top);for(e=0;e<window.outerHeight;e++)airKeys.getKey(airKeys[0].left,e);config.invert?(topKeys=touchKeys,bottomKeys=airKeys,midline=touchKeys[0].bottom):(topKeys=airKeys,bottomKeys=touchKeys,midline=touchKeys[0].top)},getKey=function(e,t){return t<midline?topKeys.getKey(e,t):bottomKeys.getKey(e,t)},lastState=[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0];function updateTouches(e){try{e.preventDefault();var t=[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0];throttledRequestFullscreen();for(var a=0;a<e.touches.length;a++){var n=e.touches[a],o=n.clientX,s=n.clientY,r=getKey(o,s);if(r){var l=getPressure(n);setKey(t,r.kflag,r.isAir,l),r.isAir||(o<r.almostLeft&&setKey(t,r.prevKeyKflag,!1,l),r.almostRight<o&&setKey(t,r.nextKeyKflag,!1,l))}}for(a=0;a<allKeys.length;a++){var i=allKeys[a],c=i.kflag;t[c]!==lastState[c]&&(t[c]?i.ref.setAttribute("data-active",""):i.ref.removeAttribute("data-active"))}t!==lastState&&throttledSendKeys(t),lastState=t}catch(e){alert(e)}}var throttledUpdateTouches=throttle(updateTouches,10),setKey=function(e,t,a,n){var o=t;e[o]&&!a&&o++,e[o]=Math.max(e[o],n)},getPressure=function(e){var t=1;e.force>0?t=e.force:e.radiusX>0&&(t=Math.min(1,(e.radiusX+e.radiusY)/(2*(config.fullPressureRadius||20))));return Math.max(1,Math.round(255*t))},PROTOCOL_V1=config.protocol||"brokenithm.v1",seq=0,encodeKeys=function(e){var t=new ArrayBuffer(39),a=new DataView(t);a.setUint8(0,1),a.setUint32(1,seq,!0),seq=seq+1>>>0;for(var n=0;n<32;n++)a.setUint8(5+n,e[n]);for(var o=0,n=0;n<6;n++)e[32+n]&&(o|=1<<n);return a.setUint8(37,o),a.setUint8(38,0),t},sendKeys=function(e){wsConnected&&(ws.protocol===PROTOCOL_V1?ws.send(encodeKeys(e)):ws.send("b"+e.map(function(e){return e?1:0}).join("")))},throttledSendKeys=throttle(sendKeys,10),ws=null,wsTimeout=0,wsConnected=!1,wsRtt=null,wsHeartbeat=function(){ws.send("alive?"+performance.now().toFixed(1)+(null===wsRtt?"":","+wsRtt.toFixed(1)))},wsConnect=function(){(ws=new WebSocket((location.protocol=="https:"?"wss://":"ws://")+location.host+"/ws",[PROTOCOL_V1])).binaryType="arraybuffer",ws.onopen=function(){wsHeartbeat()},ws.onmessage=function(e){if(e.data.byteLength)updateLed(e.data);else if(0===e.data.indexOf("alive")){wsTimeout=0,wsConnected=!0;var t=parseFloat(e.data.slice(5));isNaN(t)||(wsRtt=performance.now()-t)}}},wsWatch=function(){if(wsTimeout++>2)return wsTimeout=0,ws.close(),wsConnected=!1,void wsConnect();wsConnected&&wsHeartbeat()},canvas=document.getElementById("canvas"),canvasCtx=canvas.getContext("2d"),canvasData=canvasCtx.getImageData(0,0,33,1),setupLed=function(){for(var e=0;e<33;e++)canvasData.data[4*e+3]=255};setupLed();var updateLed=function(e){for(var t=new Uint8Array(e),a=0;a<31;a++)canvasData.data[4*a+4]=t[3*a],canvasData.data[4*a+5]=t[3*a+1],canvasData.data[4*a+6]=t[3*a+2];canvasData.data[0]=t[0],canvasData.data[1]=t[1],canvasData.data[2]=t[2],canvasData.data[128]=t[90],canvasData.data[129]=t[91],canvasData.data[130]=t[92],canvasCtx.putImageData(canvasData,0,0)},fs=document.getElementById("fullscreen"),requestFullscreen=function(){!document.fullscreenElement&&screen.height<=1024&&(fs.requestFullscreen?fs.requestFullscreen():fs.mozRequestFullScreen?fs.mozRequestFullScreen():fs.webkitRequestFullScreen&&fs.webkitRequestFullScreen())},throttledRequestFullscreen=throttle(requestFullscreen,3e3),cnt=document.getElementById("main");cnt.addEventListener("touchstart",updateTouches),cnt.addEventListener("touchmove",updateTouches),cnt.addEventListener("touchend",updateTouches);var readConfig=function(e){var t="";e.invert&&(t+=".container, .air-container {flex-flow: column-reverse nowrap;} ");var a=e.bgColor||"rbga(0, 0, 0, 0.9)";e.bgImage?t+="#fullscreen {background: ".concat(a,' url("').concat(e.bgImage,'") fixed center / cover!important; background-repeat: no-repeat;} '):t+="#fullscreen {background: ".concat(a,";} "),"number"==typeof e.ledOpacity&&(0===e.ledOpacity?t+="#canvas {display: none} ":t+="#canvas {opacity: ".concat(e.ledOpacity,"} ")),"string"==typeof e.keyColor&&(t+=".key[data-active] {background-color: ".concat(e.keyColor,";} ")),"string"==typeof e.keyColor&&(t+=".key.air[data-active] {background-color: ".concat(e.lkeyColor,";} ")),"string"==typeof e.keyBorderColor&&(t+=".key {border: 1px solid ".concat(e.keyBorderColor,";} ")),e.keyColorFade&&"number"==typeof e.keyColorFade&&(t+=".key:not([data-active]) {transition: background ".concat(e.keyColorFade,"ms ease-out;} ")),"number"==typeof e.keyHeight&&(0===e.keyHeight?t+=".touch-container {display: none;} ":t+=".touch-container {flex: ".concat(e.keyHeight,";} ")),"number"==typeof e.lkeyHeight&&(0===e.lkeyHeight?t+=".air-container {display: none;} ":t+=".air-container {flex: ".concat(e.lkeyHeight,";} "));var n=document.createElement("style");n.innerHTML=t,document.head.appendChild(n)},initialize=function(){readConfig(config),compileKeys(),(window.ensurePaired?ensurePaired():Promise.resolve()).then(function(){wsConnect(),setInterval(wsWatch,1e3)})};initialize(),window.onresize=compileKeys;
@@ -1,21 +0,0 @@
-var config = {
-  // Inverted layout mode.
-  // Set to "true" to have the "lift" key at the bottom of the screen rather than the top.
-  invert: false,
-
-  // Use a solid background color
-  bgImage: false,
-  bgColor: "#000000",
-
-  // Use a custom background image
-  // bgColor is overlayed on the image, so make it semi-transparent
-  // bgImage:
-  //   "https://raw.githubusercontent.com/gist/4yn/df052666266ce25554110ca1b4f33ce3/raw/1e5e6ab966639bb6786a4690dc49097763b16ba0/subtle-prism.svg",
-  // bgColor: "rgba(0, 0, 0, 0.5)",
-
-  // Key Press Color
-  keyColor: "#FF00FF",
-
-  // Lift key color
-  lkeyColor: "#00FFFF",
-};
@@ -2,7 +2,7 @@ import {
   HandLandmarker,
   FilesetResolver,
   DrawingUtils,
-} from "./lib/tasks-vision/vision_bundle.mjs";
+} from "./lib/tasks-vision/vision_bundle.mjs?v=@ASSET_VERSION@";
 
 // Example reference used found in https://developers.google.com/mediapipe/solutions/vision/hand_landmarker/web_js#video
 // official code example: https://codepen.io/mediapipe-preview/pen/gOKBGPN
@@ -12,9 +12,7 @@ const canvasCtx = canvasElement.getContext("2d");
 
 const circle = document.getElementById("circle");
 const circle2 = document.getElementById("circle2");
-const zone = document.getElementById("zone");
 const height = document.getElementById("zoneValue");
-const customHeight = document.getElementById("customHeight");
 let canvasOffset = null;
 
 const pauseButton = document.getElementById("pauseButton");
@@ -23,6 +21,10 @@ const detectionConfidence = document.getElementById("detectionConfidence");
 const handPresenceConfidence = document.getElementById(
   "handPresenceConfidence"
 );
+const zoneTop = document.getElementById("zoneTop");
+const zoneBottom = document.getElementById("zoneBottom");
+const cameraRotation = document.getElementById("cameraRotation");
+const cameraMirror = document.getElementById("cameraMirror");
 
 let handLandmarker = undefined;
 let drawingUtils = undefined;
@@ -32,10 +34,23 @@ let paused = false;
 let handDetectionRender = true;
 let rightHand = {};
 let leftHand = {};
-let lastRightHandAirValue = -1;
-let lastLeftHandAirValue = -1;
-let zoneHeight = 200;
-let zoneLevels = zoneHeight / 6;
+// Calibration is stored by slidershim, so it is shared by every device running
+// the hand tracker. Keys are the same as in the slidershim config.
+// Open the page with ?source=<name> to give this device its own air zone and
+// camera orientation, kept in handSources, for example a phone at the side.
+const source = new URLSearchParams(location.search).get("source");
+let calibration = {
+  handZoneTop: 0.0,
+  handZoneBottom: 0.4,
+  handDetectionConfidence: 0.25,
+  handPresenceConfidence: 0.5,
+  handTrackingConfidence: 0.1,
+  handCameraRotation: 0,
+  handCameraMirror: false,
+  handSources: {},
+  handGestures: [],
+  handModel: "full-float16",
+};
 
 function clamp(input, min, max) {
   if (input < min) {
@@ -47,38 +62,80 @@ function clamp(input, min, max) {
   return input;
 }
 
-function getSavedOrDefault(key, defaultValue) {
-  const saved = window.localStorage.getItem(key);
-  if (saved === null) {
-    return defaultValue;
+function roundLandmark(value) {
+  return Math.round(value * 1000) / 1000;
+}
+
+async function loadCalibration() {
+  try {
+    const res = await fetch("/api/calibration");
+    if (res.ok) {
+      calibration = await res.json();
+    }
+  } catch (err) {
+    console.warn("Could not load calibration from slidershim", err);
   }
-  return saved;
 }
 
-function updateInput(params) {
-  // Update height zone.
-  zoneHeight = Number(getSavedOrDefault("customHeight", zoneHeight));
-  zoneLevels = zoneHeight / 6;
-  customHeight.value = zoneHeight;
-  zone.style.height = zoneHeight + "px";
+async function saveCalibration(changes) {
+  try {
+    const res = await fetch("/api/calibration", {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(changes),
+    });
+    if (res.ok) {
+      calibration = await res.json();
+    } else {
+      console.error("Could not save calibration", await res.text());
+    }
+  } catch (err) {
+    console.error("Could not save calibration", err);
+  }
+  updateInput();
+}
+
+// Air zone and camera orientation of this device.
+function sourceCalibration() {
+  return (source && calibration.handSources?.[source]) || calibration;
+}
+
+function saveSourceCalibration(changes) {
+  if (!source) {
+    saveCalibration(changes);
+    return;
+  }
+  const current = sourceCalibration();
+  saveCalibration({
+    handSources: {
+      ...calibration.handSources,
+      [source]: {
+        handZoneTop: current.handZoneTop,
+        handZoneBottom: current.handZoneBottom,
+        handCameraRotation: current.handCameraRotation,
+        handCameraMirror: current.handCameraMirror,
+        ...changes,
+      },
+    },
+  });
+}
+
+function updateInput() {
   // Update min tracking confidence.
-  trackingConfidence.value = params.minTrackingConfidence;
+  trackingConfidence.value = calibration.handTrackingConfidence;
   // Update min detection confidence.
-  detectionConfidence.value = params.minHandDetectionConfidence;
+  detectionConfidence.value = calibration.handDetectionConfidence;
   // Update min hand presence confidence.
-  handPresenceConfidence.value = params.minHandPresenceConfidence;
+  handPresenceConfidence.value = calibration.handPresenceConfidence;
+  // Update air zone and camera orientation.
+  const own = sourceCalibration();
+  zoneTop.value = own.handZoneTop;
+  zoneBottom.value = own.handZoneBottom;
+  cameraRotation.value = own.handCameraRotation;
+  cameraMirror.checked = own.handCameraMirror;
 }
 
 function initializeListeners() {
-  // Height zone input listener
-  customHeight.addEventListener("change", () => {
-    const newValue = Number(clamp(customHeight.value, 1, canvasElement.height));
-    zoneHeight = newValue;
-    zoneLevels = zoneHeight / 6;
-    zone.style.height = zoneHeight + "px";
-    customHeight.value = newValue;
-    window.localStorage.setItem("customHeight", newValue);
-  });
   // Pause sending input to slidershim
   pauseButton.addEventListener("click", () => {
     paused = !paused;
@@ -95,54 +152,44 @@ function initializeListeners() {
   trackingConfidence.addEventListener("change", async () => {
     const newValue = Number(clamp(trackingConfidence.value, 0.05, 1));
     trackingConfidence.value = newValue;
-    window.localStorage.setItem("minTrackingConfidence", newValue);
+    saveCalibration({ handTrackingConfidence: newValue });
     handLandmarker.setOptions({ minTrackingConfidence: newValue });
   });
   // Update min detection confidence.
   detectionConfidence.addEventListener("change", async () => {
     const newValue = Number(clamp(detectionConfidence.value, 0.05, 1));
     detectionConfidence.value = newValue;
-    window.localStorage.setItem("minHandDetectionConfidence", newValue);
+    saveCalibration({ handDetectionConfidence: newValue });
     handLandmarker.setOptions({ minHandDetectionConfidence: newValue });
   });
   // Update handPresenceConfidence.
   handPresenceConfidence.addEventListener("change", async () => {
     const newValue = Number(clamp(handPresenceConfidence.value, 0.05, 1));
     handPresenceConfidence.value = newValue;
-    window.localStorage.setItem("minHandPresenceConfidence", newValue);
+    saveCalibration({ handPresenceConfidence: newValue });
     handLandmarker.setOptions({ minHandPresenceConfidence: newValue });
   });
+  // Update air zone bounds, as a fraction of the camera image height.
+  zoneTop.addEventListener("change", () => {
+    saveSourceCalibration({ handZoneTop: Number(clamp(zoneTop.value, 0, 1)) });
+  });
+  zoneBottom.addEventListener("change", () => {
+    saveSourceCalibration({
+      handZoneBottom: Number(clamp(zoneBottom.value, 0, 1)),
+    });
+  });
+  // Update camera orientation.
+  cameraRotation.addEventListener("change", () => {
+    saveSourceCalibration({ handCameraRotation: Number(cameraRotation.value) });
+  });
+  cameraMirror.addEventListener("change", () => {
+    saveSourceCalibration({ handCameraMirror: cameraMirror.checked });
+  });
 }
 
-function getAirZoneValue(hand) {
-  // x and y are normalized to [0.0, 1.0] by the image width and height respectively.
-  const handHeight = hand.y * canvasElement.height;
-  if (handHeight > zoneHeight) {
-    return -1;
-  }
-  // kflag is 0-5
-  return 5 - Math.floor(handHeight / zoneLevels);
-}
-
-function updateHands() {
-  const rightHandAirValue = getAirZoneValue(rightHand);
-  const leftHandAirValue = getAirZoneValue(leftHand);
-  if (
-    rightHandAirValue != lastRightHandAirValue ||
-    leftHandAirValue != lastLeftHandAirValue
-  ) {
-    updateTouches(rightHandAirValue, leftHandAirValue);
-  }
-  showResults(rightHandAirValue, leftHandAirValue);
-  lastRightHandAirValue = rightHandAirValue;
-  lastLeftHandAirValue = leftHandAirValue;
-}
-
-function showResults(rightHandAirValue, leftHandAirValue) {
+function showResults() {
   if (handDetectionRender) {
     canvasOffset = canvas.getBoundingClientRect();
-    zone.style.left = canvasOffset.left + "px";
-    zone.style.width = canvasOffset.width + "px";
     // instead of actual side check, just see if it exists. Occasionally left/right swaps when confidence is lower.
     if (rightHand?.sideIndex != null) {
       circle.style.transform = setStyleTransform(rightHand);
@@ -151,7 +198,18 @@ function showResults(rightHandAirValue, leftHandAirValue) {
       circle2.style.transform = setStyleTransform(leftHand);
     }
   }
-  height.textContent = rightHandAirValue + ", " + leftHandAirValue;
+}
+
+// The air zone is computed by slidershim, which replies with "z" and the 6 air
+// flags. Show the active levels, -1 if none.
+function showAirLevels(flags) {
+  const levels = [];
+  for (let i = 0; i < flags.length; i++) {
+    if (flags[i] === "1") {
+      levels.push(i);
+    }
+  }
+  height.textContent = levels.length > 0 ? levels.join(", ") : "-1";
 }
 
 function setStyleTransform(hand) {
@@ -164,29 +222,23 @@ function setStyleTransform(hand) {
   );
 }
 
-function onResults(results) {
+function onResults(results, timeMs) {
+  // Every frame is sent, also without hands, so that slidershim can release
+  // the air strings.
+  const hands = [];
   if (!!results.landmarks && results.landmarks.length > 0) {
     for (let i = 0; i < results.landmarks.length; i++) {
       // An array len 2 (if 2 hands). Consisting of an array of 21 landmarks object: x,y,z.
       let hand = {};
       const currentHand = results.landmarks[i];
-      if (!!results.handedness && results.handedness.length > 0) {
-        // results.handedness is Category[][]
-        for (const handEntry of results.handedness) {
-          // handEntry is an array of objects, so lets look inside that object. Unsure if this is ever more than 1 entry.
-          // [{score: 0.90789794921875, index: 1, categoryName: 'Left', displayName: 'Left'}]
-          let entryMatched = false;
-          for (const entry of handEntry) {
-            if (entry.index == i) {
-              hand.sideIndex = entry.index;
-              hand.name = entry.categoryName;
-              entryMatched = true;
-              break;
-            }
-          }
-          if (entryMatched) {
-            break;
-          }
+      if (!!results.handedness && results.handedness.length > i) {
+        // results.handedness is Category[][], one array per hand in the same order as the landmarks.
+        // [{score: 0.90789794921875, index: 1, categoryName: 'Left', displayName: 'Left'}]
+        const entry = results.handedness[i][0];
+        if (!!entry) {
+          hand.sideIndex = entry.index;
+          hand.name = entry.categoryName;
+          hand.score = entry.score;
         }
       } else {
         console.warn(
@@ -204,10 +256,27 @@ function onResults(results) {
           // default to right hand.
           rightHand = hand;
         }
+        const observation = {
+          x: hand.x,
+          y: hand.y,
+          hand: hand.name === "Left" || hand.name === "Right" ? hand.name : null,
+          score: hand.score ?? 1,
+        };
+        // Landmarks are only needed to recognise gestures, so they are left out
+        // unless some are bound.
+        if (calibration.handGestures && calibration.handGestures.length > 0) {
+          observation.landmarks = currentHand.map((p) => [
+            roundLandmark(p.x),
+            roundLandmark(p.y),
+            roundLandmark(p.z),
+          ]);
+        }
+        hands.push(observation);
       }
     }
-    updateHands();
+    showResults();
   }
+  sendHands(timeMs, hands);
   if (handDetectionRender && !!results.landmarks) {
     // Drawing camera view + hand tracking
     canvasCtx.save();
@@ -228,84 +297,52 @@ function onResults(results) {
 
 // Initially taken from brokenithm src.js
 
-// Button State
-// prettier-ignore
-var lastState = [0, 0, 0, 0, 0, 0];
-
-function updateTouches(hand0, hand1) {
+const sendHands = (timeMs, hands) => {
   if (paused) {
-    return;
-  }
-  try {
-    // prettier-ignore
-    let keyFlags = [0, 0, 0, 0, 0, 0];
-
-    if (hand0 > -1) {
-      keyFlags[hand0] = 1;
-    }
-    if (hand1 > -1) {
-      keyFlags[hand1] = 1;
-    }
-
-    if (keyFlags !== lastState) {
-      throttledSendKeys(keyFlags);
-    }
-    lastState = keyFlags;
-  } catch (err) {
-    alert(err);
+    hands = [];
   }
-}
-
-const throttle = (func, wait) => {
-  var ready = true;
-  var args = null;
-  return function throttled() {
-    var context = this;
-    if (ready) {
-      ready = false;
-      setTimeout(function () {
-        ready = true;
-        if (args) {
-          throttled.apply(context);
-        }
-      }, wait);
-      if (args) {
-        func.apply(this, args);
-        args = null;
-      } else {
-        func.apply(this, arguments);
-      }
-    } else {
-      args = arguments;
-    }
-  };
-};
-
-const sendKeys = (keyFlags) => {
   if (wsConnected) {
-    ws.send("d" + keyFlags.join(""));
+    const frame = { t: timeMs, hands: hands };
+    if (source) {
+      frame.source = source;
+    }
+    ws.send("h" + JSON.stringify(frame));
   }
 };
 
-const throttledSendKeys = throttle(sendKeys, 10);
-
 // Websockets
 var ws = null;
 var wsTimeout = 0;
 var wsConnected = false;
+// Round trip of the last heartbeat in ms, reported with the next one
+var wsRtt = null;
+
+const wsHeartbeat = () => {
+  ws.send(
+    "alive?" +
+      performance.now().toFixed(1) +
+      (wsRtt === null ? "" : "," + wsRtt.toFixed(1))
+  );
+};
 
 const wsConnect = () => {
-  ws = new WebSocket("ws://" + location.host + "/ws");
+  ws = new WebSocket((location.protocol == "https:" ? "wss://" : "ws://") + location.host + "/ws");
   ws.binaryType = "arraybuffer";
   ws.onopen = () => {
-    ws.send("alive?");
+    wsHeartbeat();
   };
   ws.onmessage = (e) => {
     if (e.data.byteLength) {
       updateLed(e.data);
-    } else if (e.data == "alive") {
+    } else if (e.data[0] == "z") {
+      showAirLevels(e.data.slice(1));
+    } else if (e.data.startsWith("alive")) {
       wsTimeout = 0;
       wsConnected = true;
+      const sent = parseFloat(e.data.slice(5));
+      if (!isNaN(sent)) {
+        wsRtt = performance.now() - sent;
+      }
     }
   };
 };
@@ -319,23 +356,29 @@ const wsWatch = () => {
     return;
   }
   if (wsConnected) {
-    ws.send("alive?");
+    wsHeartbeat();
   }
 };
 
+// Hash of the built-in library and model files, filled in when slidershim is
+// built. Their URLs carry it so that browsers can cache them until it changes.
+const ASSET_VERSION = "@ASSET_VERSION@";
+
+// Models are served from the asset folder set in slidershim, only the full
+// float16 model is built in.
+function modelPath(model) {
+  return (
+    "lib/models/hand_landmarker_" +
+    model.replace("-", "_") +
+    ".task?v=" +
+    ASSET_VERSION
+  );
+}
+
 const params = {
   numHands: 2,
-  minTrackingConfidence: Number(
-    getSavedOrDefault("minTrackingConfidence", 0.1)
-  ),
-  minHandPresenceConfidence: Number(
-    getSavedOrDefault("minHandPresenceConfidence", 0.5)
-  ),
-  minHandDetectionConfidence: Number(
-    getSavedOrDefault("minHandDetectionConfidence", 0.25)
-  ),
   baseOptions: {
-    modelAssetPath: "lib/hand-landmarker_float_16/hand_landmarker.task",
+    modelAssetPath: modelPath("full-float16"),
     delegate: "GPU",
   },
   runningMode: "VIDEO",
@@ -345,12 +388,25 @@ const params = {
 // loading. Machine Learning models can be large and take a moment to
 // get everything needed to run.
 async function initializeHandTracking() {
-  updateInput(params);
+  await loadCalibration();
+  params.minTrackingConfidence = calibration.handTrackingConfidence;
+  params.minHandPresenceConfidence = calibration.handPresenceConfidence;
+  params.minHandDetectionConfidence = calibration.handDetectionConfidence;
+  updateInput();
   // Set up listeners after updating the input.
   initializeListeners();
   drawingUtils = new DrawingUtils(canvasCtx);
   const vision = await FilesetResolver.forVisionTasks("lib/tasks-vision/wasm");
-  handLandmarker = await HandLandmarker.createFromOptions(vision, params);
+  vision.wasmLoaderPath += "?v=" + ASSET_VERSION;
+  vision.wasmBinaryPath += "?v=" + ASSET_VERSION;
+  params.baseOptions.modelAssetPath = modelPath(calibration.handModel);
+  try {
+    handLandmarker = await HandLandmarker.createFromOptions(vision, params);
+  } catch (err) {
+    console.error("Could not load model " + calibration.handModel, err);
+    params.baseOptions.modelAssetPath = modelPath("full-float16");
+    handLandmarker = await HandLandmarker.createFromOptions(vision, params);
+  }
 }
 
 function startCamera() {
@@ -381,14 +437,17 @@ function mainLoop() {
   if (lastVideoTime !== videoElement.currentTime) {
     lastVideoTime = videoElement.currentTime;
     let results = handLandmarker.detectForVideo(videoElement, startTimeMs);
-    onResults(results);
+    onResults(results, startTimeMs);
   }
   // its a loop, call again.
   window.requestAnimationFrame(mainLoop);
 }
 
-wsConnect();
-setInterval(wsWatch, 1000);
+const paired = window.ensurePaired ? ensurePaired() : Promise.resolve();
+paired.then(() => {
+  wsConnect();
+  setInterval(wsWatch, 1000);
+});
 
 initializeHandTracking().then(() => {
   startCamera();
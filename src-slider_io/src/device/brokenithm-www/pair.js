@@ -0,0 +1,39 @@
+// Pairs this device with slidershim when pairing is required. The server
+// keeps the device token in a cookie, so the PIN is only asked for once.
+var ensurePaired = function () {
+  var askPin = function (message) {
+    var pin = window.prompt(message);
+    if (pin === null) {
+      return Promise.resolve();
+    }
+    return fetch("/api/pair", {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ pin: pin.trim() }),
+    }).then(function (res) {
+      if (res.status === 429) {
+        return res.json().then(function (body) {
+          return askPin(
+            body.error + ". Enter the pairing PIN shown in slidershim:"
+          );
+        });
+      }
+      if (!res.ok) {
+        return askPin("Wrong PIN. Enter the pairing PIN shown in slidershim:");
+      }
+    });
+  };
+
+  return fetch("/api/pair")
+    .then(function (res) {
+      return res.json();
+    })
+    .then(function (status) {
+      if (status.required && !status.paired) {
+        return askPin("Enter the pairing PIN shown in slidershim:");
+      }
+    })
+    .catch(function (err) {
+      console.warn("Could not pair with slidershim", err);
+    });
+};
@@ -46,7 +46,9 @@ const compileKey = (key) => {
     almostLeft: !!prev ? key.offsetLeft + key.offsetWidth / 4 : -99999,
     almostRight: !!next ? key.offsetLeft + (key.offsetWidth * 3) / 4 : 99999,
     kflag: parseInt(key.dataset.kflag) + (parseInt(key.dataset.air) ? 32 : 0),
-    isAir: parseInt(key.dataset.air) ? true : window.allAir || false,
+    isAir: parseInt(key.dataset.air)
+      ? true
+      : window.allAir || config.allAir || false,
     prevKeyRef: prev,
     prevKeyKflag: prev
       ? parseInt(prev.dataset.kflag) + (parseInt(prev.dataset.air) ? 32 : 0)
@@ -167,16 +169,18 @@ function updateTouches(e) {
 
       if (!key) continue;
 
-      setKey(keyFlags, key.kflag, key.isAir);
+      const pressure = getPressure(touch);
+
+      setKey(keyFlags, key.kflag, key.isAir, pressure);
 
       if (key.isAir) continue;
 
       if (x < key.almostLeft) {
-        setKey(keyFlags, key.prevKeyKflag, false);
+        setKey(keyFlags, key.prevKeyKflag, false, pressure);
       }
 
       if (key.almostRight < x) {
-        setKey(keyFlags, key.nextKeyKflag, false);
+        setKey(keyFlags, key.nextKeyKflag, false, pressure);
       }
     }
 
@@ -203,17 +207,59 @@ function updateTouches(e) {
 }
 const throttledUpdateTouches = throttle(updateTouches, 10);
 
-const setKey = (keyFlags, kflag, isAir) => {
+const setKey = (keyFlags, kflag, isAir, pressure) => {
   var idx = kflag;
   if (keyFlags[idx] && !isAir) {
     idx++;
   }
-  keyFlags[idx] = 1;
+  keyFlags[idx] = Math.max(keyFlags[idx], pressure);
+};
+
+// Touch pressure from 1 to 255, using force when the device reports it and
+// contact radius otherwise.
+const getPressure = (touch) => {
+  var pressure = 1;
+  if (touch.force > 0) {
+    pressure = touch.force;
+  } else if (touch.radiusX > 0) {
+    const fullRadius = config.fullPressureRadius || 20;
+    pressure = Math.min(1, (touch.radiusX + touch.radiusY) / (2 * fullRadius));
+  }
+  return Math.max(1, Math.round(pressure * 255));
+};
+
+// Binary protocol v1
+// [0] version, [1..5] sequence (u32 le), [5..37] ground pressure,
+// [37] air bitmask, [38] extra bitmask
+const PROTOCOL_V1 = config.protocol || "brokenithm.v1";
+var seq = 0;
+const encodeKeys = (keyFlags) => {
+  const buf = new ArrayBuffer(39);
+  const view = new DataView(buf);
+  view.setUint8(0, 1);
+  view.setUint32(1, seq, true);
+  seq = (seq + 1) >>> 0;
+  for (var i = 0; i < 32; i++) {
+    view.setUint8(5 + i, keyFlags[i]);
+  }
+  var air = 0;
+  for (var i = 0; i < 6; i++) {
+    if (keyFlags[32 + i]) {
+      air |= 1 << i;
+    }
+  }
+  view.setUint8(37, air);
+  view.setUint8(38, 0);
+  return buf;
 };
 
 const sendKeys = (keyFlags) => {
   if (wsConnected) {
-    ws.send("b" + keyFlags.join(""));
+    if (ws.protocol === PROTOCOL_V1) {
+      ws.send(encodeKeys(keyFlags));
+    } else {
+      ws.send("b" + keyFlags.map((x) => (x ? 1 : 0)).join(""));
+    }
   }
 };
 const throttledSendKeys = throttle(sendKeys, 10);
@@ -222,18 +268,34 @@ const throttledSendKeys = throttle(sendKeys, 10);
 var ws = null;
 var wsTimeout = 0;
 var wsConnected = false;
+// Round trip of the last heartbeat in ms, reported with the next one
+var wsRtt = null;
+const wsHeartbeat = () => {
+  ws.send(
+    "alive?" +
+      performance.now().toFixed(1) +
+      (wsRtt === null ? "" : "," + wsRtt.toFixed(1))
+  );
+};
 const wsConnect = () => {
-  ws = new WebSocket((location.protocol == "https:" ? "wss://" : "ws://") + location.host + "/ws");
+  ws = new WebSocket(
+    (location.protocol == "https:" ? "wss://" : "ws://") + location.host + "/ws",
+    [PROTOCOL_V1]
+  );
   ws.binaryType = "arraybuffer";
   ws.onopen = () => {
-    ws.send("alive?");
+    wsHeartbeat();
   };
   ws.onmessage = (e) => {
     if (e.data.byteLength) {
       updateLed(e.data);
-    } else if (e.data == "alive") {
+    } else if (e.data.indexOf("alive") === 0) {
       wsTimeout = 0;
       wsConnected = true;
+      const sent = parseFloat(e.data.slice(5));
+      if (!isNaN(sent)) {
+        wsRtt = performance.now() - sent;
+      }
     }
   };
 };
@@ -246,7 +308,7 @@ const wsWatch = () => {
     return;
   }
   if (wsConnected) {
-    ws.send("alive?");
+    wsHeartbeat();
   }
 };
 
@@ -351,7 +413,7 @@ const readConfig = (config) => {
     if (config.lkeyHeight === 0) {
       style += `.air-container {display: none;} `;
     } else {
-      style += `.air-container {flex: ${config.keyHeight};} `;
+      style += `.air-container {flex: ${config.lkeyHeight};} `;
     }
   }
 
@@ -364,8 +426,12 @@ const readConfig = (config) => {
 const initialize = () => {
   readConfig(config);
   compileKeys();
-  wsConnect();
-  setInterval(wsWatch, 1000);
+  // Skins from before pairing do not load pair.js
+  const paired = window.ensurePaired ? ensurePaired() : Promise.resolve();
+  paired.then(() => {
+    wsConnect();
+    setInterval(wsWatch, 1000);
+  });
 };
 initialize();
 
@@ -1,24 +1,63 @@
 use async_trait::async_trait;
-use futures::{SinkExt, StreamExt};
+use futures::{future, FutureExt, SinkExt, StreamExt};
 use hyper::{
-  header,
-  server::conn::AddrStream,
+  header::{self, HeaderValue},
+  server::conn::{AddrStream, Http},
   service::{make_service_fn, service_fn},
   upgrade::{self, Upgraded},
-  Body, Method, Request, Response, Server, StatusCode,
+  Body, HeaderMap, Method, Request, Response, Server, StatusCode,
 };
 use log::{error, info};
-use phf::phf_map;
-use std::{convert::Infallible, future::Future, net::SocketAddr};
+use parking_lot::Mutex;
+use std::{
+  convert::Infallible,
+  future::Future,
+  net::{self, SocketAddr},
+  panic::AssertUnwindSafe,
+  path::{Path, PathBuf},
+  sync::{atomic::Ordering, Arc},
+  time::Instant,
+};
 use tokio::{
+  join,
+  net::TcpListener,
   select,
-  sync::mpsc,
+  sync::{mpsc, watch},
   time::{sleep, Duration},
 };
+use tokio_rustls::TlsAcceptor;
 use tokio_tungstenite::WebSocketStream;
 use tungstenite::{handshake, Message};
 
-use crate::{device::config::BrokenithmSpec, shared::worker::AsyncHaltableJob, state::SliderState};
+use crate::{
+  device::{
+    brokenithm_api::{
+      get_calibration, get_clients, get_pairing, get_status, get_web_config, save_calibration,
+      save_pairing,
+    },
+    brokenithm_assets::{
+      asset_path, embedded_response, file_response, find_asset, serve_hand_asset, web_file,
+      AssetRequest, AssetVersion,
+    },
+    brokenithm_clients::ClientInputs,
+    brokenithm_hands::HandSources,
+    brokenithm_pairing::{device_token, is_paired, new_pin, same_origin, ServerOrigins},
+    brokenithm_protocol::{
+      parse_binary, parse_text, ClientMessage, ClientProtocol, PingTimer, SequenceFilter,
+    },
+    brokenithm_status::ClientKind,
+    brokenithm_tls::tls_acceptor,
+    config::{BrokenithmOptions, BrokenithmSpec, BrokenithmTls, BrokenithmWebConfig},
+  },
+  handtracking::config::HandTrackingConfig,
+  shared::{
+    access::{refused, ServerAccess},
+    mdns::Advertisement,
+    worker::{panic_message, AsyncHaltableJob, WorkerStatus},
+  },
+  state::SliderState,
+  system::get_skins_dir,
+};
 
 // https://levelup.gitconnected.com/handling-websocket-and-http-on-the-same-port-with-rust-f65b770722c9
 
@@ -31,57 +70,120 @@ async fn error_response() -> Result<Response<Body>, Infallible> {
   )
 }
 
-// static x: &'static [u8] = include_bytes!("./brokenithm-www/favicon.ico");
-
-static BROKENITHM_STR_FILES: phf::Map<&'static str, (&'static str, &'static str)> = phf_map! {
-  "app.js" => (include_str!("./brokenithm-www/app.js"), "text/javascript"),
-  "config.js" => (include_str!("./brokenithm-www/config.js"), "text/javascript"),
-  "index-ns.html" => (include_str!("./brokenithm-www/index-ns.html"), "text/html"),
-  "index-go.html" => (include_str!("./brokenithm-www/index-go.html"), "text/html"),
-  "index.html" => (include_str!("./brokenithm-www/index.html"), "text/html"),
-  "handtracker.html" => (include_str!("./brokenithm-www/handtracker/index.html"), "text/html"),
-  "styles.css" => (include_str!("./brokenithm-www/handtracker/styles.css"), "text/css"),
-  "mediapipe-hands-min.js" => (include_str!("./brokenithm-www/handtracker/mediapipe-hands-min.js"), "text/javascript"),
-  "lib/tasks-vision/vision_bundle.mjs" => (include_str!("./brokenithm-www/handtracker/lib/tasks-vision/vision_bundle.mjs"), "text/javascript"),
-  "lib/tasks-vision/wasm/vision_wasm_internal.js" => (include_str!("./brokenithm-www/handtracker/lib/tasks-vision/wasm/vision_wasm_internal.js"), "text/javascript"),
-  "lib/tasks-vision/wasm/vision_wasm_nosimd_internal.js" => (include_str!("./brokenithm-www/handtracker/lib/tasks-vision/wasm/vision_wasm_nosimd_internal.js"), "text/javascript"),
-};
+fn refused_response(status: StatusCode) -> Result<Response<Body>, Infallible> {
+  Ok(refused(status))
+}
 
-static BROKENITHM_BIN_FILES: phf::Map<&'static str, (&'static [u8], &'static str)> = phf_map! {
-  "favicon.ico" => (include_bytes!("./brokenithm-www/favicon.ico"), "image/x-icon"),
-  "icon.png" => (include_bytes!("./brokenithm-www/icon.png"), "image/png"),
-  "lib/tasks-vision/wasm/vision_wasm_internal.wasm" => (include_bytes!("./brokenithm-www/handtracker/lib/tasks-vision/wasm/vision_wasm_internal.wasm"), "application/wasm"),
-  "lib/tasks-vision/wasm/vision_wasm_nosimd_internal.wasm" => (include_bytes!("./brokenithm-www/handtracker/lib/tasks-vision/wasm/vision_wasm_nosimd_internal.wasm"), "application/wasm"),
-  "lib/hand-landmarker_float_16/hand_landmarker.task" => (include_bytes!("./brokenithm-www/handtracker/lib/hand-landmarker_float_16/hand_landmarker.task"), "application/octet-stream"),
-};
+/// Serves a file from the active skin, falling back to the embedded files.
+async fn serve_file(
+  path: &str,
+  skin_dir: Option<&Path>,
+  request: &AssetRequest,
+) -> Result<Response<Body>, Infallible> {
+  if let Some(dir) = skin_dir {
+    if let Some(file) = find_asset(dir, path).await {
+      if let Some(response) = file_response(path, file, request).await {
+        return Ok(response);
+      }
+    }
+  }
 
-async fn serve_file(path: &str) -> Result<Response<Body>, Infallible> {
-  match (
-    BROKENITHM_STR_FILES.get(path),
-    BROKENITHM_BIN_FILES.get(path),
-  ) {
-    (Some((data, mime)), _) => Ok(
-      Response::builder()
-        .header(header::CONTENT_TYPE, *mime)
-        .body(Body::from(*data))
-        .unwrap(),
-    ),
-    (_, Some((data, mime))) => Ok(
-      Response::builder()
-        .header(header::CONTENT_TYPE, *mime)
-        .body(Body::from(*data))
-        .unwrap(),
-    ),
-    (None, None) => error_response().await,
+  match web_file(path) {
+    Some(file) => Ok(embedded_response(path, file, request).await),
+    None => error_response().await,
   }
 }
 
-async fn handle_brokenithm(
-  ws_stream: WebSocketStream<Upgraded>,
+/// Shared by every connection to the server.
+#[derive(Clone)]
+struct ServerContext {
   state: SliderState,
-  lights_enabled: bool,
   spec: BrokenithmSpec,
+  lights_enabled: bool,
+  web: Arc<BrokenithmWebConfig>,
+  /// Only paired devices can send input.
+  pairing: bool,
+  /// Names and ports pages allowed to post or open websockets come from.
+  origins: Arc<ServerOrigins>,
+  /// Address lists, per address limits and PIN lockouts.
+  access: Arc<ServerAccess>,
+  clients: Arc<Mutex<ClientInputs>>,
+  /// Current hand tracking calibration, replaced when a new one is saved.
+  calibration: Arc<Mutex<Arc<HandTrackingConfig>>>,
+  hands: Arc<Mutex<HandSources>>,
+  hand_asset_dir: Option<PathBuf>,
+  /// Version the pages request the hand tracker files with.
+  asset_version: Arc<AssetVersion>,
+  /// Directory of the active skin, whose files replace the embedded ones.
+  skin_dir: Option<PathBuf>,
+  started: Instant,
+  /// Round trip above which a client is reported as slow, in ms.
+  latency_warn_ms: f64,
+}
+
+impl ServerContext {
+  /// Whether a request may send input or change settings, which with pairing
+  /// on takes the token of a paired device.
+  fn authorized(&self, headers: &HeaderMap) -> bool {
+    !self.pairing || device_token(headers).is_some_and(|token| is_paired(&token))
+  }
+}
+
+/// Resolves once the device with this token is no longer paired.
+async fn wait_unpaired(state: &SliderState, token: Option<String>) {
+  let token = match token {
+    Some(token) => token,
+    None => return future::pending().await,
+  };
+  loop {
+    state.control.devices_revoked.notified().await;
+    if !is_paired(&token) {
+      return;
+    }
+  }
+}
+
+fn air_flags(air: &[u8; 6]) -> String {
+  air.iter().map(|a| if *a > 0 { '1' } else { '0' }).collect()
+}
+
+/// Counts a message in the client list, telling touch clients and hand
+/// trackers apart by what they send.
+fn client_status(state: &SliderState, status_id: usize, msg: &ClientMessage) {
+  let mut client_list = state.control.brokenithm_clients.lock();
+  let kind = match msg {
+    ClientMessage::Alive(heartbeat) => {
+      client_list.heartbeat(status_id, heartbeat.rtt);
+      None
+    }
+    // Older hand trackers send air flags alone
+    ClientMessage::Input(frame) => match frame.ground {
+      Some(_) => Some(ClientKind::Touch),
+      None => Some(ClientKind::HandTracker),
+    },
+    ClientMessage::Hands(_) => Some(ClientKind::HandTracker),
+    _ => None,
+  };
+  client_list.message(status_id, kind);
+}
+
+async fn handle_brokenithm(
+  ws_stream: WebSocketStream<Upgraded>,
+  ctx: ServerContext,
+  id: usize,
+  status_id: usize,
+  protocol: ClientProtocol,
 ) {
+  let ServerContext {
+    state,
+    lights_enabled,
+    web,
+    clients,
+    calibration,
+    hands,
+    ..
+  } = ctx;
+
   let (mut ws_write, mut ws_read) = ws_stream.split();
 
   let (msg_write, mut msg_read) = mpsc::unbounded_channel::<Message>();
@@ -106,6 +208,8 @@ async fn handle_brokenithm(
 
   let msg_write_handle = msg_write.clone();
   let state_handle = state.clone();
+  let mut seq_filter = SequenceFilter::new();
+  let mut pings = PingTimer::new();
   let read_task = async move {
     // info!("Websocket read task open");
     loop {
@@ -113,66 +217,65 @@ async fn handle_brokenithm(
         Some(msg) => match msg {
           Ok(msg) => match msg {
             Message::Text(msg) => {
-              let chars = msg.chars().collect::<Vec<char>>();
-
-              match chars.len() {
-                6 => {
-                  if chars[0] == 'a' {
-                    msg_write_handle
-                      .send(Message::Text("alive".to_string()))
-                      .ok();
-                  }
+              state_handle.mark_report();
+              let parsed = parse_text(&msg);
+              client_status(&state_handle, status_id, &parsed);
+              match parsed {
+                ClientMessage::Alive(heartbeat) => {
+                  clients.lock().seen(id);
+                  msg_write_handle.send(Message::Text(heartbeat.reply())).ok();
+                  msg_write_handle.send(Message::Ping(pings.ping())).ok();
                 }
-                7 => {
-                  if chars[0] == 'd' {
-                    // Air notes for webcam hand tracker.
-                    let mut input_handle = state_handle.input.lock();
-                    for (idx, c) in chars[1..7].iter().enumerate() {
-                      input_handle.air[idx] = match *c == '1' {
-                        false => 0,
-                        true => 1,
-                      }
-                    }
-                  }
+                ClientMessage::Input(frame) => {
+                  clients.lock().update(id, &frame);
                 }
-                39 => {
-                  if chars[0] == 'b' {
-                    let mut input_handle = state_handle.input.lock();
-                    for (idx, c) in chars[1..33].iter().enumerate() {
-                      input_handle.ground[idx] = match *c == '1' {
-                        false => 0,
-                        true => 255,
-                      }
-                    }
-                    if !matches!(spec, BrokenithmSpec::HandTracking) {
-                      for (idx, c) in chars[33..39].iter().enumerate() {
-                        input_handle.air[idx] = match *c == '1' {
-                          false => 0,
-                          true => 1,
-                        }
-                      }
-                    }
+                ClientMessage::Hands(frame) => {
+                  clients.lock().seen(id);
+                  let latest = calibration.lock().clone();
+                  let air = hands.lock().update(id, &frame, &latest);
+                  msg_write_handle
+                    .send(Message::Text(format!("z{}", air_flags(&air))))
+                    .ok();
+                }
+                ClientMessage::Ignored => {}
+                ClientMessage::Invalid => {
+                  break;
+                }
+              }
+            }
+            Message::Binary(msg) if protocol == ClientProtocol::BinaryV1 => {
+              state_handle.mark_report();
+              let parsed = parse_binary(&msg);
+              client_status(&state_handle, status_id, &parsed);
+              match parsed {
+                ClientMessage::Input(frame) => {
+                  if seq_filter.accept(frame.seq) {
+                    clients.lock().update(id, &frame);
                   }
                 }
                 _ => {
+                  error!("Websocket invalid binary message of {} bytes", msg.len());
                   break;
                 }
               }
             }
+            Message::Pong(payload) => {
+              if let Some(rtt) = pings.pong(&payload) {
+                state_handle
+                  .control
+                  .brokenithm_clients
+                  .lock()
+                  .round_trip(status_id, rtt);
+              }
+            }
             Message::Close(_) => {
               info!("Websocket connection closed");
-              let mut input_handle = state_handle.input.lock();
-              input_handle.ground.fill(0);
-              input_handle.air.fill(0);
               break;
             }
             _ => {}
           },
           Err(e) => {
             error!("Websocket connection error: {}", e);
-            let mut input_handle = state_handle.input.lock();
-            input_handle.ground.fill(0);
-            input_handle.air.fill(0);
             break;
           }
         },
@@ -194,6 +297,7 @@ async fn handle_brokenithm(
     true => {
       let msg_write_handle = msg_write.clone();
       let state_handle = state.clone();
+      let interval = Duration::from_millis(web.led_interval_ms);
       let lights_task = async move {
         loop {
           let mut lights_data = vec![0; 93];
@@ -203,7 +307,7 @@ async fn handle_brokenithm(
           }
           msg_write_handle.send(Message::Binary(lights_data)).ok();
 
-          sleep(Duration::from_millis(50)).await;
+          sleep(interval).await;
         }
       };
 
@@ -218,13 +322,37 @@ async fn handle_brokenithm(
 
 async fn handle_websocket(
   mut request: Request<Body>,
-  state: SliderState,
-  lights_enabled: bool,
-  spec: BrokenithmSpec,
+  remote_addr: SocketAddr,
+  ctx: ServerContext,
 ) -> Result<Response<Body>, Infallible> {
+  let connection = match ctx.access.connect(&remote_addr.ip()) {
+    Ok(connection) => connection,
+    Err(status) => return refused_response(status),
+  };
+  let protocol = ClientProtocol::negotiate(request.headers());
+  let token = match ctx.pairing {
+    true => device_token(request.headers()),
+    false => None,
+  };
+  let user_agent = request
+    .headers()
+    .get(header::USER_AGENT)
+    .and_then(|v| v.to_str().ok())
+    .unwrap_or("")
+    .to_string();
   let res = match handshake::server::create_response_with_body(&request, || Body::empty()) {
-    Ok(res) => {
+    Ok(mut res) => {
+      if let Some(name) = protocol.header() {
+        res.headers_mut().insert(
+          header::SEC_WEBSOCKET_PROTOCOL,
+          HeaderValue::from_static(name),
+        );
+      }
+      info!("Websocket client protocol {:?}", protocol);
+
       tokio::spawn(async move {
+        // Counted until the connection closes
+        let _connection = connection;
         match upgrade::on(&mut request).await {
           Ok(upgraded) => {
             let ws_stream = WebSocketStream::from_raw_socket(
@@ -234,7 +362,33 @@ async fn handle_websocket(
             )
             .await;
 
-            handle_brokenithm(ws_stream, state, lights_enabled, spec).await;
+            let id = ctx.clients.lock().connect();
+            let status_id = ctx.state.control.brokenithm_clients.lock().connect(
+              remote_addr,
+              &user_agent,
+              protocol,
+              ctx.latency_warn_ms,
+            );
+            let res = select! {
+              res = AssertUnwindSafe(handle_brokenithm(ws_stream, ctx.clone(), id, status_id, protocol))
+                .catch_unwind() => res,
+              _ = wait_unpaired(&ctx.state, token) => {
+                info!("Websocket client {} closed, device unpaired", id);
+                Ok(())
+              }
+            };
+            ctx.clients.lock().disconnect(id);
+            ctx.hands.lock().remove(id);
+            ctx
+              .state
+              .control
+              .brokenithm_clients
+              .lock()
+              .disconnect(status_id);
+            if let Err(e) = res {
+              error!("Websocket handler panicked: {}", panic_message(&e));
+              ctx.state.failsafe();
+            }
           }
 
           Err(e) => {
@@ -259,33 +413,106 @@ async fn handle_websocket(
 async fn handle_request(
   request: Request<Body>,
   remote_addr: SocketAddr,
-  state: SliderState,
-  spec: BrokenithmSpec,
-  lights_enabled: bool,
+  ctx: ServerContext,
 ) -> Result<Response<Body>, Infallible> {
+  if let Err(status) = ctx.access.check_request(&remote_addr.ip()) {
+    return refused_response(status);
+  }
   let method = request.method();
   let path = request.uri().path();
-  if method != Method::GET {
-    error!(
-      "Server unknown method {} -> {} {}",
-      remote_addr, method, path
-    );
-    return error_response().await;
+  match (method, path) {
+    (&Method::GET, _) => {}
+    (&Method::POST, _) if !same_origin(request.headers(), &ctx.origins) => {
+      error!(
+        "Server refused cross origin {} -> {} {}",
+        remote_addr, method, path
+      );
+      return refused_response(StatusCode::FORBIDDEN);
+    }
+    (&Method::POST, "/api/pair") => {
+      info!("Server {} -> {} {}", remote_addr, method, path);
+      let ip = remote_addr.ip();
+      return save_pairing(request, &ip, &ctx.state.control, &ctx.access).await;
+    }
+    (&Method::POST, "/api/calibration") => {
+      info!("Server {} -> {} {}", remote_addr, method, path);
+      if !ctx.authorized(request.headers()) {
+        return refused_response(StatusCode::UNAUTHORIZED);
+      }
+      return save_calibration(request, &ctx.calibration, &ctx.state).await;
+    }
+    _ => {
+      error!(
+        "Server unknown method {} -> {} {}",
+        remote_addr, method, path
+      );
+      return error_response().await;
+    }
   }
   info!("Server {} -> {} {}", remote_addr, method, path);
 
+  let asset_request = AssetRequest::new(&request, ctx.asset_version.current());
   match (
     request.uri().path(),
     request.headers().contains_key(header::UPGRADE),
   ) {
-    ("/", false) | ("/index.html", false) => match spec {
-      BrokenithmSpec::Basic => serve_file("index.html").await,
-      BrokenithmSpec::GroundOnly => serve_file("index-go.html").await,
-      BrokenithmSpec::Nostalgia => serve_file("index-ns.html").await,
-      BrokenithmSpec::HandTracking => serve_file("index-go.html").await,
+    ("/", false) | ("/index.html", false) => match ctx.spec {
+      BrokenithmSpec::Basic => {
+        serve_file("index.html", ctx.skin_dir.as_deref(), &asset_request).await
+      }
+      BrokenithmSpec::GroundOnly => {
+        serve_file("index-go.html", ctx.skin_dir.as_deref(), &asset_request).await
+      }
+      BrokenithmSpec::Nostalgia => {
+        serve_file("index-ns.html", ctx.skin_dir.as_deref(), &asset_request).await
+      }
+      BrokenithmSpec::HandTracking => {
+        serve_file("index-go.html", ctx.skin_dir.as_deref(), &asset_request).await
+      }
     },
-    (filename, false) => serve_file(&filename[1..]).await,
-    ("/ws", true) => handle_websocket(request, state, lights_enabled, spec).await,
+    ("/api/calibration", false) => get_calibration(&ctx.calibration).await,
+    ("/api/pair", false) => get_pairing(request.headers(), ctx.pairing).await,
+    ("/api/status", false) => {
+      get_status(
+        &ctx.spec,
+        ctx.lights_enabled,
+        ctx.pairing,
+        ctx.started,
+        ctx.latency_warn_ms,
+        &ctx.state,
+      )
+      .await
+    }
+    // Addresses and user agents of other devices are kept from unpaired ones
+    ("/api/clients", false) if !ctx.authorized(request.headers()) => {
+      refused_response(StatusCode::UNAUTHORIZED)
+    }
+    ("/api/clients", false) => get_clients(&ctx.state.control).await,
+    // A skin can still ship its own
+    ("/config.js", false) => match ctx.skin_dir.as_deref() {
+      Some(dir) if dir.join("config.js").is_file() => {
+        serve_file("config.js", Some(dir), &asset_request).await
+      }
+      _ => get_web_config(&ctx.spec, ctx.lights_enabled, &ctx.web).await,
+    },
+    (filename, false) if filename.starts_with("/lib/") => {
+      serve_hand_asset(
+        &filename[1..],
+        ctx.hand_asset_dir.as_deref(),
+        &asset_request,
+      )
+      .await
+    }
+    (filename, false) => serve_file(&filename[1..], ctx.skin_dir.as_deref(), &asset_request).await,
+    ("/ws", true) if !same_origin(request.headers(), &ctx.origins) => {
+      error!("Server refused cross origin websocket from {}", remote_addr);
+      refused_response(StatusCode::FORBIDDEN)
+    }
+    ("/ws", true) if !ctx.authorized(request.headers()) => {
+      error!("Server refused unpaired websocket from {}", remote_addr);
+      refused_response(StatusCode::UNAUTHORIZED)
+    }
+    ("/ws", true) => handle_websocket(request, remote_addr, ctx).await,
     _ => error_response().await,
   }
 }
@@ -295,6 +522,7 @@ pub struct BrokenithmJob {
   spec: BrokenithmSpec,
   lights_enabled: bool,
   port: u16,
+  options: BrokenithmOptions,
 }
 
 impl BrokenithmJob {
@@ -303,47 +531,303 @@ impl BrokenithmJob {
     spec: &BrokenithmSpec,
     lights_enabled: &bool,
     port: &u16,
+    options: &BrokenithmOptions,
   ) -> Self {
     Self {
       state: state.clone(),
       spec: spec.clone(),
       lights_enabled: *lights_enabled,
       port: *port,
+      options: options.clone(),
     }
   }
 }
 
+/// Binds a port of the server, recording the port actually bound under `key`
+/// or why it could not be bound in `status`.
+async fn bind_port(
+  options: &BrokenithmOptions,
+  key: &'static str,
+  port: u16,
+  status: &mut WorkerStatus,
+) -> Option<net::TcpListener> {
+  match options.access.bind(port, options.fallback_ports).await {
+    Ok(listener) => {
+      let bound = listener.local_addr().map_or(port, |addr| addr.port());
+      status.ports.insert(key, bound);
+      Some(listener)
+    }
+    Err(e) => {
+      error!("Brokenithm server could not listen on port {}: {}", port, e);
+      let error = format!("Port {} could not be used: {}", port, e);
+      status.error = Some(match status.error.take() {
+        Some(previous) => format!("{}; {}", previous, error),
+        None => error,
+      });
+      None
+    }
+  }
+}
+
+/// Serves HTTPS on `listener` until `stop_signal` resolves, then gracefully
+/// shuts down open connections.
+async fn serve_tls<F: Future<Output = ()>>(
+  listener: net::TcpListener,
+  acceptor: TlsAcceptor,
+  ctx: ServerContext,
+  stop_signal: F,
+) {
+  let listener = match TcpListener::from_std(listener) {
+    Ok(listener) => listener,
+    Err(e) => {
+      error!("Brokenithm TLS server error: {}", e);
+      return;
+    }
+  };
+  if let Ok(addr) = listener.local_addr() {
+    info!("Brokenithm TLS server listening on {}", addr);
+  }
+
+  let (stop_write, stop_read) = watch::channel(false);
+  tokio::pin!(stop_signal);
+
+  loop {
+    let (stream, remote_addr) = select! {
+      _ = &mut stop_signal => break,
+      accepted = listener.accept() => match accepted {
+        Ok(accepted) => accepted,
+        Err(e) => {
+          error!("Brokenithm TLS accept error: {}", e);
+          continue;
+        }
+      },
+    };
+
+    // Refused before spending a TLS handshake on it
+    if !ctx.access.permits(&remote_addr.ip()) {
+      info!("Brokenithm TLS refused {}", remote_addr);
+      continue;
+    }
+
+    let acceptor = acceptor.clone();
+    let ctx = ctx.clone();
+    let mut stop_read = stop_read.clone();
+    tokio::spawn(async move {
+      let stream = match acceptor.accept(stream).await {
+        Ok(stream) => stream,
+        Err(e) => {
+          info!(
+            "Brokenithm TLS handshake from {} failed: {}",
+            remote_addr, e
+          );
+          return;
+        }
+      };
+
+      let service =
+        service_fn(move |request: Request<Body>| handle_request(request, remote_addr, ctx.clone()));
+      let conn = Http::new()
+        .http1_only(true)
+        .serve_connection(stream, service)
+        .with_upgrades();
+      tokio::pin!(conn);
+
+      select! {
+        res = conn.as_mut() => {
+          if let Err(e) = res {
+            info!("Brokenithm TLS connection from {} closed: {}", remote_addr, e);
+          }
+        }
+        _ = stop_read.changed() => {
+          conn.as_mut().graceful_shutdown();
+          conn.await.ok();
+        }
+      }
+    });
+  }
+
+  stop_write.send(true).ok();
+}
+
 #[async_trait]
 impl AsyncHaltableJob for BrokenithmJob {
   async fn run<F: Future<Output = ()> + Send>(self, stop_signal: F) {
-    let state = self.state.clone();
-    let spec = self.spec.clone();
-    let lights_enabled = self.lights_enabled;
+    if self.options.pairing {
+      let control = &self.state.control;
+      if control.get_brokenithm_pin().is_none() {
+        control.brokenithm_pin.store(new_pin(), Ordering::SeqCst);
+      }
+      info!("Brokenithm pairing required");
+    }
+
+    let clients = Arc::new(Mutex::new(ClientInputs::new(
+      &self.state,
+      &self.spec,
+      &self.options.merge,
+    )));
+    self.state.freshness.lock().clients = Some(Arc::downgrade(&clients));
+    let stop_signal = stop_signal.shared();
+
+    let tls = match self.options.tls {
+      BrokenithmTls::Off => None,
+      BrokenithmTls::Https | BrokenithmTls::Both => match tls_acceptor(&self.options) {
+        Ok(acceptor) => Some(acceptor),
+        Err(e) => {
+          // Serving plain HTTP on a port meant for HTTPS would only confuse
+          error!("Brokenithm TLS setup error: {}", e);
+          self.state.control.set_worker_status(
+            "brokenithm",
+            WorkerStatus::failed(format!("HTTPS could not be set up: {}", e)),
+          );
+          return;
+        }
+      },
+    };
+    // Ports are reported under the config key they stand in for
+    let (http_port, tls_port) = match (self.options.tls, tls.is_some()) {
+      (_, false) => (Some(("brokenithmPort", self.port)), None),
+      (BrokenithmTls::Https, true) => (None, Some(("brokenithmPort", self.port))),
+      (_, true) => (
+        Some(("brokenithmPort", self.port)),
+        Some(("brokenithmTlsPort", self.options.tls_port)),
+      ),
+    };
+
+    let mut status = WorkerStatus::default();
+    let http_listener = match http_port {
+      Some((key, port)) => bind_port(&self.options, key, port, &mut status).await,
+      None => None,
+    };
+    let tls_listener = match tls_port {
+      Some((key, port)) => bind_port(&self.options, key, port, &mut status).await,
+      None => None,
+    };
+    self.state.control.set_worker_status("brokenithm", status);
+
+    // Withdrawn when the server stops
+    let bound_port = |listener: &Option<net::TcpListener>| {
+      listener
+        .as_ref()
+        .and_then(|l| l.local_addr().ok())
+        .map(|addr| addr.port())
+    };
+    let advertised = [
+      ("_http._tcp", bound_port(&http_listener)),
+      ("_https._tcp", bound_port(&tls_listener)),
+    ];
+    let _advertisements = match &self.options.mdns_name {
+      Some(name) => advertised
+        .iter()
+        .filter_map(|(service_type, port)| {
+          Advertisement::new(
+            service_type,
+            name,
+            name,
+            self.options.access.bind,
+            (*port)?,
+            &[("path", "/")],
+          )
+        })
+        .collect::<Vec<_>>(),
+      None => vec![],
+    };
+
+    let calibration = Arc::new(self.options.hand_tracking.clone());
+    let ctx = ServerContext {
+      state: self.state.clone(),
+      spec: self.spec.clone(),
+      lights_enabled: self.lights_enabled,
+      web: Arc::new(self.options.web.clone()),
+      pairing: self.options.pairing,
+      origins: Arc::new(ServerOrigins::new(
+        bound_port(&http_listener),
+        bound_port(&tls_listener),
+        self.options.mdns_name.clone(),
+        self.options.allowed_origins.clone(),
+      )),
+      access: ServerAccess::new(&self.options.access),
+      hands: Arc::new(Mutex::new(HandSources::new(
+        &self.state,
+        &clients,
+        &calibration,
+        self.options.hand_record,
+      ))),
+      clients,
+      calibration: Arc::new(Mutex::new(calibration)),
+      hand_asset_dir: self.options.hand_asset_dir.clone(),
+      asset_version: Arc::new(AssetVersion::new(self.options.hand_asset_dir.clone())),
+      skin_dir: self.options.skin.as_ref().and_then(|skin| {
+        let dir = get_skins_dir().and_then(|skins| asset_path(&skins, skin));
+        match dir {
+          Some(dir) if dir.is_dir() => {
+            info!("Brokenithm skin {:?}", dir);
+            Some(dir)
+          }
+          _ => {
+            error!("Brokenithm skin {} not found", skin);
+            None
+          }
+        }
+      }),
+      started: Instant::now(),
+      latency_warn_ms: self.options.latency_warn_ms,
+    };
     let make_svc = make_service_fn(|conn: &AddrStream| {
       let remote_addr = conn.remote_addr();
-      let make_svc_state = state.clone();
-      let make_spec = spec.clone();
+      let make_ctx = ctx.clone();
       async move {
         Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
-          let svc_state = make_svc_state.clone();
-          let spec = make_spec.clone();
-          handle_request(request, remote_addr, svc_state, spec, lights_enabled)
+          handle_request(request, remote_addr, make_ctx.clone())
         }))
       }
     });
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], self.port));
-    info!("Brokenithm server listening on {}", addr);
+    let http_server = async {
+      if let Some(listener) = http_listener {
+        let server = match Server::from_tcp(listener) {
+          Ok(server) => server,
+          Err(e) => {
+            error!("Brokenithm server error: {}", e);
+            return;
+          }
+        };
+        let server = server
+          // .http1_keepalive(false)
+          // .http2_keep_alive_interval(None)
+          // .tcp_keepalive(None)
+          .serve(make_svc);
+        info!("Brokenithm server listening on {}", server.local_addr());
 
-    let server = Server::bind(&addr)
-      // .http1_keepalive(false)
-      // .http2_keep_alive_interval(None)
-      // .tcp_keepalive(None)
-      .serve(make_svc)
-      .with_graceful_shutdown(stop_signal);
+        if let Err(e) = server.with_graceful_shutdown(stop_signal.clone()).await {
+          error!("Brokenithm server stopped: {}", e);
+          self.state.control.set_worker_status(
+            "brokenithm",
+            WorkerStatus::failed(format!("Server stopped: {}", e)),
+          );
+        }
+      }
+    };
 
-    if let Err(e) = server.await {
-      info!("Brokenithm server stopped: {}", e);
-    }
+    let tls_server = async {
+      if let (Some(listener), Some(acceptor)) = (tls_listener, tls.clone()) {
+        serve_tls(listener, acceptor, ctx.clone(), stop_signal.clone()).await;
+      }
+    };
+
+    // Releases the air strings of hand trackers that stop sending frames
+    let hand_expiry = async {
+      let expire = async {
+        loop {
+          sleep(Duration::from_millis(50)).await;
+          ctx.hands.lock().expire();
+        }
+      };
+      select! {
+        _ = stop_signal.clone() => {}
+        _ = expire => {}
+      }
+    };
+
+    join!(http_server, tls_server, hand_expiry);
   }
 }
@@ -0,0 +1,347 @@
+use hyper::{
+  body,
+  header::{self, HeaderValue},
+  Body, HeaderMap, Request, Response, StatusCode,
+};
+use log::{error, info};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::{
+  convert::Infallible,
+  net::IpAddr,
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+use crate::{
+  config::Config,
+  device::{
+    brokenithm_pairing::{device_token, is_paired, pair_device, token_cookie},
+    brokenithm_protocol::ClientProtocol,
+    brokenithm_status::ClientKind,
+    config::{BrokenithmSpec, BrokenithmWebConfig},
+  },
+  handtracking::config::HandTrackingConfig,
+  shared::access::ServerAccess,
+  state::{SliderControl, SliderState},
+};
+
+/// Largest request body accepted by the API.
+const MAX_BODY: u64 = 16 * 1024;
+
+fn json_response(status: StatusCode, value: &Value) -> Response<Body> {
+  Response::builder()
+    .status(status)
+    .header(header::CONTENT_TYPE, "application/json")
+    .header(header::CACHE_CONTROL, "no-store")
+    .body(Body::from(value.to_string()))
+    .unwrap()
+}
+
+fn error_json(status: StatusCode, message: &str) -> Response<Body> {
+  json_response(status, &serde_json::json!({ "error": message }))
+}
+
+/// Refuses PINs from an address that guessed wrong too often.
+fn locked_out_json(wait: Duration) -> Response<Body> {
+  let seconds = wait.as_secs() + 1;
+  let mut res = error_json(
+    StatusCode::TOO_MANY_REQUESTS,
+    &format!("Too many wrong PINs, try again in {} seconds", seconds),
+  );
+  res
+    .headers_mut()
+    .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+  res
+}
+
+/// Whether a request body is declared as JSON. Pages of other sites cannot
+/// post JSON without a CORS preflight, which this server never allows, so
+/// this also keeps them from posting to the API.
+fn json_content(headers: &HeaderMap) -> bool {
+  headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.split(';').next())
+    .is_some_and(|v| v.trim().eq_ignore_ascii_case("application/json"))
+}
+
+async fn read_json(request: Request<Body>) -> Result<Value, Response<Body>> {
+  if !json_content(request.headers()) {
+    return Err(error_json(
+      StatusCode::UNSUPPORTED_MEDIA_TYPE,
+      "Expected application/json",
+    ));
+  }
+  let length = request
+    .headers()
+    .get(header::CONTENT_LENGTH)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.parse::<u64>().ok());
+  match length {
+    Some(length) if length <= MAX_BODY => {}
+    Some(_) => return Err(error_json(StatusCode::PAYLOAD_TOO_LARGE, "Body too large")),
+    None => return Err(error_json(StatusCode::LENGTH_REQUIRED, "Missing length")),
+  }
+
+  let bytes = body::to_bytes(request.into_body())
+    .await
+    .map_err(|_| error_json(StatusCode::BAD_REQUEST, "Could not read body"))?;
+  serde_json::from_slice(&bytes).map_err(|_| error_json(StatusCode::BAD_REQUEST, "Invalid JSON"))
+}
+
+/// `GET /api/calibration`, the hand tracking calibration as config keys.
+pub async fn get_calibration(
+  calibration: &Mutex<Arc<HandTrackingConfig>>,
+) -> Result<Response<Body>, Infallible> {
+  let value = calibration.lock().to_serde_value();
+  Ok(json_response(StatusCode::OK, &value))
+}
+
+/// `POST /api/calibration`, updates the given calibration keys, applies them
+/// to the running server and saves them to the config file.
+pub async fn save_calibration(
+  request: Request<Body>,
+  calibration: &Mutex<Arc<HandTrackingConfig>>,
+  state: &SliderState,
+) -> Result<Response<Body>, Infallible> {
+  let changes = match read_json(request).await {
+    Ok(Value::Object(changes)) => changes,
+    Ok(_) => return Ok(error_json(StatusCode::BAD_REQUEST, "Expected an object")),
+    Err(res) => return Ok(res),
+  };
+
+  let mut merged = calibration.lock().to_serde_value();
+  let keys = merged.as_object_mut().unwrap();
+  for (key, value) in changes {
+    if !keys.contains_key(&key) {
+      return Ok(error_json(
+        StatusCode::BAD_REQUEST,
+        &format!("Unknown key {}", key),
+      ));
+    }
+    keys.insert(key, value);
+  }
+
+  let new_calibration = HandTrackingConfig::from_serde_value(&merged);
+  if let Err(e) = new_calibration.validate() {
+    return Ok(error_json(StatusCode::BAD_REQUEST, &e));
+  }
+  let value = new_calibration.to_serde_value();
+
+  if Config::update_saved(&value).is_none() {
+    error!("Hand tracking calibration could not be saved");
+    return Ok(error_json(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "Could not save config",
+    ));
+  }
+  info!("Hand tracking calibration saved {}", value);
+  *calibration.lock() = Arc::new(new_calibration);
+  state.control.config_saved.notify_one();
+
+  Ok(json_response(StatusCode::OK, &value))
+}
+
+/// `GET /config.js`, the web controller settings for the active spec and
+/// config. It is generated on every request so that a reload picks up
+/// changes.
+pub async fn get_web_config(
+  spec: &BrokenithmSpec,
+  lights_enabled: bool,
+  web: &BrokenithmWebConfig,
+) -> Result<Response<Body>, Infallible> {
+  // Ground keys, air keys, whether the air region is shown
+  let (ground_keys, air_keys, air_shown) = match spec {
+    BrokenithmSpec::Basic => (16, 6, true),
+    BrokenithmSpec::GroundOnly | BrokenithmSpec::HandTracking => (16, 6, false),
+    BrokenithmSpec::Nostalgia => (28, 0, false),
+  };
+
+  let config = serde_json::json!({
+    "invert": web.invert,
+    "bgImage": match web.bg_image.is_empty() {
+      true => Value::Bool(false),
+      false => Value::from(web.bg_image.as_str()),
+    },
+    "bgColor": web.bg_color,
+    "keyColor": web.key_color,
+    "lkeyColor": web.air_color,
+    "keyHeight": 1,
+    "lkeyHeight": match air_shown {
+      true => Value::from(web.air_height),
+      false => Value::Null,
+    },
+    "groundKeys": ground_keys,
+    "airKeys": air_keys,
+    "allAir": matches!(spec, BrokenithmSpec::Nostalgia),
+    "lights": lights_enabled,
+    "ledRefreshMs": web.led_interval_ms,
+    "protocol": ClientProtocol::LATEST.header(),
+    "protocolVersion": ClientProtocol::LATEST.version(),
+  });
+
+  Ok(
+    Response::builder()
+      .header(header::CONTENT_TYPE, "text/javascript")
+      .header(header::CACHE_CONTROL, "no-store")
+      .body(Body::from(format!("var config = {};\n", config)))
+      .unwrap(),
+  )
+}
+
+/// `GET /api/status`, what the server runs and how many clients it has.
+pub async fn get_status(
+  spec: &BrokenithmSpec,
+  lights_enabled: bool,
+  pairing: bool,
+  started: Instant,
+  latency_warn_ms: f64,
+  state: &SliderState,
+) -> Result<Response<Body>, Infallible> {
+  let last_input = state.freshness.lock().last_report;
+  let (clients, touch_clients, hand_trackers, latency, slow_clients) = {
+    let clients = state.control.brokenithm_clients.lock();
+    (
+      clients.len(),
+      clients.count(ClientKind::Touch),
+      clients.count(ClientKind::HandTracker),
+      clients.latency(),
+      clients.slow(),
+    )
+  };
+
+  Ok(json_response(
+    StatusCode::OK,
+    &serde_json::json!({
+      "version": env!("CARGO_PKG_VERSION"),
+      "layout": match spec {
+        BrokenithmSpec::Basic => "basic",
+        BrokenithmSpec::GroundOnly => "groundOnly",
+        BrokenithmSpec::Nostalgia => "nostalgia",
+        BrokenithmSpec::HandTracking => "handTracking",
+      },
+      "lights": lights_enabled,
+      "pairing": pairing,
+      "protocol": ClientProtocol::LATEST.header(),
+      "protocolVersion": ClientProtocol::LATEST.version(),
+      "uptime": started.elapsed().as_secs(),
+      "clients": clients,
+      "touchClients": touch_clients,
+      "handTrackers": hand_trackers,
+      "lastInputMs": last_input.map(|t| t.elapsed().as_millis() as u64),
+      "latency": latency.to_serde_value(),
+      "latencyWarnMs": latency_warn_ms,
+      "slowClients": slow_clients,
+    }),
+  ))
+}
+
+/// `GET /api/clients`, the connected websocket clients.
+pub async fn get_clients(control: &SliderControl) -> Result<Response<Body>, Infallible> {
+  let clients = control.brokenithm_clients.lock().to_serde_value();
+  Ok(json_response(StatusCode::OK, &clients))
+}
+
+/// `GET /api/pair`, whether the device has to pair before sending input and
+/// whether it already is.
+pub async fn get_pairing(
+  headers: &HeaderMap,
+  required: bool,
+) -> Result<Response<Body>, Infallible> {
+  let paired = device_token(headers).is_some_and(|token| is_paired(&token));
+  Ok(json_response(
+    StatusCode::OK,
+    &serde_json::json!({ "required": required, "paired": paired }),
+  ))
+}
+
+/// `POST /api/pair`, pairs the device with `{"pin": "123456"}` and keeps its
+/// token in a cookie. An address that guesses wrong too often is locked out
+/// for longer and longer, the PIN itself stays the same.
+pub async fn save_pairing(
+  request: Request<Body>,
+  ip: &IpAddr,
+  control: &SliderControl,
+  access: &ServerAccess,
+) -> Result<Response<Body>, Infallible> {
+  if let Some(wait) = access.locked_out(ip) {
+    return Ok(locked_out_json(wait));
+  }
+
+  let name = request
+    .headers()
+    .get(header::USER_AGENT)
+    .and_then(|v| v.to_str().ok())
+    .unwrap_or("Unknown device")
+    .to_string();
+  let pin = match read_json(request).await {
+    Ok(value) => parse_pin(&value["pin"]),
+    Err(res) => return Ok(res),
+  };
+
+  let expected = control.get_brokenithm_pin();
+  if expected.is_none() || pin != expected {
+    return Ok(match access.record_failure(ip) {
+      Some(wait) => locked_out_json(wait),
+      None => error_json(StatusCode::FORBIDDEN, "Wrong PIN"),
+    });
+  }
+  access.clear_failures(ip);
+
+  match pair_device(&name) {
+    Some(token) => {
+      let mut res = json_response(StatusCode::OK, &serde_json::json!({ "paired": true }));
+      res
+        .headers_mut()
+        .insert(header::SET_COOKIE, token_cookie(&token).parse().unwrap());
+      Ok(res)
+    }
+    None => Ok(error_json(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "Could not save paired device",
+    )),
+  }
+}
+
+/// PIN as typed on the pairing prompt, a string or a number.
+fn parse_pin(v: &Value) -> Option<u32> {
+  match v {
+    Value::String(pin) => pin.trim().parse::<u32>().ok(),
+    pin => pin.as_u64().and_then(|p| u32::try_from(p).ok()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn accepts_json_content_only() {
+    let content = |v: &str| {
+      let mut headers = HeaderMap::new();
+      headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(v).unwrap());
+      json_content(&headers)
+    };
+    assert!(content("application/json"));
+    assert!(content("Application/JSON; charset=utf-8"));
+    assert!(!content("text/plain"));
+    assert!(!content("application/x-www-form-urlencoded"));
+    assert!(!content("multipart/form-data; boundary=x"));
+    assert!(!json_content(&HeaderMap::new()));
+  }
+
+  #[test]
+  fn parses_pins() {
+    let pin = |v: Value| parse_pin(&v);
+    assert_eq!(pin(serde_json::json!("123456")), Some(123456));
+    assert_eq!(pin(serde_json::json!(" 123456\n")), Some(123456));
+    assert_eq!(pin(serde_json::json!(123456)), Some(123456));
+    assert_eq!(pin(serde_json::json!("12 34 56")), None);
+    assert_eq!(pin(serde_json::json!("-123456")), None);
+    assert_eq!(pin(serde_json::json!(-1)), None);
+    assert_eq!(pin(serde_json::json!(12.5)), None);
+    assert_eq!(pin(serde_json::json!(u64::MAX)), None);
+    assert_eq!(pin(Value::Null), None);
+  }
+}
@@ -0,0 +1,634 @@
+use flate2::{write::GzEncoder, Compression};
+use hyper::{
+  header::{self, HeaderValue},
+  Body, Request, Response, StatusCode,
+};
+use log::error;
+use parking_lot::Mutex;
+use phf::phf_map;
+use std::{
+  convert::Infallible,
+  fs::{self, Metadata},
+  io::{self, Write},
+  path::{Component, Path, PathBuf},
+  sync::Arc,
+  time::{Duration, Instant, UNIX_EPOCH},
+};
+
+/// A file built into the binary with a strong ETag and precompressed variants
+/// of it, generated by the build script.
+pub struct EmbeddedFile {
+  data: &'static [u8],
+  etag: &'static str,
+  gzip: Option<(&'static [u8], &'static str)>,
+  brotli: Option<(&'static [u8], &'static str)>,
+  /// Requests hand tracker files with `ASSET_VERSION`.
+  has_version: bool,
+}
+
+// Defines `WEB_FILES` and `HAND_FILES`, the hand tracker wasm and model files
+// are only in there with the `embedded-hand-assets` feature. `ASSET_VERSION`
+// is a hash of the contents of `HAND_FILES`, the embedded pages request them
+// with it as their `v` query.
+include!(concat!(env!("OUT_DIR"), "/embedded_files.rs"));
+
+/// Cache lifetime of files requested with the current asset version.
+const CACHE_VERSIONED: &str = "public, max-age=31536000, immutable";
+/// Everything else is checked against its ETag on every use.
+const CACHE_REVALIDATE: &str = "no-cache";
+
+/// How long the files in the hand asset folder are trusted not to have
+/// changed before they are listed again.
+const VERSION_REFRESH: Duration = Duration::from_secs(2);
+
+/// Version of the hand tracker files that are actually served: the embedded
+/// ones, and the files in the hand asset folder replacing them.
+pub struct AssetVersion {
+  dir: Option<PathBuf>,
+  current: Mutex<Option<(Instant, Arc<str>)>>,
+}
+
+impl AssetVersion {
+  pub fn new(dir: Option<PathBuf>) -> Self {
+    Self {
+      dir,
+      current: Mutex::new(None),
+    }
+  }
+
+  pub fn current(&self) -> Arc<str> {
+    let mut current = self.current.lock();
+    match current.as_ref() {
+      Some((checked, version)) if checked.elapsed() < VERSION_REFRESH => version.clone(),
+      _ => {
+        let version: Arc<str> = match self.dir.as_deref().and_then(dir_hash) {
+          Some(hash) => format!("{}-{:016x}", ASSET_VERSION, hash).into(),
+          None => ASSET_VERSION.into(),
+        };
+        *current = Some((Instant::now(), version.clone()));
+        version
+      }
+    }
+  }
+}
+
+/// Hash of the path, length and modification time of every file under
+/// `lib/` in the hand asset folder, `None` when there are none.
+fn dir_hash(dir: &Path) -> Option<u64> {
+  fn list(dir: &Path, prefix: &str, files: &mut Vec<(String, Metadata)>) {
+    let entries = match fs::read_dir(dir) {
+      Ok(entries) => entries,
+      Err(_) => return,
+    };
+    for entry in entries.flatten() {
+      let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+      match entry.metadata() {
+        Ok(metadata) if metadata.is_dir() => list(&entry.path(), &name, files),
+        Ok(metadata) if metadata.is_file() => files.push((name, metadata)),
+        _ => {}
+      }
+    }
+  }
+
+  let mut files = vec![];
+  list(&dir.join("lib"), "lib", &mut files);
+  if files.is_empty() {
+    return None;
+  }
+  files.sort_by(|a, b| a.0.cmp(&b.0));
+  // FNV-1a, as the build script uses for the embedded files
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for (name, metadata) in &files {
+    let line = format!("{} {}\n", name, file_tag(metadata));
+    for byte in line.bytes() {
+      hash ^= byte as u64;
+      hash = hash.wrapping_mul(0x100000001b3);
+    }
+  }
+  Some(hash)
+}
+
+/// Identifies a version of a file on disk by its modification time and
+/// length, so it can be compared without reading it.
+fn file_tag(metadata: &Metadata) -> String {
+  let modified = metadata
+    .modified()
+    .ok()
+    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    .unwrap_or_default();
+  format!(
+    "{:x}{:08x}-{:x}",
+    modified.as_secs(),
+    modified.subsec_nanos(),
+    metadata.len()
+  )
+}
+
+/// What a request for a static file accepts.
+pub struct AssetRequest {
+  if_none_match: Option<String>,
+  gzip: bool,
+  brotli: bool,
+  /// Current asset version, which pages request the hand tracker files with.
+  version: Arc<str>,
+  /// Requested with the current asset version as the `v` query. Content at
+  /// such a URL only changes with the version, so it can be cached for good.
+  versioned: bool,
+}
+
+impl AssetRequest {
+  pub fn new(request: &Request<Body>, version: Arc<str>) -> Self {
+    let get = |name| {
+      request
+        .headers()
+        .get(name)
+        .and_then(|v: &HeaderValue| v.to_str().ok())
+    };
+    let accept_encoding = get(header::ACCEPT_ENCODING).unwrap_or("");
+
+    Self {
+      if_none_match: get(header::IF_NONE_MATCH).map(|v| v.to_string()),
+      gzip: accepts(accept_encoding, "gzip"),
+      brotli: accepts(accept_encoding, "br"),
+      versioned: request
+        .uri()
+        .query()
+        .is_some_and(|q| is_versioned(q, &version)),
+      version,
+    }
+  }
+
+  fn not_modified(&self, etag: &str) -> bool {
+    match self.if_none_match.as_deref() {
+      Some(tags) => tags
+        .split(',')
+        .map(|t| t.trim().trim_start_matches("W/"))
+        .any(|t| t == "*" || t == etag),
+      None => false,
+    }
+  }
+}
+
+/// Whether a query asks for the current version of the hand tracker files.
+/// Any other version may be stale.
+fn is_versioned(query: &str, version: &str) -> bool {
+  query
+    .split('&')
+    .any(|p| p.strip_prefix("v=") == Some(version))
+}
+
+/// Whether an `Accept-Encoding` header allows an encoding, which it does
+/// unless it is missing or has a quality of 0.
+fn accepts(accept_encoding: &str, encoding: &str) -> bool {
+  accept_encoding.split(',').any(|item| {
+    let mut parts = item.split(';').map(|p| p.trim());
+    parts
+      .next()
+      .is_some_and(|e| e.eq_ignore_ascii_case(encoding))
+      && parts.all(|p| {
+        p.strip_prefix("q=")
+          .and_then(|q| q.parse::<f64>().ok())
+          .is_none_or(|q| q > 0.0)
+      })
+  })
+}
+
+/// Embedded web controller file at a served path.
+pub fn web_file(path: &str) -> Option<&'static EmbeddedFile> {
+  WEB_FILES.get(path)
+}
+
+/// Serves an embedded file in the best encoding the request accepts, or
+/// `304 Not Modified` when the client has it already.
+pub async fn embedded_response(
+  path: &str,
+  file: &'static EmbeddedFile,
+  request: &AssetRequest,
+) -> Response<Body> {
+  // Pages built with another version than the served files request them with
+  // the current one instead, compressed here since the build time variants
+  // have the old one
+  if file.has_version && *request.version != *ASSET_VERSION {
+    let data = String::from_utf8_lossy(file.data)
+      .replace(ASSET_VERSION, &request.version)
+      .into_bytes();
+    let etag = format!("{}-{}\"", file.etag.trim_end_matches('"'), request.version);
+    return data_response(path, data, &etag, CACHE_REVALIDATE, request).await;
+  }
+
+  let (data, etag, encoding) = match (file.brotli, file.gzip) {
+    (Some((data, etag)), _) if request.brotli => (data, etag, Some("br")),
+    (_, Some((data, etag))) if request.gzip => (data, etag, Some("gzip")),
+    _ => (file.data, file.etag, None),
+  };
+  let cache = match request.versioned {
+    true => CACHE_VERSIONED,
+    false => CACHE_REVALIDATE,
+  };
+  encoded_response(path, Body::from(data), etag, encoding, cache, request)
+}
+
+/// Builds a response with caching headers, or `304 Not Modified` when the
+/// client has this ETag already.
+fn encoded_response(
+  path: &str,
+  body: Body,
+  etag: &str,
+  encoding: Option<&str>,
+  cache: &str,
+  request: &AssetRequest,
+) -> Response<Body> {
+  let builder = Response::builder()
+    .header(header::ETAG, etag)
+    .header(header::CACHE_CONTROL, cache)
+    .header(header::VARY, header::ACCEPT_ENCODING);
+  if request.not_modified(etag) {
+    return builder
+      .status(StatusCode::NOT_MODIFIED)
+      .body(Body::empty())
+      .unwrap();
+  }
+
+  let builder = builder.header(header::CONTENT_TYPE, mime_type(path));
+  match encoding {
+    Some(encoding) => builder.header(header::CONTENT_ENCODING, encoding),
+    None => builder,
+  }
+  .body(body)
+  .unwrap()
+}
+
+/// Serves content that was not compressed at build time, gzipped here when
+/// the request accepts it and the content type is worth compressing.
+async fn data_response(
+  path: &str,
+  data: Vec<u8>,
+  etag: &str,
+  cache: &str,
+  request: &AssetRequest,
+) -> Response<Body> {
+  if request.gzip && compressible(path) {
+    let gzip_etag = format!("{}-gz\"", etag.trim_end_matches('"'));
+    if request.not_modified(&gzip_etag) {
+      return encoded_response(path, Body::empty(), &gzip_etag, None, cache, request);
+    }
+    if let Ok(Ok(compressed)) = tokio::task::spawn_blocking(move || gzip(&data)).await {
+      return encoded_response(
+        path,
+        Body::from(compressed),
+        &gzip_etag,
+        Some("gzip"),
+        cache,
+        request,
+      );
+    }
+    error!("Asset {} could not be compressed", path);
+    return Response::builder()
+      .status(StatusCode::INTERNAL_SERVER_ERROR)
+      .body(Body::empty())
+      .unwrap();
+  }
+  encoded_response(path, Body::from(data), etag, None, cache, request)
+}
+
+fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+  let mut encoder = GzEncoder::new(vec![], Compression::fast());
+  encoder.write_all(data)?;
+  encoder.finish()
+}
+
+/// Whether a file type is text or otherwise shrinks when compressed, unlike
+/// images, fonts and audio that are compressed already.
+fn compressible(path: &str) -> bool {
+  let mime = mime_type(path);
+  mime.starts_with("text/")
+    || matches!(
+      mime,
+      "application/json" | "application/wasm" | "image/svg+xml"
+    )
+}
+
+/// Content type of a served file, from its extension.
+pub fn mime_type(path: &str) -> &'static str {
+  let extension = Path::new(path)
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(|e| e.to_ascii_lowercase());
+  match extension.as_deref() {
+    Some("html") | Some("htm") => "text/html",
+    Some("css") => "text/css",
+    Some("js") | Some("mjs") => "text/javascript",
+    Some("json") | Some("map") => "application/json",
+    Some("txt") => "text/plain",
+    Some("png") => "image/png",
+    Some("jpg") | Some("jpeg") => "image/jpeg",
+    Some("gif") => "image/gif",
+    Some("webp") => "image/webp",
+    Some("svg") => "image/svg+xml",
+    Some("ico") => "image/x-icon",
+    Some("woff") => "font/woff",
+    Some("woff2") => "font/woff2",
+    Some("ttf") => "font/ttf",
+    Some("otf") => "font/otf",
+    Some("mp3") => "audio/mpeg",
+    Some("ogg") => "audio/ogg",
+    Some("wav") => "audio/wav",
+    Some("wasm") => "application/wasm",
+    _ => "application/octet-stream",
+  }
+}
+
+/// Path of an asset inside a directory, refusing anything that could point
+/// outside of it.
+pub fn asset_path(dir: &Path, path: &str) -> Option<PathBuf> {
+  // Backslashes and drive letters are refused on every platform since they
+  // are separators and prefixes on Windows
+  if path.is_empty() || path.contains(['\\', ':']) {
+    return None;
+  }
+  let relative = Path::new(path);
+  match relative
+    .components()
+    .all(|c| matches!(c, Component::Normal(_)))
+  {
+    true => Some(dir.join(relative)),
+    false => None,
+  }
+}
+
+/// A user provided file, which can change at any time.
+pub struct DiskFile {
+  path: PathBuf,
+  etag: String,
+}
+
+/// Finds a file in a directory of user provided files, `None` when the
+/// directory does not have it. Directories and other non-files are treated as
+/// missing.
+pub async fn find_asset(dir: &Path, path: &str) -> Option<DiskFile> {
+  let file = asset_path(dir, path)?;
+  match tokio::fs::metadata(&file).await {
+    Ok(metadata) if metadata.is_file() => Some(DiskFile {
+      etag: format!("\"{}\"", file_tag(&metadata)),
+      path: file,
+    }),
+    Ok(_) => None,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+    Err(e) => {
+      error!("Asset {:?} could not be read: {}", file, e);
+      None
+    }
+  }
+}
+
+/// Serves a user provided file, checked against its ETag on every use. `None`
+/// when it could not be read.
+pub async fn file_response(
+  path: &str,
+  file: DiskFile,
+  request: &AssetRequest,
+) -> Option<Response<Body>> {
+  disk_response(path, file, CACHE_REVALIDATE, request).await
+}
+
+/// Serves a file from disk with an ETag from its modification time and
+/// length, which is only read when the client does not have it already.
+async fn disk_response(
+  path: &str,
+  file: DiskFile,
+  cache: &str,
+  request: &AssetRequest,
+) -> Option<Response<Body>> {
+  if request.not_modified(&file.etag) {
+    return Some(encoded_response(
+      path,
+      Body::empty(),
+      &file.etag,
+      None,
+      cache,
+      request,
+    ));
+  }
+  match tokio::fs::read(&file.path).await {
+    Ok(data) => Some(data_response(path, data, &file.etag, cache, request).await),
+    Err(e) => {
+      error!("Asset {:?} could not be read: {}", file.path, e);
+      None
+    }
+  }
+}
+
+/// Serves a hand tracker asset under `lib/`, from the asset directory when it
+/// has the file and from the embedded files otherwise. The asset version
+/// covers both, so either can be cached for good when requested with it.
+pub async fn serve_hand_asset(
+  path: &str,
+  dir: Option<&Path>,
+  request: &AssetRequest,
+) -> Result<Response<Body>, Infallible> {
+  if let Some(dir) = dir {
+    if let Some(file) = find_asset(dir, path).await {
+      let cache = match request.versioned {
+        true => CACHE_VERSIONED,
+        false => CACHE_REVALIDATE,
+      };
+      if let Some(response) = disk_response(path, file, cache, request).await {
+        return Ok(response);
+      }
+    }
+  }
+
+  match HAND_FILES.get(path) {
+    Some(file) => Ok(embedded_response(path, file, request).await),
+    None => {
+      error!("Hand asset {} not found", path);
+      Ok(
+        Response::builder()
+          .status(StatusCode::NOT_FOUND)
+          .body(Body::from("Not found"))
+          .unwrap(),
+      )
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn asset_request(if_none_match: Option<&str>) -> AssetRequest {
+    AssetRequest {
+      if_none_match: if_none_match.map(|v| v.to_string()),
+      gzip: false,
+      brotli: false,
+      version: ASSET_VERSION.into(),
+      versioned: false,
+    }
+  }
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("slidershim-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+  }
+
+  #[test]
+  fn accepts_encodings() {
+    assert!(accepts("gzip, deflate, br", "br"));
+    assert!(accepts("GZip;q=0.5", "gzip"));
+    assert!(accepts("br ; q=1.0", "br"));
+    assert!(!accepts("gzip;q=0, br", "gzip"));
+    assert!(!accepts("gzip;q=0.0", "gzip"));
+    assert!(!accepts("gzip", "br"));
+    assert!(!accepts("", "gzip"));
+  }
+
+  #[test]
+  fn matches_if_none_match() {
+    let etag = "\"abc\"";
+    assert!(!asset_request(None).not_modified(etag));
+    assert!(asset_request(Some("\"abc\"")).not_modified(etag));
+    assert!(asset_request(Some("\"x\", \"abc\"")).not_modified(etag));
+    assert!(asset_request(Some("W/\"abc\"")).not_modified(etag));
+    assert!(asset_request(Some("*")).not_modified(etag));
+    assert!(!asset_request(Some("\"x\",\"abcd\"")).not_modified(etag));
+    assert!(!asset_request(Some("abc")).not_modified(etag));
+  }
+
+  #[test]
+  fn caches_current_version_only() {
+    let version = ASSET_VERSION;
+    assert!(is_versioned(&format!("v={}", version), version));
+    assert!(is_versioned(&format!("a=1&v={}", version), version));
+    assert!(!is_versioned("v=0.10.10", version));
+    assert!(!is_versioned("v=", version));
+    assert!(!is_versioned(&format!("xv={}", version), version));
+    assert!(!is_versioned(&format!("v={}0", version), version));
+  }
+
+  #[test]
+  fn version_follows_asset_dir() {
+    let dir = temp_dir("version");
+    assert_eq!(&*AssetVersion::new(None).current(), ASSET_VERSION);
+    assert_eq!(
+      &*AssetVersion::new(Some(dir.clone())).current(),
+      ASSET_VERSION
+    );
+
+    // Only files under lib/ replace hand tracker files
+    std::fs::create_dir_all(dir.join("lib/models")).unwrap();
+    std::fs::write(dir.join("skin.css"), "body {}").unwrap();
+    assert_eq!(
+      &*AssetVersion::new(Some(dir.clone())).current(),
+      ASSET_VERSION
+    );
+
+    std::fs::write(dir.join("lib/models/a.task"), "a").unwrap();
+    let first = AssetVersion::new(Some(dir.clone())).current();
+    assert!(first.starts_with(ASSET_VERSION));
+    assert_ne!(&*first, ASSET_VERSION);
+
+    std::fs::write(dir.join("lib/models/a.task"), "ab").unwrap();
+    let second = AssetVersion::new(Some(dir.clone())).current();
+    assert_ne!(first, second);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn pages_request_served_version() {
+    let file = web_file("handtracker.html").unwrap();
+    let mut request = asset_request(None);
+    request.version = format!("{}-0123", ASSET_VERSION).into();
+    let response = embedded_response("handtracker.html", file, &request).await;
+    let etag = response.headers()[header::ETAG]
+      .to_str()
+      .unwrap()
+      .to_string();
+    assert_ne!(etag, file.etag);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains(&format!("?v={}-0123", ASSET_VERSION)));
+
+    request.if_none_match = Some(etag);
+    let response = embedded_response("handtracker.html", file, &request).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+  }
+
+  #[test]
+  fn pages_request_current_version() {
+    for path in ["handtracker.html", "mediapipe-hands-min.js"] {
+      let data = String::from_utf8_lossy(web_file(path).unwrap().data);
+      assert!(data.contains(&format!("?v={}", ASSET_VERSION)), "{}", path);
+      assert!(!data.contains("@ASSET_VERSION@"), "{}", path);
+    }
+  }
+
+  #[test]
+  fn asset_path_stays_in_dir() {
+    let dir = Path::new("assets");
+    assert_eq!(
+      asset_path(dir, "lib/models/a.task"),
+      Some(dir.join("lib/models/a.task"))
+    );
+    for path in [
+      "",
+      "..",
+      "lib/../../secret",
+      "./lib",
+      "/etc/passwd",
+      "C:/Windows/win.ini",
+      "C:win.ini",
+      "lib\\..\\..\\secret",
+      "\\\\server\\share",
+    ] {
+      assert_eq!(asset_path(dir, path), None, "{}", path);
+    }
+  }
+
+  #[tokio::test]
+  async fn reads_files_only() {
+    let dir = temp_dir("assets");
+    std::fs::create_dir_all(dir.join("css")).unwrap();
+    std::fs::write(dir.join("css/skin.css"), "body {}").unwrap();
+
+    let file = find_asset(&dir, "css/skin.css").await.unwrap();
+    let response = file_response("css/skin.css", file, &asset_request(None))
+      .await
+      .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"body {}");
+    assert!(find_asset(&dir, "css").await.is_none());
+    assert!(find_asset(&dir, "css/").await.is_none());
+    assert!(find_asset(&dir, "missing.css").await.is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn revalidates_files() {
+    let dir = temp_dir("etag");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("skin.css"), "body {}").unwrap();
+
+    let mut request = asset_request(None);
+    request.gzip = true;
+    let file = find_asset(&dir, "skin.css").await.unwrap();
+    let response = file_response("skin.css", file, &request).await.unwrap();
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    let etag = response.headers()[header::ETAG]
+      .to_str()
+      .unwrap()
+      .to_string();
+
+    request.if_none_match = Some(etag.clone());
+    let file = find_asset(&dir, "skin.css").await.unwrap();
+    let response = file_response("skin.css", file, &request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    std::fs::write(dir.join("skin.css"), "body { color: red }").unwrap();
+    let file = find_asset(&dir, "skin.css").await.unwrap();
+    let response = file_response("skin.css", file, &request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()[header::ETAG], etag.as_str());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,289 @@
+use log::{info, warn};
+use std::time::{Duration, Instant};
+
+use crate::{
+  device::{
+    brokenithm_protocol::InputFrame,
+    config::{BrokenithmMerge, BrokenithmSpec},
+  },
+  state::{SliderInput, SliderState},
+};
+
+/// Number of ground pads owned by one client with `BrokenithmMerge::Halves`.
+const HALF: usize = 16;
+
+struct ClientInput {
+  id: usize,
+  /// Region of the slider this client owns, assigned when it first sends
+  /// ground touches so that air-only hand trackers do not take one.
+  region: Option<usize>,
+  input: SliderInput,
+  /// Time of the latest message of any kind, heartbeats included.
+  last_frame: Instant,
+  /// Set once the input of this client has been cleared for being stale.
+  stale: bool,
+}
+
+/// Keeps a separate input buffer for every connected Brokenithm client and
+/// merges them into the shared slider state, so that clients do not overwrite
+/// or release each other's touches.
+pub struct ClientInputs {
+  state: SliderState,
+  spec: BrokenithmSpec,
+  merge: BrokenithmMerge,
+  next_id: usize,
+  clients: Vec<ClientInput>,
+  /// Fused input of all hand tracker clients, see `HandSources`.
+  hands: SliderInput,
+}
+
+impl ClientInputs {
+  pub fn new(state: &SliderState, spec: &BrokenithmSpec, merge: &BrokenithmMerge) -> Self {
+    Self {
+      state: state.clone(),
+      spec: spec.clone(),
+      merge: *merge,
+      next_id: 0,
+      clients: vec![],
+      hands: SliderInput::new(),
+    }
+  }
+
+  /// Registers a new client and returns its id.
+  pub fn connect(&mut self) -> usize {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.clients.push(ClientInput {
+      id,
+      region: None,
+      input: SliderInput::new(),
+      last_frame: Instant::now(),
+      stale: false,
+    });
+    id
+  }
+
+  /// Removes a client, releasing only the inputs it was holding.
+  pub fn disconnect(&mut self, id: usize) {
+    self.clients.retain(|c| c.id != id);
+    self.merge();
+  }
+
+  /// Records a message that carries no slider input, such as a heartbeat, so
+  /// that a client holding still is not taken for stale.
+  pub fn seen(&mut self, id: usize) {
+    if let Some(client) = self.clients.iter_mut().find(|c| c.id == id) {
+      client.last_frame = Instant::now();
+      client.stale = false;
+    }
+  }
+
+  /// Clears the input of clients that sent nothing for longer than `timeout`,
+  /// such as a tablet that went to sleep mid-touch, and returns how many were
+  /// cleared.
+  pub fn release_stale(&mut self, timeout: Duration) -> usize {
+    let now = Instant::now();
+    let mut released = 0;
+    for client in self.clients.iter_mut() {
+      let elapsed = now - client.last_frame;
+      if !client.stale && elapsed > timeout {
+        warn!(
+          "Brokenithm client {} input stale for {:?}, releasing",
+          client.id, elapsed
+        );
+        client.stale = true;
+        client.input.clear();
+        released += 1;
+      }
+    }
+    if released > 0 {
+      self.merge();
+    }
+    released
+  }
+
+  pub fn update(&mut self, id: usize, frame: &InputFrame) {
+    let hand_tracking = matches!(self.spec, BrokenithmSpec::HandTracking);
+    let regions = match self.merge {
+      BrokenithmMerge::Or => 1,
+      BrokenithmMerge::Halves => 32 / HALF,
+    };
+    let taken = self
+      .clients
+      .iter()
+      .filter_map(|c| c.region)
+      .collect::<Vec<usize>>();
+
+    let client = match self.clients.iter_mut().find(|c| c.id == id) {
+      Some(client) => client,
+      None => return,
+    };
+    client.last_frame = Instant::now();
+    client.stale = false;
+
+    if let Some(ground) = frame.ground {
+      // An idle client that only reports released pads takes no region
+      if client.region.is_none() && ground.iter().any(|x| *x > 0) {
+        client.region = (0..regions).find(|r| !taken.contains(r));
+        match client.region {
+          Some(region) => info!("Brokenithm client {} owns region {}", id, region),
+          None => info!("Brokenithm client {} has no free region", id),
+        }
+      }
+      client.input.ground = ground;
+    }
+    if let Some(air) = frame.air {
+      // Touch clients send air alongside ground, which the hand tracker owns.
+      if frame.ground.is_none() || !hand_tracking {
+        client.input.air = air;
+      }
+    }
+    if let Some(extra) = frame.extra {
+      client.input.extra = extra;
+    }
+
+    self.merge();
+  }
+
+  /// Sets the air and extra buttons held by the hand trackers.
+  pub fn update_hands(&mut self, air: &[u8; 6], extra: &[u8; 3]) {
+    self.hands.air = *air;
+    self.hands.extra = *extra;
+    self.merge();
+  }
+
+  fn merge(&self) {
+    let mut merged = SliderInput::new();
+    merged.air = self.hands.air;
+    merged.extra = self.hands.extra;
+
+    for client in self.clients.iter() {
+      let owned = match (self.merge, client.region) {
+        (BrokenithmMerge::Or, _) => 0..32,
+        (BrokenithmMerge::Halves, Some(region)) => (region * HALF)..((region + 1) * HALF),
+        (BrokenithmMerge::Halves, None) => 0..0,
+      };
+      for idx in owned {
+        merged.ground[idx] = merged.ground[idx].max(client.input.ground[idx]);
+      }
+      for (m, c) in merged.air.iter_mut().zip(client.input.air.iter()) {
+        *m = (*m).max(*c);
+      }
+      for (m, c) in merged.extra.iter_mut().zip(client.input.extra.iter()) {
+        *m = (*m).max(*c);
+      }
+    }
+
+    let mut input_handle = self.state.input.lock();
+    input_handle.ground = merged.ground;
+    input_handle.air = merged.air;
+    input_handle.extra = merged.extra;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ground(pads: &[usize]) -> [u8; 32] {
+    let mut ground = [0; 32];
+    for idx in pads {
+      ground[*idx] = 255;
+    }
+    ground
+  }
+
+  fn touch(pads: &[usize], air: [u8; 6]) -> InputFrame {
+    InputFrame {
+      ground: Some(ground(pads)),
+      air: Some(air),
+      ..InputFrame::default()
+    }
+  }
+
+  fn inputs(spec: BrokenithmSpec, merge: BrokenithmMerge) -> (SliderState, ClientInputs) {
+    let state = SliderState::new();
+    let clients = ClientInputs::new(&state, &spec, &merge);
+    (state, clients)
+  }
+
+  #[test]
+  fn or_merges_every_pad() {
+    let (state, mut clients) = inputs(BrokenithmSpec::Basic, BrokenithmMerge::Or);
+    let a = clients.connect();
+    let b = clients.connect();
+    clients.update(a, &touch(&[0, 20], [0; 6]));
+    clients.update(b, &touch(&[1, 31], [0, 255, 0, 0, 0, 0]));
+    assert_eq!(state.input.lock().ground, ground(&[0, 1, 20, 31]));
+    assert_eq!(state.input.lock().air, [0, 255, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn halves_assigns_regions_on_first_touch() {
+    let (state, mut clients) = inputs(BrokenithmSpec::Basic, BrokenithmMerge::Halves);
+    let idle = clients.connect();
+    let a = clients.connect();
+    let b = clients.connect();
+
+    // Idle clients keep reporting released pads without taking a region
+    clients.update(idle, &touch(&[], [0; 6]));
+    clients.update(a, &touch(&[0, 20], [0; 6]));
+    clients.update(b, &touch(&[1, 31], [0; 6]));
+    assert_eq!(state.input.lock().ground, ground(&[0, 31]));
+
+    // A third touching client has no region left until one disconnects
+    clients.update(idle, &touch(&[2, 17], [0; 6]));
+    assert_eq!(state.input.lock().ground, ground(&[0, 31]));
+    clients.disconnect(b);
+    clients.update(idle, &touch(&[2, 17], [0; 6]));
+    assert_eq!(state.input.lock().ground, ground(&[0, 17]));
+  }
+
+  #[test]
+  fn touch_does_not_override_hand_air() {
+    let (state, mut clients) = inputs(BrokenithmSpec::HandTracking, BrokenithmMerge::Or);
+    let tablet = clients.connect();
+    let tracker = clients.connect();
+    clients.update_hands(&[0, 0, 255, 0, 0, 0], &[0; 3]);
+    clients.update(tablet, &touch(&[3], [255; 6]));
+    clients.update(
+      tracker,
+      &InputFrame {
+        air: Some([0, 0, 0, 255, 0, 0]),
+        ..InputFrame::default()
+      },
+    );
+    assert_eq!(state.input.lock().ground, ground(&[3]));
+    assert_eq!(state.input.lock().air, [0, 0, 255, 255, 0, 0]);
+  }
+
+  #[test]
+  fn disconnect_releases_own_input() {
+    let (state, mut clients) = inputs(BrokenithmSpec::Basic, BrokenithmMerge::Or);
+    let a = clients.connect();
+    let b = clients.connect();
+    clients.update(a, &touch(&[4], [255, 0, 0, 0, 0, 0]));
+    clients.update(b, &touch(&[5], [0; 6]));
+    clients.disconnect(a);
+    assert_eq!(state.input.lock().ground, ground(&[5]));
+    assert_eq!(state.input.lock().air, [0; 6]);
+  }
+
+  #[test]
+  fn releases_stale_client_only() {
+    let (state, mut clients) = inputs(BrokenithmSpec::Basic, BrokenithmMerge::Or);
+    let asleep = clients.connect();
+    let active = clients.connect();
+    clients.update(asleep, &touch(&[6], [0; 6]));
+    clients.update(active, &touch(&[7], [0; 6]));
+    clients.clients[0].last_frame -= Duration::from_secs(2);
+
+    assert_eq!(clients.release_stale(Duration::from_secs(1)), 1);
+    assert_eq!(clients.release_stale(Duration::from_secs(1)), 0);
+    assert_eq!(state.input.lock().ground, ground(&[7]));
+
+    // Frames of the other clients do not bring the stale touch back
+    clients.update(active, &touch(&[8], [0; 6]));
+    assert_eq!(state.input.lock().ground, ground(&[8]));
+  }
+}
@@ -0,0 +1,139 @@
+use log::{error, info};
+use parking_lot::Mutex;
+use std::{sync::Arc, time::Instant};
+
+use crate::{
+  device::brokenithm_clients::ClientInputs,
+  handtracking::{
+    config::HandTrackingConfig,
+    frame::HandFrame,
+    fusion::HandFusion,
+    recorder::HandRecorder,
+    tracker::{HandOutput, HandTracker},
+  },
+  meta::meta::apply_action,
+  state::SliderState,
+  system::get_hand_recording_path,
+};
+
+/// Air input shared by all hand tracker clients of one server. Their frames
+/// are fused into one stream and run through a single tracker, whose output
+/// is merged with the other clients.
+pub struct HandSources {
+  state: SliderState,
+  clients: Arc<Mutex<ClientInputs>>,
+  start: Instant,
+  calibration: Arc<HandTrackingConfig>,
+  fusion: HandFusion,
+  tracker: HandTracker,
+  recorder: Option<HandRecorder>,
+}
+
+impl HandSources {
+  pub fn new(
+    state: &SliderState,
+    clients: &Arc<Mutex<ClientInputs>>,
+    calibration: &Arc<HandTrackingConfig>,
+    record: bool,
+  ) -> Self {
+    let recorder = match record {
+      true => {
+        get_hand_recording_path().and_then(|path| match HandRecorder::create(&path, calibration) {
+          Ok(recorder) => {
+            info!("Recording hand tracking to {:?}", path);
+            Some(recorder)
+          }
+          Err(e) => {
+            error!("Hand recording could not be created at {:?}: {}", path, e);
+            None
+          }
+        })
+      }
+      false => None,
+    };
+
+    Self {
+      state: state.clone(),
+      clients: clients.clone(),
+      start: Instant::now(),
+      calibration: calibration.clone(),
+      fusion: HandFusion::new(calibration),
+      tracker: HandTracker::new(&calibration.upright()),
+      recorder,
+    }
+  }
+
+  fn now(&self) -> f64 {
+    self.start.elapsed().as_secs_f64() * 1000.0
+  }
+
+  /// Adds a frame from a client with the current calibration and returns the
+  /// fused air string state.
+  pub fn update(
+    &mut self,
+    id: usize,
+    frame: &HandFrame,
+    calibration: &Arc<HandTrackingConfig>,
+  ) -> [u8; 6] {
+    if !Arc::ptr_eq(calibration, &self.calibration) {
+      // Sources are dropped, the next frame of each one adds it back
+      self.calibration = calibration.clone();
+      self.fusion = HandFusion::new(calibration);
+      self.tracker = HandTracker::new(&calibration.upright());
+      let now = self.now();
+      if let Some(recorder) = self.recorder.as_mut() {
+        recorder.calibrate(now, calibration);
+      }
+    }
+
+    let now = self.now();
+    if let Some(recorder) = self.recorder.as_mut() {
+      recorder.record(now, id, frame);
+    }
+
+    let count = self.fusion.source_count();
+    let fused = self.fusion.update(id, frame, now);
+    if self.fusion.source_count() != count {
+      info!(
+        "Hand tracking sources changed to {}",
+        self.fusion.source_count()
+      );
+    }
+    self.apply(&fused)
+  }
+
+  /// Removes a disconnected client.
+  pub fn remove(&mut self, id: usize) {
+    if let Some(fused) = self.fusion.remove(id, self.now()) {
+      info!(
+        "Hand tracking sources changed to {}",
+        self.fusion.source_count()
+      );
+      self.apply(&fused);
+    }
+  }
+
+  /// Drops clients that stopped sending frames.
+  pub fn expire(&mut self) {
+    if let Some(fused) = self.fusion.expire(self.now()) {
+      info!(
+        "Hand tracking sources changed to {}",
+        self.fusion.source_count()
+      );
+      self.apply(&fused);
+    }
+  }
+
+  fn apply(&mut self, fused: &HandFrame) -> [u8; 6] {
+    let HandOutput {
+      air,
+      extra,
+      actions,
+    } = self.tracker.update(fused);
+    self.clients.lock().update_hands(&air, &extra);
+    for action in actions {
+      apply_action(&self.state.control, action);
+    }
+    air
+  }
+}
@@ -0,0 +1,453 @@
+use hyper::{header, HeaderMap};
+use log::{error, info};
+use parking_lot::Mutex;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+  fs,
+  net::IpAddr,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::system::{get_paired_devices_path, list_ips};
+
+/// Cookie holding the token of a paired device.
+const TOKEN_COOKIE: &str = "slidershim_device";
+
+/// Paired devices as last read from or written to their file, read on first
+/// use so that checking a token does not touch the disk. Held while the list
+/// is changed and written back, so that two devices pairing at once do not
+/// drop one of them.
+static PAIRED_DEVICES: Mutex<Option<Vec<PairedDevice>>> = Mutex::new(None);
+
+/// How long the addresses of this machine are trusted before they are listed
+/// again, in case an adapter connected or got a new address.
+const OWN_IPS_REFRESH: Duration = Duration::from_secs(5);
+
+/// A device that entered the pairing PIN, persisted in the config folder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairedDevice {
+  /// Names the device in the UI, unlike the token it is not secret.
+  pub id: String,
+  token: String,
+  /// User agent of the browser that paired.
+  pub name: String,
+  /// Unix time of pairing in seconds.
+  pub paired: u64,
+}
+
+impl PairedDevice {
+  /// The device as shown in the UI, without its token.
+  pub fn to_serde_value(&self) -> Value {
+    serde_json::json!({
+      "id": self.id,
+      "name": self.name,
+      "paired": self.paired,
+    })
+  }
+}
+
+/// A new random pairing PIN of 6 digits.
+pub fn new_pin() -> u32 {
+  rand::thread_rng().gen_range(100_000..1_000_000)
+}
+
+fn random_string(len: usize) -> String {
+  rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(len)
+    .map(char::from)
+    .collect()
+}
+
+fn read_paired_devices() -> Vec<PairedDevice> {
+  let path = match get_paired_devices_path() {
+    Some(path) => path,
+    None => return vec![],
+  };
+  match fs::read_to_string(&path) {
+    Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+      error!("Paired devices in {:?} could not be read: {}", path, e);
+      vec![]
+    }),
+    Err(_) => vec![],
+  }
+}
+
+fn with_paired_devices<T>(f: impl FnOnce(&mut Vec<PairedDevice>) -> T) -> T {
+  let mut devices = PAIRED_DEVICES.lock();
+  f(devices.get_or_insert_with(read_paired_devices))
+}
+
+pub fn load_paired_devices() -> Vec<PairedDevice> {
+  with_paired_devices(|devices| devices.clone())
+}
+
+fn save_paired_devices(devices: &[PairedDevice]) -> bool {
+  let path = match get_paired_devices_path() {
+    Some(path) => path,
+    None => return false,
+  };
+  match fs::write(&path, serde_json::to_string_pretty(devices).unwrap()) {
+    Ok(_) => true,
+    Err(e) => {
+      error!("Paired devices could not be saved to {:?}: {}", path, e);
+      false
+    }
+  }
+}
+
+/// Pairs a new device, returning its token.
+pub fn pair_device(name: &str) -> Option<String> {
+  let device = PairedDevice {
+    id: random_string(8),
+    token: random_string(32),
+    name: name.chars().take(120).collect(),
+    paired: SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |d| d.as_secs()),
+  };
+
+  with_paired_devices(|devices| {
+    let mut changed = devices.clone();
+    changed.push(device.clone());
+    match save_paired_devices(&changed) {
+      true => {
+        info!("Paired device {} ({})", device.id, device.name);
+        *devices = changed;
+        Some(device.token)
+      }
+      false => None,
+    }
+  })
+}
+
+/// Forgets a paired device, whose token then stops working.
+pub fn revoke_paired_device(id: &str) -> bool {
+  with_paired_devices(|devices| {
+    let mut changed = devices.clone();
+    changed.retain(|d| d.id != id);
+    if changed.len() == devices.len() || !save_paired_devices(&changed) {
+      return false;
+    }
+    info!("Revoked paired device {}", id);
+    *devices = changed;
+    true
+  })
+}
+
+/// Compares without returning early, so that the time taken does not tell how
+/// much of a guessed token was right.
+fn tokens_equal(a: &str, b: &str) -> bool {
+  a.len() == b.len()
+    && a
+      .bytes()
+      .zip(b.bytes())
+      .fold(0, |acc, (x, y)| acc | (x ^ y))
+      == 0
+}
+
+pub fn is_paired(token: &str) -> bool {
+  with_paired_devices(|devices| devices.iter().any(|d| tokens_equal(&d.token, token)))
+}
+
+/// Token sent by a device in its cookie.
+pub fn device_token(headers: &HeaderMap) -> Option<String> {
+  headers
+    .get_all(header::COOKIE)
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(';'))
+    .filter_map(|c| c.trim().split_once('='))
+    .find(|(name, _)| *name == TOKEN_COOKIE)
+    .map(|(_, token)| token.to_string())
+}
+
+/// `Set-Cookie` value that keeps a device token in the browser.
+pub fn token_cookie(token: &str) -> String {
+  format!(
+    "{}={}; Path=/; Max-Age=315360000; HttpOnly; SameSite=Strict",
+    TOKEN_COOKIE, token
+  )
+}
+
+/// Reads a list of origins such as `https://slider.example.com`, comma
+/// separated or as an array, lowercased and without a trailing slash.
+pub fn origin_list(v: &Value) -> Vec<String> {
+  let entries: Vec<&str> = match v {
+    Value::String(s) => s.split(',').collect(),
+    Value::Array(a) => a.iter().filter_map(|e| e.as_str()).collect(),
+    _ => vec![],
+  };
+  entries
+    .into_iter()
+    .map(|e| e.trim().trim_end_matches('/').to_ascii_lowercase())
+    .filter(|e| !e.is_empty())
+    .filter(|e| match e.split_once("://") {
+      Some((_, host)) if !host.is_empty() && !host.contains('/') => true,
+      _ => {
+        error!("Invalid origin {}", e);
+        false
+      }
+    })
+    .collect()
+}
+
+/// Where the server can be reached, which pages that post or open websockets
+/// must be served from.
+#[derive(Debug, Default)]
+pub struct ServerOrigins {
+  /// Bound plain HTTP port.
+  http_port: Option<u16>,
+  /// Bound HTTPS port.
+  https_port: Option<u16>,
+  /// Name advertised over mDNS, without `.local`.
+  mdns_name: Option<String>,
+  /// Origins of pages served by something else in front of this server, such
+  /// as a tunnel or reverse proxy, from `origin_list`.
+  allowed: Vec<String>,
+  /// Addresses of this machine and when they were listed.
+  own_ips: Mutex<Option<(Instant, Vec<IpAddr>)>>,
+}
+
+impl ServerOrigins {
+  pub fn new(
+    http_port: Option<u16>,
+    https_port: Option<u16>,
+    mdns_name: Option<String>,
+    allowed: Vec<String>,
+  ) -> Self {
+    Self {
+      http_port,
+      https_port,
+      mdns_name,
+      allowed,
+      own_ips: Mutex::new(None),
+    }
+  }
+
+  fn own_ip(&self, ip: IpAddr) -> bool {
+    let mut own_ips = self.own_ips.lock();
+    let ips = match own_ips.as_ref() {
+      Some((listed, ips)) if listed.elapsed() < OWN_IPS_REFRESH => ips,
+      _ => {
+        let ips = list_ips()
+          .unwrap_or_default()
+          .iter()
+          .filter_map(|own| own.parse().ok())
+          .map(canonical)
+          .collect();
+        &own_ips.insert((Instant::now(), ips)).1
+      }
+    };
+    ips.contains(&ip)
+  }
+
+  /// Whether a host name is this machine: loopback, `localhost`, the mDNS
+  /// name or one of its addresses.
+  fn own_host(&self, host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    if host == "localhost" {
+      return true;
+    }
+    if let Some(name) = host.strip_suffix(".local") {
+      return self
+        .mdns_name
+        .as_ref()
+        .is_some_and(|mdns_name| name.eq_ignore_ascii_case(mdns_name));
+    }
+    let ip = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+      Ok(ip) => canonical(ip),
+      Err(_) => return false,
+    };
+    ip.is_loopback() || self.own_ip(ip)
+  }
+
+  /// Whether `scheme://authority` is a page of this server.
+  fn own_origin(&self, scheme: &str, authority: &str) -> bool {
+    let (host, port) = match split_authority(authority) {
+      Some(parts) => parts,
+      None => return false,
+    };
+    let bound = match scheme {
+      "http" => self.http_port.map(|p| (p, 80)),
+      "https" => self.https_port.map(|p| (p, 443)),
+      _ => None,
+    };
+    match bound {
+      Some((bound, default)) => port.unwrap_or(default) == bound && self.own_host(&host),
+      None => false,
+    }
+  }
+}
+
+fn canonical(ip: IpAddr) -> IpAddr {
+  match ip {
+    IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+    _ => ip,
+  }
+}
+
+/// Splits `host[:port]` into the lowercase host and the port, keeping the
+/// brackets of an IPv6 address.
+fn split_authority(authority: &str) -> Option<(String, Option<u16>)> {
+  let authority = authority.to_ascii_lowercase();
+  let (host, port) = match authority.rfind(':') {
+    Some(idx) if !authority[idx..].contains(']') => {
+      (&authority[..idx], Some(authority[idx + 1..].parse().ok()?))
+    }
+    _ => (&authority[..], None),
+  };
+  match host.is_empty() {
+    true => None,
+    false => Some((host.to_string(), port)),
+  }
+}
+
+/// Browsers send the origin of the page with websocket upgrades and posts.
+/// Only pages served by this server or from an allowed origin are allowed,
+/// other clients do not send an origin. Both the origin and the host must
+/// name this server, as a page of another site can point its own name at this
+/// server's address. Allowed origins are trusted whatever the host, which a
+/// tunnel may rewrite.
+pub fn same_origin(headers: &HeaderMap, origins: &ServerOrigins) -> bool {
+  let origin = match headers.get(header::ORIGIN) {
+    Some(origin) => origin.to_str().unwrap_or(""),
+    None => return true,
+  };
+  if origins
+    .allowed
+    .iter()
+    .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+  {
+    return true;
+  }
+  let host = headers
+    .get(header::HOST)
+    .and_then(|h| h.to_str().ok())
+    .unwrap_or("");
+
+  let (scheme, authority) = match origin.split_once("://") {
+    Some(parts) => parts,
+    None => return false,
+  };
+  authority.eq_ignore_ascii_case(host)
+    && origins.own_origin(&scheme.to_ascii_lowercase(), authority)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use hyper::header::HeaderValue;
+
+  fn headers(entries: &[(header::HeaderName, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in entries {
+      headers.append(name, HeaderValue::from_str(value).unwrap());
+    }
+    headers
+  }
+
+  #[test]
+  fn reads_token_cookie() {
+    let token = |cookies: &[&str]| {
+      device_token(&headers(
+        &cookies
+          .iter()
+          .map(|c| (header::COOKIE, *c))
+          .collect::<Vec<_>>(),
+      ))
+    };
+    assert_eq!(token(&[]), None);
+    assert_eq!(
+      token(&["theme=dark; slidershim_device=abc; lang=en"]),
+      Some("abc".to_string())
+    );
+    assert_eq!(
+      token(&["theme=dark", "slidershim_device=abc"]),
+      Some("abc".to_string())
+    );
+    assert_eq!(
+      token(&["flag; slidershim_device; slidershim_device=x=y"]),
+      Some("x=y".to_string())
+    );
+    assert_eq!(token(&["slidershim_devices=abc"]), None);
+  }
+
+  #[test]
+  fn compares_tokens() {
+    assert!(tokens_equal("abc", "abc"));
+    assert!(tokens_equal("", ""));
+    assert!(!tokens_equal("abc", "abd"));
+    assert!(!tokens_equal("abc", "abcd"));
+    assert!(!tokens_equal("abc", "ABC"));
+  }
+
+  #[test]
+  fn allows_own_origins_only() {
+    let origins = ServerOrigins::new(
+      Some(1606),
+      Some(1607),
+      Some("slidershim".to_string()),
+      vec![],
+    );
+    let allowed = |origin: &str, host: &str| {
+      same_origin(
+        &headers(&[(header::ORIGIN, origin), (header::HOST, host)]),
+        &origins,
+      )
+    };
+
+    assert!(same_origin(&HeaderMap::new(), &origins));
+    assert!(allowed("http://127.0.0.1:1606", "127.0.0.1:1606"));
+    assert!(allowed("http://localhost:1606", "LOCALHOST:1606"));
+    assert!(allowed(
+      "HTTP://Slidershim.local:1606",
+      "slidershim.local:1606"
+    ));
+    assert!(allowed("https://[::1]:1607", "[::1]:1607"));
+
+    // Wrong scheme or port for the page
+    assert!(!allowed("https://127.0.0.1:1606", "127.0.0.1:1606"));
+    assert!(!allowed("http://127.0.0.1:1607", "127.0.0.1:1607"));
+    assert!(!allowed("http://127.0.0.1", "127.0.0.1"));
+    assert!(!allowed("http://127.0.0.1:1606", "127.0.0.1:1607"));
+    // Other sites, even when their name points at this server
+    assert!(!allowed("http://evil.example:1606", "evil.example:1606"));
+    assert!(!allowed("http://other.local:1606", "other.local:1606"));
+    assert!(!allowed("http://203.0.113.5:1606", "203.0.113.5:1606"));
+    assert!(!allowed("null", "127.0.0.1:1606"));
+    assert!(!allowed("http://127.0.0.1:1606", ""));
+    assert!(!same_origin(
+      &headers(&[(header::ORIGIN, "http://127.0.0.1:1606")]),
+      &origins
+    ));
+  }
+
+  #[test]
+  fn allows_tunnelled_origins() {
+    let allowed = origin_list(&serde_json::json!(
+      " https://Slider.example.com/, http://, 203.0.113.5, https://a.example/x"
+    ));
+    assert_eq!(allowed, vec!["https://slider.example.com"]);
+    let origins = ServerOrigins::new(Some(1606), None, None, allowed);
+    let allowed = |origin: &str, host: &str| {
+      same_origin(
+        &headers(&[(header::ORIGIN, origin), (header::HOST, host)]),
+        &origins,
+      )
+    };
+
+    // The tunnel may pass its own name on as the host or rewrite it
+    assert!(allowed("https://slider.example.com", "slider.example.com"));
+    assert!(allowed("https://slider.example.com", "127.0.0.1:1606"));
+    assert!(allowed("http://127.0.0.1:1606", "127.0.0.1:1606"));
+    assert!(!allowed("http://slider.example.com", "slider.example.com"));
+    assert!(!allowed(
+      "https://slider.example.com.evil.example",
+      "slider.example.com"
+    ));
+    assert!(!allowed("https://other.example.com", "slider.example.com"));
+  }
+}
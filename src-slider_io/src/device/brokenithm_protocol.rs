@@ -0,0 +1,420 @@
+use hyper::{header::HeaderValue, http::HeaderMap};
+use std::time::Instant;
+
+use crate::handtracking::frame::HandFrame;
+
+/// Websocket subprotocol name of the binary input format.
+pub const PROTOCOL_V1: &str = "brokenithm.v1";
+
+const V1_LEN: usize = 39;
+
+/// Input format spoken by a connected client, negotiated from the
+/// `Sec-WebSocket-Protocol` header at connect time. Clients that do not ask
+/// for a subprotocol use the original text format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientProtocol {
+  Text,
+  BinaryV1,
+}
+
+impl ClientProtocol {
+  /// Newest protocol, which the web controller is told to ask for.
+  pub const LATEST: Self = ClientProtocol::BinaryV1;
+
+  pub fn negotiate(headers: &HeaderMap<HeaderValue>) -> Self {
+    let offered = headers
+      .get_all("sec-websocket-protocol")
+      .iter()
+      .filter_map(|v| v.to_str().ok())
+      .flat_map(|v| v.split(','))
+      .any(|p| p.trim() == PROTOCOL_V1);
+
+    match offered {
+      true => ClientProtocol::BinaryV1,
+      false => ClientProtocol::Text,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      ClientProtocol::Text => "text",
+      ClientProtocol::BinaryV1 => PROTOCOL_V1,
+    }
+  }
+
+  /// Version of the protocol, 0 for the original text format.
+  pub fn version(&self) -> u64 {
+    match self {
+      ClientProtocol::Text => 0,
+      ClientProtocol::BinaryV1 => 1,
+    }
+  }
+
+  /// Subprotocol to echo back in the upgrade response, if any.
+  pub fn header(&self) -> Option<&'static str> {
+    match self {
+      ClientProtocol::Text => None,
+      ClientProtocol::BinaryV1 => Some(PROTOCOL_V1),
+    }
+  }
+}
+
+/// One input update from a client. Fields that the message does not carry
+/// are left as `None`.
+#[derive(Debug, Clone, Default)]
+pub struct InputFrame {
+  pub seq: Option<u32>,
+  pub ground: Option<[u8; 32]>,
+  pub air: Option<[u8; 6]>,
+  pub extra: Option<[u8; 3]>,
+}
+
+/// A heartbeat, which newer clients time with their own clock.
+#[derive(Debug, Clone, Default)]
+pub struct Heartbeat {
+  /// Client clock reading, echoed back so that the client can time the round
+  /// trip.
+  pub sent: Option<String>,
+  /// Round trip of the previous heartbeat as timed by the client, in ms. It
+  /// is only shown, the latency stats come from `PingTimer`.
+  pub rtt: Option<f64>,
+}
+
+impl Heartbeat {
+  pub fn reply(&self) -> String {
+    match &self.sent {
+      Some(sent) => format!("alive{}", sent),
+      None => "alive".to_string(),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum ClientMessage {
+  Alive(Heartbeat),
+  Input(InputFrame),
+  /// Raw hand positions from the hand tracker.
+  Hands(HandFrame),
+  /// Well formed but not meant for us.
+  Ignored,
+  /// Malformed, the connection should be dropped.
+  Invalid,
+}
+
+fn flag(c: &char) -> u8 {
+  match *c == '1' {
+    false => 0,
+    true => 1,
+  }
+}
+
+/// Parses `alive?`, optionally followed by the client time and the round trip
+/// of the previous heartbeat in ms, as in `alive?1234.5,8.2`.
+fn parse_heartbeat(timing: &str) -> ClientMessage {
+  if timing.is_empty() {
+    return ClientMessage::Alive(Heartbeat::default());
+  }
+  let number = |s: &str| s.parse::<f64>().ok().filter(|n| n.is_finite());
+  let (sent, rtt) = match timing.split_once(',') {
+    Some((sent, rtt)) => (sent, Some(rtt)),
+    None => (timing, None),
+  };
+  if sent.len() > 24 || number(sent).is_none() {
+    return ClientMessage::Ignored;
+  }
+
+  ClientMessage::Alive(Heartbeat {
+    sent: Some(sent.to_string()),
+    rtt: rtt
+      .and_then(number)
+      .filter(|rtt| (0.0..60_000.0).contains(rtt)),
+  })
+}
+
+/// Parses the text format: `alive?` with optional timing, `b` + 32 ground + 6
+/// air flags, `d` + 6 air flags from older hand trackers, or `h` + a JSON
+/// `HandFrame`.
+pub fn parse_text(msg: &str) -> ClientMessage {
+  if let Some(timing) = msg.strip_prefix("alive?") {
+    return parse_heartbeat(timing);
+  }
+  if let Some(json) = msg.strip_prefix('h') {
+    return match HandFrame::from_json(json) {
+      Some(frame) => ClientMessage::Hands(frame),
+      None => ClientMessage::Invalid,
+    };
+  }
+
+  let chars = msg.chars().collect::<Vec<char>>();
+
+  match (chars.len(), chars.first()) {
+    (7, Some('d')) => {
+      let mut air = [0; 6];
+      for (idx, c) in chars[1..7].iter().enumerate() {
+        air[idx] = flag(c);
+      }
+      ClientMessage::Input(InputFrame {
+        air: Some(air),
+        ..Default::default()
+      })
+    }
+    (39, Some('b')) => {
+      let mut ground = [0; 32];
+      let mut air = [0; 6];
+      for (idx, c) in chars[1..33].iter().enumerate() {
+        ground[idx] = flag(c) * 255;
+      }
+      for (idx, c) in chars[33..39].iter().enumerate() {
+        air[idx] = flag(c);
+      }
+      ClientMessage::Input(InputFrame {
+        ground: Some(ground),
+        air: Some(air),
+        ..Default::default()
+      })
+    }
+    (6, _) | (7, _) | (39, _) => ClientMessage::Ignored,
+    _ => ClientMessage::Invalid,
+  }
+}
+
+/// Parses a binary v1 frame.
+///
+/// | Offset | Size | Field                                 |
+/// | ------ | ---- | ------------------------------------- |
+/// | 0      | 1    | Version, always 1                     |
+/// | 1      | 4    | Sequence number, little endian        |
+/// | 5      | 32   | Ground pressure 0-255, in input order |
+/// | 37     | 1    | Air bitmask, bit n is air n           |
+/// | 38     | 1    | Extra bitmask, bit n is extra n       |
+pub fn parse_binary(msg: &[u8]) -> ClientMessage {
+  if msg.len() != V1_LEN || msg[0] != 1 {
+    return ClientMessage::Invalid;
+  }
+
+  let seq = u32::from_le_bytes([msg[1], msg[2], msg[3], msg[4]]);
+  let mut ground = [0; 32];
+  ground.copy_from_slice(&msg[5..37]);
+  let mut air = [0; 6];
+  for (idx, a) in air.iter_mut().enumerate() {
+    *a = (msg[37] >> idx) & 1;
+  }
+  let mut extra = [0; 3];
+  for (idx, e) in extra.iter_mut().enumerate() {
+    *e = (msg[38] >> idx) & 1;
+  }
+
+  ClientMessage::Input(InputFrame {
+    seq: Some(seq),
+    ground: Some(ground),
+    air: Some(air),
+    extra: Some(extra),
+  })
+}
+
+/// Drops frames that arrive out of order, allowing the sequence number to
+/// wrap around.
+pub struct SequenceFilter {
+  last: Option<u32>,
+}
+
+impl SequenceFilter {
+  pub fn new() -> Self {
+    Self { last: None }
+  }
+
+  pub fn accept(&mut self, seq: Option<u32>) -> bool {
+    let seq = match seq {
+      Some(seq) => seq,
+      None => return true,
+    };
+
+    match self.last {
+      Some(last) if seq.wrapping_sub(last) == 0 || seq.wrapping_sub(last) > u32::MAX / 2 => false,
+      _ => {
+        self.last = Some(seq);
+        true
+      }
+    }
+  }
+}
+
+/// Times websocket pings sent along with each heartbeat reply. Browsers
+/// answer pings themselves, and a client cannot answer one it has not seen
+/// with a payload it cannot guess, so the round trip is the server's own
+/// measure.
+#[derive(Default)]
+pub struct PingTimer {
+  pending: Option<([u8; 8], Instant)>,
+}
+
+impl PingTimer {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Payload of a new ping, timed from now. A ping still unanswered is no
+  /// longer timed.
+  pub fn ping(&mut self) -> Vec<u8> {
+    let payload = rand::random::<u64>().to_le_bytes();
+    self.pending = Some((payload, Instant::now()));
+    payload.to_vec()
+  }
+
+  /// Round trip in ms when a pong answers the pending ping.
+  pub fn pong(&mut self, payload: &[u8]) -> Option<f64> {
+    match self.pending {
+      Some((pending, sent)) if payload == pending => {
+        self.pending = None;
+        Some(sent.elapsed().as_secs_f64() * 1000.0)
+      }
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::handtracking::frame::Handedness;
+
+  #[test]
+  fn times_answered_pings_only() {
+    let mut pings = PingTimer::new();
+    assert_eq!(pings.pong(&[0; 8]), None);
+
+    let first = pings.ping();
+    let second = pings.ping();
+    assert_eq!(pings.pong(&first), None);
+    assert_eq!(pings.pong(&[]), None);
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    let rtt = pings.pong(&second).unwrap();
+    assert!((5.0..1000.0).contains(&rtt), "{}", rtt);
+    assert_eq!(pings.pong(&second), None);
+  }
+
+  fn input(msg: ClientMessage) -> InputFrame {
+    match msg {
+      ClientMessage::Input(frame) => frame,
+      other => panic!("expected input, got {:?}", other),
+    }
+  }
+
+  fn binary(seq: u32, air: u8, extra: u8) -> Vec<u8> {
+    let mut msg = vec![1];
+    msg.extend(seq.to_le_bytes());
+    msg.extend((0..32).map(|x| x * 8));
+    msg.push(air);
+    msg.push(extra);
+    msg
+  }
+
+  #[test]
+  fn binary_unpacks_fields() {
+    let frame = input(parse_binary(&binary(0x01020304, 0b100101, 0b110)));
+    assert_eq!(frame.seq, Some(0x01020304));
+    assert_eq!(frame.ground.unwrap()[0], 0);
+    assert_eq!(frame.ground.unwrap()[31], 248);
+    assert_eq!(frame.air, Some([1, 0, 1, 0, 0, 1]));
+    assert_eq!(frame.extra, Some([0, 1, 1]));
+  }
+
+  #[test]
+  fn binary_rejects_length_and_version() {
+    let msg = binary(0, 0, 0);
+    assert!(matches!(parse_binary(&msg[..38]), ClientMessage::Invalid));
+    assert!(matches!(
+      parse_binary(&[msg.clone(), vec![0]].concat()),
+      ClientMessage::Invalid
+    ));
+    assert!(matches!(parse_binary(&[]), ClientMessage::Invalid));
+    let mut v2 = msg;
+    v2[0] = 2;
+    assert!(matches!(parse_binary(&v2), ClientMessage::Invalid));
+  }
+
+  #[test]
+  fn sequence_wraps_and_drops_reordered() {
+    let mut filter = SequenceFilter::new();
+    assert!(filter.accept(Some(u32::MAX - 1)));
+    assert!(filter.accept(Some(u32::MAX)));
+    assert!(filter.accept(Some(0)));
+    assert!(!filter.accept(Some(0)));
+    assert!(!filter.accept(Some(u32::MAX)));
+    assert!(filter.accept(Some(5)));
+    assert!(!filter.accept(Some(3)));
+    assert!(filter.accept(Some(1_000_000)));
+    // Frames without a sequence number are always taken
+    assert!(filter.accept(None));
+    assert!(!filter.accept(Some(999_999)));
+  }
+
+  #[test]
+  fn negotiates_subprotocol() {
+    let negotiate = |values: &[&str]| {
+      let mut headers = HeaderMap::new();
+      for v in values {
+        headers.append("sec-websocket-protocol", HeaderValue::from_str(v).unwrap());
+      }
+      ClientProtocol::negotiate(&headers)
+    };
+    assert_eq!(negotiate(&[]), ClientProtocol::Text);
+    assert_eq!(negotiate(&["brokenithm.v1"]), ClientProtocol::BinaryV1);
+    assert_eq!(
+      negotiate(&["chat, brokenithm.v1"]),
+      ClientProtocol::BinaryV1
+    );
+    assert_eq!(
+      negotiate(&["chat", "brokenithm.v1"]),
+      ClientProtocol::BinaryV1
+    );
+    assert_eq!(negotiate(&["brokenithm.v2, chat"]), ClientProtocol::Text);
+    assert_eq!(ClientProtocol::BinaryV1.header(), Some(PROTOCOL_V1));
+    assert_eq!(ClientProtocol::Text.header(), None);
+  }
+
+  #[test]
+  fn parses_text_frames() {
+    let frame = input(parse_text(&format!("b{}{}", "10".repeat(16), "011000")));
+    assert_eq!(frame.ground.unwrap()[0], 255);
+    assert_eq!(frame.ground.unwrap()[1], 0);
+    assert_eq!(frame.ground.unwrap()[30], 255);
+    assert_eq!(frame.air, Some([0, 1, 1, 0, 0, 0]));
+    assert_eq!(frame.extra, None);
+
+    let frame = input(parse_text("d100001"));
+    assert_eq!(frame.ground, None);
+    assert_eq!(frame.air, Some([1, 0, 0, 0, 0, 1]));
+
+    match parse_text(r#"h{"t":12.5,"hands":[{"x":0.5,"y":0.25,"hand":"Left"}]}"#) {
+      ClientMessage::Hands(frame) => {
+        assert_eq!(frame.t, 12.5);
+        assert_eq!(frame.hands[0].hand, Some(Handedness::Left));
+        assert_eq!(frame.hands[0].score, 1.0);
+      }
+      other => panic!("expected hands, got {:?}", other),
+    }
+    assert!(matches!(parse_text("h{"), ClientMessage::Invalid));
+    assert!(matches!(parse_text("x123456"), ClientMessage::Ignored));
+    assert!(matches!(parse_text("b100"), ClientMessage::Invalid));
+  }
+
+  #[test]
+  fn parses_heartbeats() {
+    match parse_text("alive?1234.5,8.25") {
+      ClientMessage::Alive(heartbeat) => {
+        assert_eq!(heartbeat.rtt, Some(8.25));
+        assert_eq!(heartbeat.reply(), "alive1234.5");
+      }
+      other => panic!("expected heartbeat, got {:?}", other),
+    }
+    match parse_text("alive?") {
+      ClientMessage::Alive(heartbeat) => assert_eq!(heartbeat.reply(), "alive"),
+      other => panic!("expected heartbeat, got {:?}", other),
+    }
+    assert!(matches!(
+      parse_text("alive?<script>"),
+      ClientMessage::Ignored
+    ));
+  }
+}
@@ -0,0 +1,335 @@
+use log::{info, warn};
+use serde_json::Value;
+use std::{
+  collections::VecDeque,
+  net::SocketAddr,
+  time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::device::brokenithm_protocol::ClientProtocol;
+
+/// Messages are counted over windows of this long to get a rate, in seconds.
+const RATE_WINDOW: f64 = 1.0;
+
+/// Round trips kept per client, about 2 minutes of heartbeats.
+const LATENCY_SAMPLES: usize = 120;
+
+/// Round trips needed before warning about a slow client.
+const LATENCY_MIN_SAMPLES: usize = 10;
+
+/// Upper bounds of the latency histogram buckets in ms, followed by one for
+/// everything slower.
+const LATENCY_BUCKETS: [f64; 9] = [5.0, 10.0, 20.0, 30.0, 50.0, 75.0, 100.0, 200.0, 500.0];
+
+/// Round trips of the latest heartbeats of a client, in ms.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+  samples: VecDeque<f64>,
+  /// Smoothed difference between consecutive round trips, as in RFC 3550.
+  jitter: f64,
+}
+
+impl LatencyStats {
+  pub fn add(&mut self, rtt: f64) {
+    if let Some(last) = self.samples.back() {
+      self.jitter += ((rtt - last).abs() - self.jitter) / 16.0;
+    }
+    if self.samples.len() == LATENCY_SAMPLES {
+      self.samples.pop_front();
+    }
+    self.samples.push_back(rtt);
+  }
+
+  pub fn len(&self) -> usize {
+    self.samples.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.samples.is_empty()
+  }
+
+  /// Nearest rank percentile, `p` from 0 to 100.
+  pub fn percentile(&self, p: f64) -> Option<f64> {
+    let mut sorted = self.samples.iter().copied().collect::<Vec<f64>>();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+  }
+
+  fn histogram(&self) -> Vec<usize> {
+    let mut counts = vec![0; LATENCY_BUCKETS.len() + 1];
+    for rtt in self.samples.iter() {
+      let bucket = LATENCY_BUCKETS
+        .iter()
+        .position(|bound| rtt <= bound)
+        .unwrap_or(LATENCY_BUCKETS.len());
+      counts[bucket] += 1;
+    }
+    counts
+  }
+
+  pub fn to_serde_value(&self) -> Value {
+    let round = |ms: Option<f64>| ms.map(|ms| (ms * 10.0).round() / 10.0);
+    let buckets = self
+      .histogram()
+      .into_iter()
+      .enumerate()
+      .map(|(idx, count)| serde_json::json!({ "le": LATENCY_BUCKETS.get(idx), "count": count }))
+      .collect::<Vec<Value>>();
+
+    serde_json::json!({
+      "samples": self.samples.len(),
+      "last": round(self.samples.back().copied()),
+      "p50": round(self.percentile(50.0)),
+      "p95": round(self.percentile(95.0)),
+      "p99": round(self.percentile(99.0)),
+      "jitter": round((self.samples.len() > 1).then_some(self.jitter)),
+      "histogram": buckets,
+    })
+  }
+}
+
+/// What a client sends, told apart by its messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientKind {
+  /// Has not sent any input yet.
+  Unknown,
+  Touch,
+  HandTracker,
+}
+
+impl ClientKind {
+  fn name(&self) -> &'static str {
+    match self {
+      ClientKind::Unknown => "unknown",
+      ClientKind::Touch => "touch",
+      ClientKind::HandTracker => "handTracker",
+    }
+  }
+}
+
+/// A connected websocket client as shown by the status API and the UI.
+pub struct ClientStatus {
+  id: usize,
+  addr: SocketAddr,
+  user_agent: String,
+  protocol: ClientProtocol,
+  kind: ClientKind,
+  /// Unix time of connecting in seconds.
+  connected: u64,
+  messages: u64,
+  window_start: Instant,
+  window_messages: u64,
+  /// Messages per second over the last full window.
+  rate: f64,
+  last_heartbeat: Option<Instant>,
+  /// Round trip the client reports for its own heartbeats, only shown.
+  client_rtt: Option<f64>,
+  /// Round trips of the server's pings.
+  latency: LatencyStats,
+  /// 95th percentile round trip above which the client is reported as slow,
+  /// in ms. 0 turns the warning off.
+  latency_warn_ms: f64,
+  latency_warning: bool,
+}
+
+impl ClientStatus {
+  fn add_rtt(&mut self, rtt: f64) {
+    self.latency.add(rtt);
+    if self.latency_warn_ms <= 0.0 || self.latency.len() < LATENCY_MIN_SAMPLES {
+      return;
+    }
+    let p95 = self.latency.percentile(95.0).unwrap_or(0.0);
+    match (p95 > self.latency_warn_ms, self.latency_warning) {
+      (true, false) => warn!(
+        "Brokenithm client {} is slow, 95% of round trips within {:.1} ms",
+        self.addr, p95
+      ),
+      (false, true) => info!("Brokenithm client {} is no longer slow", self.addr),
+      _ => {}
+    }
+    self.latency_warning = p95 > self.latency_warn_ms;
+  }
+
+  fn rate(&self, now: Instant) -> f64 {
+    // A client that stops sending decays to 0 instead of keeping its last rate
+    let elapsed = (now - self.window_start).as_secs_f64();
+    match elapsed >= 2.0 * RATE_WINDOW {
+      true => self.window_messages as f64 / elapsed,
+      false => self.rate,
+    }
+  }
+
+  pub fn to_serde_value(&self, now: Instant) -> Value {
+    serde_json::json!({
+      "id": self.id,
+      "address": self.addr.to_string(),
+      "userAgent": self.user_agent,
+      "kind": self.kind.name(),
+      "protocol": self.protocol.name(),
+      "protocolVersion": self.protocol.version(),
+      "connected": self.connected,
+      "messages": self.messages,
+      "messageRate": (self.rate(now) * 10.0).round() / 10.0,
+      "lastHeartbeatMs": self
+        .last_heartbeat
+        .map(|t| (now - t).as_millis() as u64),
+      "clientRttMs": self.client_rtt.map(|rtt| (rtt * 10.0).round() / 10.0),
+      "latency": self.latency.to_serde_value(),
+      "latencyWarning": self.latency_warning,
+    })
+  }
+}
+
+/// Websocket clients of the Brokenithm server. Kept in `SliderControl` so that
+/// the UI can list them, with ids unique across config rebuilds.
+#[derive(Default)]
+pub struct ClientList {
+  next_id: usize,
+  clients: Vec<ClientStatus>,
+}
+
+impl ClientList {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a client and returns its id in the list.
+  pub fn connect(
+    &mut self,
+    addr: SocketAddr,
+    user_agent: &str,
+    protocol: ClientProtocol,
+    latency_warn_ms: f64,
+  ) -> usize {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.clients.push(ClientStatus {
+      id,
+      addr,
+      user_agent: user_agent.chars().take(200).collect(),
+      protocol,
+      kind: ClientKind::Unknown,
+      connected: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs()),
+      messages: 0,
+      window_start: Instant::now(),
+      window_messages: 0,
+      rate: 0.0,
+      last_heartbeat: None,
+      client_rtt: None,
+      latency: LatencyStats::default(),
+      latency_warn_ms,
+      latency_warning: false,
+    });
+    id
+  }
+
+  pub fn disconnect(&mut self, id: usize) {
+    self.clients.retain(|c| c.id != id);
+  }
+
+  /// Counts a message from a client, and what kind of client sent it when
+  /// that is known from the message.
+  pub fn message(&mut self, id: usize, kind: Option<ClientKind>) {
+    let client = match self.clients.iter_mut().find(|c| c.id == id) {
+      Some(client) => client,
+      None => return,
+    };
+    let now = Instant::now();
+    client.messages += 1;
+    client.window_messages += 1;
+    let elapsed = (now - client.window_start).as_secs_f64();
+    if elapsed >= RATE_WINDOW {
+      client.rate = client.window_messages as f64 / elapsed;
+      client.window_start = now;
+      client.window_messages = 0;
+    }
+    if let Some(kind) = kind {
+      client.kind = kind;
+    }
+  }
+
+  /// Records a heartbeat, with the round trip of the previous one when the
+  /// client timed it.
+  pub fn heartbeat(&mut self, id: usize, client_rtt: Option<f64>) {
+    if let Some(client) = self.clients.iter_mut().find(|c| c.id == id) {
+      client.last_heartbeat = Some(Instant::now());
+      if client_rtt.is_some() {
+        client.client_rtt = client_rtt;
+      }
+    }
+  }
+
+  /// Records the round trip of a ping the server timed, in ms.
+  pub fn round_trip(&mut self, id: usize, rtt: f64) {
+    if let Some(client) = self.clients.iter_mut().find(|c| c.id == id) {
+      client.add_rtt(rtt);
+    }
+  }
+
+  /// Round trips of all clients together, with the jitter of the worst one.
+  pub fn latency(&self) -> LatencyStats {
+    let mut all = LatencyStats::default();
+    for client in self.clients.iter() {
+      all.samples.extend(client.latency.samples.iter());
+      all.jitter = all.jitter.max(client.latency.jitter);
+    }
+    all
+  }
+
+  /// Clients whose round trips are over the warning threshold.
+  pub fn slow(&self) -> usize {
+    self.clients.iter().filter(|c| c.latency_warning).count()
+  }
+
+  pub fn count(&self, kind: ClientKind) -> usize {
+    self.clients.iter().filter(|c| c.kind == kind).count()
+  }
+
+  pub fn len(&self) -> usize {
+    self.clients.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.clients.is_empty()
+  }
+
+  pub fn to_serde_value(&self) -> Value {
+    let now = Instant::now();
+    Value::Array(self.clients.iter().map(|c| c.to_serde_value(now)).collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn latency_percentiles() {
+    let mut latency = LatencyStats::default();
+    assert_eq!(latency.percentile(95.0), None);
+    for rtt in 1..=100 {
+      latency.add(rtt as f64);
+    }
+    assert_eq!(latency.percentile(50.0), Some(50.0));
+    assert_eq!(latency.percentile(95.0), Some(95.0));
+    assert_eq!(latency.percentile(0.0), Some(1.0));
+    assert_eq!(latency.histogram(), vec![5, 5, 10, 10, 20, 25, 25, 0, 0, 0]);
+    assert!((latency.jitter - 1.0).abs() < 0.01);
+  }
+
+  #[test]
+  fn latency_keeps_latest_samples() {
+    let mut latency = LatencyStats::default();
+    for _ in 0..LATENCY_SAMPLES {
+      latency.add(100.0);
+    }
+    for _ in 0..LATENCY_SAMPLES {
+      latency.add(10.0);
+    }
+    assert_eq!(latency.len(), LATENCY_SAMPLES);
+    assert_eq!(latency.percentile(99.0), Some(10.0));
+  }
+}
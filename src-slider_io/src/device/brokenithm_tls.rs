@@ -0,0 +1,107 @@
+use log::info;
+use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, SanType};
+use std::{
+  error::Error,
+  fs,
+  io::BufReader,
+  net::IpAddr,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+use tokio_rustls::{
+  rustls::{self, ServerConfig},
+  TlsAcceptor,
+};
+
+use crate::{device::config::BrokenithmOptions, system};
+
+/// Host names the generated certificate is issued for: localhost and every
+/// address of this machine, so that any link shown in the UI is covered.
+fn cert_hosts() -> Vec<String> {
+  let mut hosts = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+  for ip in system::list_ips().unwrap_or_default() {
+    if !hosts.contains(&ip) {
+      hosts.push(ip);
+    }
+  }
+  hosts
+}
+
+fn generate(cert_path: &Path, key_path: &Path, hosts: &[String]) -> Result<(), Box<dyn Error>> {
+  let mut params = CertificateParams::default();
+  let mut name = DistinguishedName::new();
+  name.push(DnType::CommonName, "slidershim brokenithm");
+  params.distinguished_name = name;
+  params.subject_alt_names = hosts
+    .iter()
+    .map(|h| match h.parse::<IpAddr>() {
+      Ok(ip) => SanType::IpAddress(ip),
+      Err(_) => SanType::DnsName(h.clone()),
+    })
+    .collect();
+
+  let cert = Certificate::from_params(params)?;
+  fs::write(cert_path, cert.serialize_pem()?)?;
+  fs::write(key_path, cert.serialize_private_key_pem())?;
+  Ok(())
+}
+
+/// Returns the paths of the generated certificate and key, creating them if
+/// they are missing or were issued for a different set of addresses.
+fn generated_cert() -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+  let (cert_path, key_path, hosts_path) =
+    system::get_brokenithm_cert_paths().ok_or("Cannot find config directory")?;
+
+  let hosts = cert_hosts().join("\n");
+  let saved_hosts = fs::read_to_string(&hosts_path).unwrap_or_default();
+  if !cert_path.exists() || !key_path.exists() || saved_hosts != hosts {
+    info!("Brokenithm generating certificate for {:?}", cert_hosts());
+    generate(&cert_path, &key_path, &cert_hosts())?;
+    fs::write(&hosts_path, hosts)?;
+  }
+
+  Ok((cert_path, key_path))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<rustls::Certificate>, Box<dyn Error>> {
+  let mut reader = BufReader::new(fs::File::open(path)?);
+  let certs = rustls_pemfile::certs(&mut reader)?;
+  if certs.is_empty() {
+    return Err(format!("No certificates in {:?}", path).into());
+  }
+  Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<rustls::PrivateKey, Box<dyn Error>> {
+  let mut reader = BufReader::new(fs::File::open(path)?);
+  for item in rustls_pemfile::read_all(&mut reader)? {
+    match item {
+      rustls_pemfile::Item::RSAKey(key)
+      | rustls_pemfile::Item::PKCS8Key(key)
+      | rustls_pemfile::Item::ECKey(key) => return Ok(rustls::PrivateKey(key)),
+      _ => {}
+    }
+  }
+  Err(format!("No private key in {:?}", path).into())
+}
+
+/// Builds a TLS acceptor from the user provided certificate and key, or from
+/// a persisted self-signed certificate when neither is configured.
+pub fn tls_acceptor(options: &BrokenithmOptions) -> Result<TlsAcceptor, Box<dyn Error>> {
+  let (cert_path, key_path) = match (&options.tls_cert, &options.tls_key) {
+    (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+    (None, None) => generated_cert()?,
+    (Some(_), None) => return Err("TLS certificate is set but the key is not".into()),
+    (None, Some(_)) => return Err("TLS key is set but the certificate is not".into()),
+  };
+  info!("Brokenithm TLS certificate {:?}", cert_path);
+
+  let mut config = ServerConfig::builder()
+    .with_safe_defaults()
+    .with_no_client_auth()
+    .with_single_cert(load_certs(&cert_path)?, load_key(&key_path)?)?;
+  // Websocket upgrades only work over HTTP/1.1
+  config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+  Ok(TlsAcceptor::from(Arc::new(config)))
+}
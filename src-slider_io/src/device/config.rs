@@ -1,4 +1,11 @@
 use serde_json::Value;
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+  device::brokenithm_pairing::origin_list,
+  handtracking::config::HandTrackingConfig,
+  shared::{access::ServerAccessConfig, mdns::mdns_name},
+};
 
 #[derive(Debug, Clone)]
 pub enum HardwareSpec {
@@ -17,6 +24,190 @@ pub enum BrokenithmSpec {
   HandTracking,
 }
 
+/// How input from several connected Brokenithm clients is combined.
+#[derive(Debug, Clone, Copy)]
+pub enum BrokenithmMerge {
+  /// Any client can press any pad.
+  Or,
+  /// The first client to send touches owns the left half of the slider, the
+  /// second owns the right half.
+  Halves,
+}
+
+/// Which protocols the Brokenithm server listens on.
+#[derive(Debug, Clone, Copy)]
+pub enum BrokenithmTls {
+  /// Plain HTTP only.
+  Off,
+  /// HTTPS only, on the Brokenithm port.
+  Https,
+  /// HTTP on the Brokenithm port and HTTPS on the TLS port.
+  Both,
+}
+
+/// Look of the web controller, sent to it as the generated `config.js`.
+#[derive(Debug, Clone)]
+pub struct BrokenithmWebConfig {
+  /// Puts the air strings at the bottom of the screen.
+  pub invert: bool,
+  pub key_color: String,
+  pub air_color: String,
+  pub bg_color: String,
+  /// Background image URL, none when empty.
+  pub bg_image: String,
+  /// Height of the air region relative to the slider.
+  pub air_height: f64,
+  /// How often LED colours are sent to the controller.
+  pub led_interval_ms: u64,
+}
+
+impl BrokenithmWebConfig {
+  pub fn from_serde_value(v: &Value) -> Self {
+    let string = |key: &str, default: &str| {
+      v[key]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .unwrap_or(default)
+        .to_string()
+    };
+
+    Self {
+      invert: v["brokenithmInvert"].as_bool().unwrap_or(false),
+      key_color: string("brokenithmKeyColor", "#FF00FF"),
+      air_color: string("brokenithmAirColor", "#00FFFF"),
+      bg_color: string("brokenithmBgColor", "#000000"),
+      bg_image: string("brokenithmBgImage", ""),
+      air_height: v["brokenithmAirHeight"]
+        .as_f64()
+        .filter(|h| *h > 0.0)
+        .unwrap_or(1.0),
+      led_interval_ms: v["brokenithmLedInterval"]
+        .as_u64()
+        .unwrap_or(50)
+        .clamp(10, 1000),
+    }
+  }
+}
+
+impl Default for BrokenithmWebConfig {
+  fn default() -> Self {
+    Self::from_serde_value(&Value::Null)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct BrokenithmOptions {
+  pub merge: BrokenithmMerge,
+  pub tls: BrokenithmTls,
+  pub tls_port: u16,
+  /// User provided certificate and key. A self-signed certificate is generated
+  /// when these are not set.
+  pub tls_cert: Option<PathBuf>,
+  pub tls_key: Option<PathBuf>,
+  /// Only devices that entered the pairing PIN can send input.
+  pub pairing: bool,
+  /// Origins of pages served through a tunnel or proxy that may send input
+  /// and change settings like pages of this server.
+  pub allowed_origins: Vec<String>,
+  /// Bind address, address lists and limits of the server.
+  pub access: ServerAccessConfig,
+  /// Following ports tried when a configured port is taken.
+  pub fallback_ports: u16,
+  /// Name the server is advertised with over mDNS, off when `None`.
+  pub mdns_name: Option<String>,
+  /// 95th percentile heartbeat round trip above which a client is reported
+  /// as slow, in ms. 0 turns the warning off.
+  pub latency_warn_ms: f64,
+  /// Name of the skin folder whose files replace the embedded web controller
+  /// files.
+  pub skin: Option<String>,
+  pub web: BrokenithmWebConfig,
+  pub hand_tracking: HandTrackingConfig,
+  /// Hand tracker page assets in this directory are served instead of the
+  /// embedded ones.
+  pub hand_asset_dir: Option<PathBuf>,
+  /// Records the hand tracker stream for offline tuning.
+  pub hand_record: bool,
+}
+
+impl BrokenithmOptions {
+  pub fn from_serde_value(v: &Value) -> Self {
+    let path = |key: &str| v[key].as_str().filter(|s| !s.is_empty()).map(PathBuf::from);
+
+    Self {
+      merge: match v["brokenithmMerge"].as_str() {
+        Some("halves") => BrokenithmMerge::Halves,
+        _ => BrokenithmMerge::Or,
+      },
+      tls: match v["brokenithmTls"].as_str() {
+        Some("https") => BrokenithmTls::Https,
+        Some("both") => BrokenithmTls::Both,
+        _ => BrokenithmTls::Off,
+      },
+      tls_port: v["brokenithmTlsPort"]
+        .as_u64()
+        .and_then(|p| u16::try_from(p).ok())
+        .unwrap_or(1607),
+      tls_cert: path("brokenithmTlsCert"),
+      tls_key: path("brokenithmTlsKey"),
+      pairing: v["brokenithmPairing"].as_bool().unwrap_or(false),
+      allowed_origins: origin_list(&v["brokenithmAllowedOrigins"]),
+      access: ServerAccessConfig::from_serde_value(v, "brokenithm"),
+      fallback_ports: v["brokenithmFallbackPorts"]
+        .as_u64()
+        .map_or(0, |n| n.min(100) as u16),
+      mdns_name: mdns_name(v),
+      latency_warn_ms: v["brokenithmLatencyWarnMs"]
+        .as_f64()
+        .filter(|ms| *ms >= 0.0)
+        .unwrap_or(50.0),
+      skin: v["brokenithmSkin"]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string()),
+      web: BrokenithmWebConfig::from_serde_value(v),
+      hand_tracking: HandTrackingConfig::from_serde_value(v),
+      hand_asset_dir: path("handAssetDir"),
+      hand_record: v["handRecord"].as_bool().unwrap_or(false),
+    }
+  }
+}
+
+/// Native hand tracking from a video, without the hand tracker page.
+#[derive(Debug, Clone)]
+pub struct HandVideoOptions {
+  /// Video file, `http://` MJPEG stream URL or V4L2 camera.
+  pub source: String,
+  /// ONNX hand landmark model.
+  pub model: Option<PathBuf>,
+  /// ONNX palm detection model finding the hands for the landmark model.
+  pub palm_model: Option<PathBuf>,
+  /// Frame rate of video files, streams are timed as frames arrive.
+  pub fps: f64,
+  pub hand_tracking: HandTrackingConfig,
+}
+
+impl HandVideoOptions {
+  pub fn from_serde_value(v: &Value) -> Self {
+    Self {
+      source: v["handVideoSource"].as_str().unwrap_or("").to_string(),
+      model: v["handVideoModel"]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from),
+      palm_model: v["handVideoPalmModel"]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from),
+      fps: v["handVideoFps"]
+        .as_f64()
+        .filter(|fps| *fps > 0.0)
+        .unwrap_or(30.0),
+      hand_tracking: HandTrackingConfig::from_serde_value(v),
+    }
+  }
+}
+
 #[derive(Debug, Clone)]
 pub enum DeviceMode {
   None,
@@ -28,11 +219,15 @@ pub enum DeviceMode {
     spec: BrokenithmSpec,
     lights_enabled: bool,
     port: u16,
+    options: Box<BrokenithmOptions>,
   },
   DivaSlider {
     port: String,
     brightness: u8,
   },
+  HandVideo {
+    options: Box<HandVideoOptions>,
+  },
 }
 
 impl DeviceMode {
@@ -72,6 +267,7 @@ impl DeviceMode {
         port: u16::try_from(v["brokenithmPort"].as_i64()?)
           .ok()
           .or(Some(1606))?,
+        options: Box::new(BrokenithmOptions::from_serde_value(v)),
       },
       "brokenithm-led" => DeviceMode::Brokenithm {
         spec: match v["disableAirStrings"].as_bool()? {
@@ -82,6 +278,7 @@ impl DeviceMode {
         port: u16::try_from(v["brokenithmPort"].as_i64()?)
           .ok()
           .or(Some(1606))?,
+        options: Box::new(BrokenithmOptions::from_serde_value(v)),
       },
       "brokenithm-nostalgia" => DeviceMode::Brokenithm {
         spec: BrokenithmSpec::Nostalgia,
@@ -89,6 +286,7 @@ impl DeviceMode {
         port: u16::try_from(v["brokenithmPort"].as_i64()?)
           .ok()
           .or(Some(1606))?,
+        options: Box::new(BrokenithmOptions::from_serde_value(v)),
       },
       "brokenithm-handtracking" => DeviceMode::Brokenithm {
         spec: BrokenithmSpec::HandTracking,
@@ -96,15 +294,47 @@ impl DeviceMode {
         port: u16::try_from(v["brokenithmPort"].as_i64()?)
           .ok()
           .or(Some(1606))?,
+        options: Box::new(BrokenithmOptions::from_serde_value(v)),
+      },
+      "hand-video" => DeviceMode::HandVideo {
+        options: Box::new(HandVideoOptions::from_serde_value(v)),
       },
       _ => return None,
     })
   }
 
+  /// Reads how long the device may go without reporting before its input is
+  /// considered stale. `None` disables the stale input watchdog. It is off by
+  /// default for HID controllers, which may only report when a pad changes,
+  /// so a held pad would be released mid-hold. The other devices send
+  /// reports or heartbeats continuously.
+  pub fn stale_timeout_from_serde_value(&self, v: &Value) -> Option<Duration> {
+    let (key, default) = match self {
+      DeviceMode::None => return None,
+      DeviceMode::Hardware { .. } => ("staleTimeoutHardware", 0),
+      DeviceMode::DivaSlider { .. } => ("staleTimeoutDiva", 500),
+      DeviceMode::Brokenithm { .. } => ("staleTimeoutBrokenithm", 3000),
+      DeviceMode::HandVideo { .. } => ("staleTimeoutHandVideo", 1000),
+    };
+
+    match v[key].as_u64().unwrap_or(default) {
+      0 => None,
+      ms => Some(Duration::from_millis(ms)),
+    }
+  }
+
   pub fn get_port(&self) -> Option<u16> {
     match self {
       DeviceMode::Brokenithm { port, .. } => Some(*port),
       _ => None,
     }
   }
+
+  /// Whether the Brokenithm port only answers HTTPS.
+  pub fn is_https_only(&self) -> bool {
+    match self {
+      DeviceMode::Brokenithm { options, .. } => matches!(options.tls, BrokenithmTls::Https),
+      _ => false,
+    }
+  }
 }
@@ -296,6 +296,7 @@ impl ThreadJob for DivaSliderJob {
       DivaSliderBootstrap::ReadLoop => {
         while let Some(data_packet) = self.in_packets.pop_front() {
           if data_packet.command == 0x01 && data_packet.len == 32 {
+            self.state.mark_report();
             let mut input_handle = self.state.input.lock();
             input_handle
               .ground
@@ -0,0 +1,366 @@
+use image::RgbImage;
+use log::{error, info, warn};
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+    Arc,
+  },
+  thread::{self, sleep},
+  time::{Duration, Instant},
+};
+
+use crate::{
+  device::config::HandVideoOptions,
+  handtracking::{
+    frame::{HandFrame, HandObservation},
+    landmarks::LandmarkModel,
+    tracker::{HandOutput, HandTracker},
+    video::{FrameSource, MjpegSource},
+  },
+  meta::meta::apply_action,
+  shared::worker::{ThreadJob, WorkerStatus},
+  state::SliderState,
+};
+
+/// Wait before opening the video again after it ended or failed.
+const REOPEN_DELAY: Duration = Duration::from_secs(1);
+/// Longest a tick waits for a frame, well below the stall timeout so that the
+/// worker keeps its heartbeat while the video is slow or reconnecting.
+const FRAME_WAIT: Duration = Duration::from_millis(50);
+
+#[cfg(feature = "native-hand-tracking")]
+fn load_model(options: &HandVideoOptions) -> Result<Box<dyn LandmarkModel>, String> {
+  use crate::handtracking::landmarks::{OnnxLandmarkModel, OnnxPalmDetector};
+
+  let path = options
+    .model
+    .as_ref()
+    .ok_or_else(|| "No hand landmark model is set".to_string())?;
+  let mut model = OnnxLandmarkModel::load(path, options.hand_tracking.presence_confidence)?;
+  if let Some(path) = options.palm_model.as_ref() {
+    let palm_detector = OnnxPalmDetector::load(path, options.hand_tracking.detection_confidence)?;
+    model = model.with_palm_detector(palm_detector);
+  }
+  Ok(Box::new(model))
+}
+
+#[cfg(not(feature = "native-hand-tracking"))]
+fn load_model(_options: &HandVideoOptions) -> Result<Box<dyn LandmarkModel>, String> {
+  Err("slidershim was built without the native-hand-tracking feature".to_string())
+}
+
+fn open(options: &HandVideoOptions) -> Result<MjpegSource, String> {
+  let source = MjpegSource::open(&options.source, options.fps)
+    .map_err(|e| format!("{} could not be opened: {}", options.source, e))?;
+  info!("Hand video opened {}", options.source);
+  Ok(source)
+}
+
+/// What the capture thread sends to the worker.
+enum CaptureEvent {
+  Hands(HandFrame),
+  /// The video ended or failed, it is opened again after `REOPEN_DELAY`.
+  Closed,
+}
+
+/// Reads frames and runs the model on its own thread. Connecting to a
+/// stream, waiting for its next frame and running the model can each take
+/// longer than the stall watchdog allows a worker tick.
+struct Capture {
+  options: HandVideoOptions,
+  model: Box<dyn LandmarkModel>,
+  events: SyncSender<CaptureEvent>,
+  stop: Arc<AtomicBool>,
+}
+
+impl Capture {
+  fn run(mut self, mut source: Option<MjpegSource>) {
+    while !self.stop.load(Ordering::SeqCst) {
+      let mut video = match source.take() {
+        Some(video) => video,
+        None => {
+          sleep(REOPEN_DELAY);
+          match open(&self.options) {
+            Ok(video) => video,
+            Err(e) => {
+              warn!("Hand video {}", e);
+              continue;
+            }
+          }
+        }
+      };
+      if !self.play(&mut video) || self.events.send(CaptureEvent::Closed).is_err() {
+        break;
+      }
+    }
+  }
+
+  /// Tracks the frames of a video until it ends or fails, playing files in
+  /// real time. Returns `false` once the worker is gone.
+  fn play(&mut self, video: &mut MjpegSource) -> bool {
+    // When the first frame was shown and its time
+    let mut anchor = None;
+    loop {
+      let frame = match video.next_frame() {
+        Ok(Some(frame)) => frame,
+        Ok(None) => {
+          info!("Hand video ended, starting over");
+          return true;
+        }
+        Err(e) => {
+          warn!("Hand video stopped: {}", e);
+          return true;
+        }
+      };
+
+      let (start, start_t) = *anchor.get_or_insert((Instant::now(), frame.t));
+      let due = start + Duration::from_secs_f64((frame.t - start_t).max(0.0) / 1000.0);
+      if let Some(wait) = due.checked_duration_since(Instant::now()) {
+        sleep(wait);
+      }
+
+      let hands = HandFrame {
+        t: frame.t,
+        hands: self.detect(&frame.image),
+        source: None,
+      };
+      if self.events.send(CaptureEvent::Hands(hands)).is_err() {
+        return false;
+      }
+    }
+  }
+
+  fn detect(&mut self, image: &RgbImage) -> Vec<HandObservation> {
+    match self.model.detect(image) {
+      Ok(hands) => hands,
+      Err(e) => {
+        warn!("Hand landmark model failed: {}", e);
+        vec![]
+      }
+    }
+  }
+}
+
+/// Tracks hands in a video and writes the air strings, like the hand tracker
+/// page does through the Brokenithm server. Files are played in real time and
+/// started over when they end, streams and cameras are reopened when they
+/// drop.
+pub struct HandVideoJob {
+  state: SliderState,
+  options: HandVideoOptions,
+  model: Option<Box<dyn LandmarkModel>>,
+  events: Option<Receiver<CaptureEvent>>,
+  stop: Arc<AtomicBool>,
+  tracker: HandTracker,
+}
+
+impl HandVideoJob {
+  pub fn new(state: &SliderState, options: &HandVideoOptions) -> Self {
+    Self {
+      state: state.clone(),
+      options: options.clone(),
+      model: None,
+      events: None,
+      stop: Arc::new(AtomicBool::new(false)),
+      tracker: HandTracker::new(&options.hand_tracking),
+    }
+  }
+
+  /// Uses `model` instead of loading the configured one.
+  pub fn with_model(mut self, model: Box<dyn LandmarkModel>) -> Self {
+    self.model = Some(model);
+    self
+  }
+
+  fn fail(&self, message: String) -> bool {
+    error!("Hand video {}", message);
+    self
+      .state
+      .control
+      .set_worker_status("hand-video", WorkerStatus::failed(message));
+    false
+  }
+
+  /// Releases the input until the video is opened again.
+  fn close(&mut self) {
+    self.tracker = HandTracker::new(&self.options.hand_tracking);
+    self.write(&HandOutput {
+      air: [0; 6],
+      extra: [0; 3],
+      actions: vec![],
+    });
+  }
+
+  fn write(&self, output: &HandOutput) {
+    self.state.mark_report();
+    let mut input_handle = self.state.input.lock();
+    input_handle.air = output.air;
+    input_handle.extra = output.extra;
+  }
+}
+
+impl ThreadJob for HandVideoJob {
+  fn setup(&mut self) -> bool {
+    let model = match self.model.take() {
+      Some(model) => model,
+      None => match load_model(&self.options) {
+        Ok(model) => model,
+        Err(e) => return self.fail(format!("model could not be loaded: {}", e)),
+      },
+    };
+    // Opened here the first time so that a wrong source shows up in the UI
+    let source = match open(&self.options) {
+      Ok(source) => source,
+      Err(e) => return self.fail(e),
+    };
+
+    let (events, receiver) = mpsc::sync_channel(2);
+    let capture = Capture {
+      options: self.options.clone(),
+      model,
+      events,
+      stop: self.stop.clone(),
+    };
+    thread::spawn(move || capture.run(Some(source)));
+    self.events = Some(receiver);
+    true
+  }
+
+  fn tick(&mut self) -> bool {
+    let event = self.events.as_ref().unwrap().recv_timeout(FRAME_WAIT);
+    match event {
+      Ok(CaptureEvent::Hands(hands)) => {
+        let output = self.tracker.update(&hands);
+        self.write(&output);
+        for action in output.actions {
+          apply_action(&self.state.control, action);
+        }
+        true
+      }
+      Ok(CaptureEvent::Closed) => {
+        self.close();
+        false
+      }
+      Err(RecvTimeoutError::Timeout) => false,
+      Err(RecvTimeoutError::Disconnected) => panic!("Hand video capture thread stopped"),
+    }
+  }
+}
+
+impl Drop for HandVideoJob {
+  fn drop(&mut self) {
+    // The capture thread also stops at its next frame once the receiver is
+    // gone, this stops it while it is waiting to reopen the video
+    self.stop.store(true, Ordering::SeqCst);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::handtracking::{config::HandTrackingConfig, frame::Handedness};
+
+  /// Finds the lit square of each half of the frame, left half as the left
+  /// hand.
+  struct BrightSpots;
+
+  impl LandmarkModel for BrightSpots {
+    fn detect(&mut self, image: &RgbImage) -> Result<Vec<HandObservation>, String> {
+      let half = image.width() / 2;
+      let mut hands = vec![];
+      for (side, hand) in [Handedness::Left, Handedness::Right].iter().enumerate() {
+        let lit = image
+          .enumerate_pixels()
+          .filter(|(x, _, p)| *x / half == side as u32 && p.0[0] > 128)
+          .map(|(x, y, _)| (x as f64 + 0.5, y as f64 + 0.5))
+          .collect::<Vec<_>>();
+        if lit.is_empty() {
+          continue;
+        }
+        hands.push(HandObservation {
+          x: lit.iter().map(|p| p.0).sum::<f64>() / lit.len() as f64 / image.width() as f64,
+          y: lit.iter().map(|p| p.1).sum::<f64>() / lit.len() as f64 / image.height() as f64,
+          hand: Some(*hand),
+          score: 1.0,
+          landmarks: vec![],
+        });
+      }
+      Ok(hands)
+    }
+  }
+
+  #[test]
+  fn tracks_hands_in_clip() {
+    // The left hand starts low, rises to the top of the zone for 6 frames
+    // and drops again. The right hand stays low.
+    let options = HandVideoOptions {
+      source: concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/handtracking/testdata/raise_left.mjpeg"
+      )
+      .to_string(),
+      model: None,
+      palm_model: None,
+      fps: 100.0,
+      hand_tracking: HandTrackingConfig {
+        zone_top: 0.0,
+        zone_bottom: 0.6,
+        ..HandTrackingConfig::default()
+      },
+    };
+    let state = SliderState::new();
+    let mut job = HandVideoJob::new(&state, &options).with_model(Box::new(BrightSpots));
+    assert!(job.setup());
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut air = vec![];
+    while air.len() < 24 && Instant::now() < deadline {
+      if job.tick() {
+        air.push(state.input.lock().air);
+      }
+    }
+    assert_eq!(air.len(), 24);
+    assert_eq!(air[0], [0; 6]);
+    // Strings count from the bottom, the hand at 0.15 is in the second
+    // string from the top
+    assert_eq!(air[12], [0, 0, 0, 0, 1, 0]);
+    assert_eq!(air[23], [0; 6]);
+
+    // The end of the clip releases the air
+    state.input.lock().air = [1; 6];
+    while state.input.lock().air != [0; 6] {
+      assert!(!job.tick());
+      assert!(Instant::now() < deadline);
+    }
+
+    // and the clip starts over, with ticks that keep returning meanwhile so
+    // that the stall watchdog sees the worker alive
+    loop {
+      let tick = Instant::now();
+      if job.tick() {
+        break;
+      }
+      assert!(tick.elapsed() < Duration::from_millis(500));
+      assert!(Instant::now() < deadline);
+    }
+  }
+
+  #[test]
+  fn fails_without_source() {
+    let options = HandVideoOptions {
+      source: "/nonexistent/hands.mjpeg".to_string(),
+      model: None,
+      palm_model: None,
+      fps: 30.0,
+      hand_tracking: HandTrackingConfig::default(),
+    };
+    let state = SliderState::new();
+    let mut job = HandVideoJob::new(&state, &options).with_model(Box::new(BrightSpots));
+    assert!(!job.setup());
+    assert!(state.control.get_worker_status()["hand-video"]["error"]
+      .as_str()
+      .unwrap()
+      .contains("could not be opened"));
+  }
+}
@@ -362,7 +362,10 @@ impl ThreadJob for HidJob {
       self.read_buf.len = res;
       // debug!("{:?}", self.read_buf.slice());
       // if self.read_buf.len != 0 {
-      if (self.read_buf.len != 0) && (self.read_buf.slice() != self.last_read_buf.slice()) {
+      let was_stale = self.read_buf.len != 0 && self.state.mark_report();
+      if (self.read_buf.len != 0)
+        && (was_stale || self.read_buf.slice() != self.last_read_buf.slice())
+      {
         work = true;
         let mut input_handle = self.state.input.lock();
         (self.read_callback)(&self.read_buf, input_handle.deref_mut());
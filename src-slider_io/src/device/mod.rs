@@ -1,5 +1,15 @@
 pub mod config;
 
 pub mod brokenithm;
+pub mod brokenithm_api;
+pub mod brokenithm_assets;
+pub mod brokenithm_clients;
+pub mod brokenithm_hands;
+pub mod brokenithm_pairing;
+pub mod brokenithm_protocol;
+pub mod brokenithm_status;
+pub mod brokenithm_tls;
 pub mod diva;
+pub mod hand_video;
 pub mod hid;
+pub mod watchdog;
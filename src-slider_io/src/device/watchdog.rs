@@ -0,0 +1,67 @@
+use async_trait::async_trait;
+use log::warn;
+use std::{sync::Weak, time::Duration};
+use tokio::time::{interval, Interval};
+
+use crate::{shared::worker::AsyncJob, state::SliderState};
+
+/// Releases ground and air input when the device has not delivered a report or
+/// heartbeat within its timeout, so that pads do not stay latched when a
+/// device sleeps or stops responding. Devices with several clients are checked
+/// for each client, so that one active client does not hide a stale one.
+pub struct StaleInputJob {
+  state: SliderState,
+  timeout: Duration,
+  timer: Interval,
+}
+
+impl StaleInputJob {
+  pub fn new(state: &SliderState, timeout: &Duration) -> Self {
+    Self {
+      state: state.clone(),
+      timeout: *timeout,
+      timer: interval(Duration::from_millis(50)),
+    }
+  }
+}
+
+#[async_trait]
+impl AsyncJob for StaleInputJob {
+  async fn setup(&mut self) -> bool {
+    true
+  }
+
+  async fn tick(&mut self) -> bool {
+    {
+      let clients = self
+        .state
+        .freshness
+        .lock()
+        .clients
+        .as_ref()
+        .and_then(Weak::upgrade);
+      if let Some(clients) = clients {
+        clients.lock().release_stale(self.timeout);
+      } else {
+        let mut freshness_handle = self.state.freshness.lock();
+        if let (Some(last_report), false) = (freshness_handle.last_report, freshness_handle.stale) {
+          let elapsed = last_report.elapsed();
+          if elapsed > self.timeout {
+            warn!(
+              "Device input stale for {:?}, releasing ground and air",
+              elapsed
+            );
+            freshness_handle.stale = true;
+
+            let mut input_handle = self.state.input.lock();
+            input_handle.ground.fill(0);
+            input_handle.air.fill(0);
+          }
+        }
+      }
+    }
+    self.timer.tick().await;
+
+    true
+  }
+}
@@ -0,0 +1,223 @@
+use crate::handtracking::{
+  camera::CameraOrientation, config::HandTrackingConfig, filter::OneEuroFilter, frame::HandFrame,
+};
+
+/// Motion state of one hand.
+struct HandMotion {
+  filter: OneEuroFilter,
+  /// A flick or swipe only fires again once the hand slowed down.
+  flick_armed: bool,
+  swipe_armed: bool,
+  /// End times of the current pulses in seconds.
+  flick_until: f64,
+  swipe_until: f64,
+  /// Time since the raised hand has been still.
+  still_since: Option<f64>,
+  holding: bool,
+}
+
+impl HandMotion {
+  fn new(config: &HandTrackingConfig) -> Self {
+    Self {
+      filter: OneEuroFilter::new(
+        config.filter_min_cutoff,
+        config.filter_beta,
+        config.filter_d_cutoff,
+      ),
+      flick_armed: true,
+      swipe_armed: true,
+      flick_until: f64::NEG_INFINITY,
+      swipe_until: f64::NEG_INFINITY,
+      still_since: None,
+      holding: false,
+    }
+  }
+
+  fn lost(&mut self) {
+    self.filter.reset();
+    self.flick_armed = true;
+    self.swipe_armed = true;
+    self.still_since = None;
+    self.holding = false;
+  }
+}
+
+/// Detects air actions from hand velocity: an upward flick and a downward
+/// swipe each press strings for a short pulse, and a raised hand held still
+/// presses strings until it moves.
+pub struct AirActions {
+  min_confidence: f64,
+  camera: CameraOrientation,
+  /// Hands above this height are raised.
+  zone_bottom: f64,
+  flick_speed: f64,
+  swipe_speed: f64,
+  hold_speed: f64,
+  hold_time: f64,
+  pulse_time: f64,
+  flick_air: [u8; 6],
+  swipe_air: [u8; 6],
+  hold_air: [u8; 6],
+  hands: [HandMotion; 2],
+}
+
+impl AirActions {
+  pub fn new(config: &HandTrackingConfig) -> Self {
+    Self {
+      min_confidence: config.min_confidence,
+      camera: config.camera,
+      zone_bottom: config.zone_bottom,
+      flick_speed: config.flick_speed,
+      swipe_speed: config.swipe_speed,
+      hold_speed: config.hold_speed,
+      hold_time: config.hold_ms / 1000.0,
+      pulse_time: config.pulse_ms / 1000.0,
+      flick_air: config.flick_air,
+      swipe_air: config.swipe_air,
+      hold_air: config.hold_air,
+      hands: [HandMotion::new(config), HandMotion::new(config)],
+    }
+  }
+
+  /// Updates with a new frame and returns the strings pressed by actions, with
+  /// index 0 the bottom string.
+  pub fn update(&mut self, frame: &HandFrame) -> [u8; 6] {
+    let t = frame.t / 1000.0;
+    let seen = frame.by_side(self.min_confidence, &self.camera);
+
+    let mut air = [0; 6];
+    for (motion, hand) in self.hands.iter_mut().zip(seen.iter()) {
+      match hand {
+        Some(hand) => {
+          let y = motion.filter.filter(hand.y, t);
+          // Image y grows downwards
+          let up = -motion.filter.velocity();
+
+          if motion.flick_armed && up > self.flick_speed {
+            motion.flick_armed = false;
+            motion.flick_until = t + self.pulse_time;
+          } else if up < self.flick_speed / 2.0 {
+            motion.flick_armed = true;
+          }
+          if motion.swipe_armed && -up > self.swipe_speed {
+            motion.swipe_armed = false;
+            motion.swipe_until = t + self.pulse_time;
+          } else if -up < self.swipe_speed / 2.0 {
+            motion.swipe_armed = true;
+          }
+
+          let raised = y < self.zone_bottom;
+          if raised && up.abs() < self.hold_speed {
+            let since = *motion.still_since.get_or_insert(t);
+            motion.holding |= t - since >= self.hold_time;
+          } else if !raised || up.abs() > self.hold_speed * 2.0 {
+            motion.still_since = None;
+            motion.holding = false;
+          }
+        }
+        None => motion.lost(),
+      }
+
+      let pressed = [
+        (t < motion.flick_until, &self.flick_air),
+        (t < motion.swipe_until, &self.swipe_air),
+        (motion.holding, &self.hold_air),
+      ];
+      for (active, strings) in pressed {
+        if active {
+          for (a, s) in air.iter_mut().zip(strings.iter()) {
+            *a |= *s;
+          }
+        }
+      }
+    }
+    air
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::handtracking::frame::HandObservation;
+
+  fn config() -> HandTrackingConfig {
+    HandTrackingConfig {
+      zone_bottom: 0.5,
+      flick_speed: 1.0,
+      swipe_speed: 1.0,
+      hold_speed: 0.2,
+      hold_ms: 100.0,
+      pulse_ms: 50.0,
+      flick_air: [0, 0, 0, 1, 1, 1],
+      swipe_air: [1, 1, 1, 0, 0, 0],
+      hold_air: [0, 0, 1, 1, 0, 0],
+      // Effectively no smoothing
+      filter_min_cutoff: 1e9,
+      filter_beta: 0.0,
+      filter_d_cutoff: 1e9,
+      ..HandTrackingConfig::default()
+    }
+  }
+
+  fn frame(t: f64, y: Option<f64>) -> HandFrame {
+    HandFrame {
+      t,
+      hands: y
+        .map(|y| HandObservation {
+          x: 0.5,
+          y,
+          hand: None,
+          score: 1.0,
+          landmarks: vec![],
+        })
+        .into_iter()
+        .collect(),
+      source: None,
+    }
+  }
+
+  #[test]
+  fn flick_up_pulses_once() {
+    let mut actions = AirActions::new(&config());
+    assert_eq!(actions.update(&frame(0.0, Some(0.8))), [0; 6]);
+    // 0.1 image heights in 20ms is 5 per second upwards
+    assert_eq!(actions.update(&frame(20.0, Some(0.7))), [0, 0, 0, 1, 1, 1]);
+    // Still moving up, but the pulse does not repeat and runs out
+    assert_eq!(actions.update(&frame(40.0, Some(0.6))), [0, 0, 0, 1, 1, 1]);
+    assert_eq!(actions.update(&frame(80.0, Some(0.5))), [0; 6]);
+  }
+
+  #[test]
+  fn swipe_down_pulses() {
+    let mut actions = AirActions::new(&config());
+    actions.update(&frame(0.0, Some(0.6)));
+    assert_eq!(actions.update(&frame(20.0, Some(0.7))), [1, 1, 1, 0, 0, 0]);
+    assert_eq!(actions.update(&frame(100.0, Some(0.7))), [0; 6]);
+  }
+
+  #[test]
+  fn raised_still_hand_holds_until_it_moves() {
+    let mut actions = AirActions::new(&config());
+    assert_eq!(actions.update(&frame(0.0, Some(0.3))), [0; 6]);
+    assert_eq!(actions.update(&frame(50.0, Some(0.3))), [0; 6]);
+    assert_eq!(actions.update(&frame(150.0, Some(0.3))), [0, 0, 1, 1, 0, 0]);
+    assert_eq!(
+      actions.update(&frame(200.0, Some(0.301))),
+      [0, 0, 1, 1, 0, 0]
+    );
+    // Moving down at 1.5 per second releases the hold and swipes
+    assert_eq!(
+      actions.update(&frame(300.0, Some(0.451))),
+      [1, 1, 1, 0, 0, 0]
+    );
+  }
+
+  #[test]
+  fn lowered_hand_does_not_hold() {
+    let mut actions = AirActions::new(&config());
+    for t in 0..10 {
+      assert_eq!(actions.update(&frame(t as f64 * 50.0, Some(0.8))), [0; 6]);
+    }
+    assert_eq!(actions.update(&frame(500.0, None)), [0; 6]);
+  }
+}
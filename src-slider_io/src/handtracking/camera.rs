@@ -0,0 +1,91 @@
+use crate::handtracking::frame::{HandObservation, Handedness};
+
+/// How the camera is mounted, used to turn hand positions from the camera
+/// image into an upright, unmirrored view.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CameraOrientation {
+  /// Clockwise rotation in degrees that makes the image upright, one of 0, 90,
+  /// 180 or 270.
+  pub rotation: u16,
+  /// Flips the image horizontally after rotating, which also swaps the
+  /// reported handedness. Set this when the camera looks the same way as the
+  /// player instead of facing them.
+  pub mirror: bool,
+}
+
+impl CameraOrientation {
+  pub fn is_valid(&self) -> bool {
+    matches!(self.rotation, 0 | 90 | 180 | 270)
+  }
+
+  pub fn orient(&self, hand: &HandObservation) -> HandObservation {
+    let (x, y) = match self.rotation {
+      90 => (1.0 - hand.y, hand.x),
+      180 => (1.0 - hand.x, 1.0 - hand.y),
+      270 => (hand.y, 1.0 - hand.x),
+      _ => (hand.x, hand.y),
+    };
+
+    match self.mirror {
+      false => HandObservation {
+        x,
+        y,
+        ..hand.clone()
+      },
+      true => HandObservation {
+        x: 1.0 - x,
+        y,
+        hand: hand.hand.map(|h| match h {
+          Handedness::Left => Handedness::Right,
+          Handedness::Right => Handedness::Left,
+        }),
+        ..hand.clone()
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hand(x: f64, y: f64) -> HandObservation {
+    HandObservation {
+      x,
+      y,
+      hand: Some(Handedness::Left),
+      score: 1.0,
+      landmarks: vec![],
+    }
+  }
+
+  #[test]
+  fn rotates_clockwise() {
+    let cases = [
+      (0, (0.2, 0.1)),
+      (90, (0.9, 0.2)),
+      (180, (0.8, 0.9)),
+      (270, (0.1, 0.8)),
+    ];
+    for (rotation, (x, y)) in cases {
+      let camera = CameraOrientation {
+        rotation,
+        mirror: false,
+      };
+      let oriented = camera.orient(&hand(0.2, 0.1));
+      assert!((oriented.x - x).abs() < 1e-9, "rotation {}", rotation);
+      assert!((oriented.y - y).abs() < 1e-9, "rotation {}", rotation);
+    }
+  }
+
+  #[test]
+  fn mirror_swaps_handedness() {
+    let camera = CameraOrientation {
+      rotation: 0,
+      mirror: true,
+    };
+    let oriented = camera.orient(&hand(0.2, 0.1));
+    assert!((oriented.x - 0.8).abs() < 1e-9);
+    assert_eq!(oriented.hand, Some(Handedness::Right));
+  }
+}
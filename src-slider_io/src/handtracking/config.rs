@@ -0,0 +1,368 @@
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+use crate::handtracking::{camera::CameraOrientation, gestures::GestureBinding};
+
+/// How air actions detected from hand motion combine with the air zones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandActionMode {
+  /// Zones only.
+  Off,
+  /// Actions press strings in addition to the zones.
+  Add,
+  /// Actions only, the zones are not used.
+  Only,
+}
+
+/// MediaPipe hand landmarker model run by the hand tracker page. Lite models
+/// are faster and less accurate, int8 models are smaller than float16 ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandModel {
+  FullFloat16,
+  FullInt8,
+  LiteFloat16,
+  LiteInt8,
+}
+
+impl HandModel {
+  pub fn from_str(s: &str) -> Option<Self> {
+    match s {
+      "full-float16" => Some(HandModel::FullFloat16),
+      "full-int8" => Some(HandModel::FullInt8),
+      "lite-float16" => Some(HandModel::LiteFloat16),
+      "lite-int8" => Some(HandModel::LiteInt8),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      HandModel::FullFloat16 => "full-float16",
+      HandModel::FullInt8 => "full-int8",
+      HandModel::LiteFloat16 => "lite-float16",
+      HandModel::LiteInt8 => "lite-int8",
+    }
+  }
+}
+
+/// Parses a list of air string indices into string flags.
+fn air_strings(v: &Value) -> Option<[u8; 6]> {
+  let mut air = [0; 6];
+  for idx in v.as_array()? {
+    match idx.as_u64() {
+      Some(idx) if idx < 6 => air[idx as usize] = 1,
+      _ => return None,
+    }
+  }
+  Some(air)
+}
+
+fn air_indices(air: &[u8; 6]) -> Vec<usize> {
+  (0..6).filter(|idx| air[*idx] > 0).collect()
+}
+
+/// Where one hand tracker source is mounted and where its air zone lies in its
+/// own image. Sources without one use the main calibration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceCalibration {
+  pub camera: CameraOrientation,
+  /// Top edge of the air zone in this source's upright image.
+  pub zone_top: f64,
+  /// Bottom edge of the air zone in this source's upright image.
+  pub zone_bottom: f64,
+}
+
+impl SourceCalibration {
+  fn from_serde_value(v: &Value, default: &Self) -> Self {
+    Self {
+      camera: CameraOrientation {
+        rotation: v["handCameraRotation"]
+          .as_u64()
+          .map_or(default.camera.rotation, |r| r as u16),
+        mirror: v["handCameraMirror"]
+          .as_bool()
+          .unwrap_or(default.camera.mirror),
+      },
+      zone_top: v["handZoneTop"].as_f64().unwrap_or(default.zone_top),
+      zone_bottom: v["handZoneBottom"].as_f64().unwrap_or(default.zone_bottom),
+    }
+  }
+
+  fn to_serde_value(self) -> Value {
+    json!({
+      "handCameraRotation": self.camera.rotation,
+      "handCameraMirror": self.camera.mirror,
+      "handZoneTop": self.zone_top,
+      "handZoneBottom": self.zone_bottom,
+    })
+  }
+}
+
+/// Tuning for mapping hand positions from the hand tracker to air strings.
+/// Positions are normalized to the camera image, with y = 0 at the top.
+#[derive(Debug, Clone)]
+pub struct HandTrackingConfig {
+  /// Top edge of the air zone.
+  pub zone_top: f64,
+  /// Bottom edge of the air zone. Hands below this do not press any string.
+  pub zone_bottom: f64,
+  /// Relative heights of the 6 air strings, listed from the top string down.
+  pub zone_heights: [f64; 6],
+  /// How far past a boundary a hand must move to change strings, as a fraction
+  /// of the average string height.
+  pub hysteresis: f64,
+  /// Hands reported with a lower confidence are ignored.
+  pub min_confidence: f64,
+  pub model: HandModel,
+  /// Minimum hand detection confidence used by the hand tracker page.
+  pub detection_confidence: f64,
+  /// Minimum hand presence confidence used by the hand tracker page.
+  pub presence_confidence: f64,
+  /// Minimum tracking confidence used by the hand tracker page.
+  pub tracking_confidence: f64,
+  /// Hand tracker clients that have not reported for this long, in
+  /// milliseconds, are dropped.
+  pub source_timeout_ms: f64,
+  pub camera: CameraOrientation,
+  /// Calibrations of named hand tracker sources, such as a phone at the side,
+  /// that are mounted differently from the main camera.
+  pub sources: BTreeMap<String, SourceCalibration>,
+  /// One-Euro filter minimum cutoff frequency in Hz.
+  pub filter_min_cutoff: f64,
+  /// One-Euro filter speed coefficient.
+  pub filter_beta: f64,
+  /// One-Euro filter cutoff frequency for the derivative in Hz.
+  pub filter_d_cutoff: f64,
+  pub actions: HandActionMode,
+  /// Upward speed in image heights per second that counts as a flick.
+  pub flick_speed: f64,
+  /// Downward speed in image heights per second that counts as a swipe.
+  pub swipe_speed: f64,
+  /// Raised hands slower than this, in image heights per second, are still.
+  pub hold_speed: f64,
+  /// How long a raised hand must stay still before holding, in milliseconds.
+  pub hold_ms: f64,
+  /// Length of the flick and swipe pulses in milliseconds.
+  pub pulse_ms: f64,
+  /// Air strings pressed by a flick, with index 0 the bottom string.
+  pub flick_air: [u8; 6],
+  /// Air strings pressed by a swipe.
+  pub swipe_air: [u8; 6],
+  /// Air strings pressed while holding.
+  pub hold_air: [u8; 6],
+  /// Gestures bound to extra buttons or meta actions.
+  pub gestures: Vec<GestureBinding>,
+}
+
+impl HandTrackingConfig {
+  pub fn from_serde_value(v: &Value) -> Self {
+    let default = Self::default();
+    let num = |key: &str, default: f64| v[key].as_f64().unwrap_or(default);
+
+    let mut zone_heights = default.zone_heights;
+    if let Some(heights) = v["handZoneHeights"].as_array() {
+      if heights.len() == 6 && heights.iter().all(|h| h.as_f64().unwrap_or(0.0) > 0.0) {
+        for (z, h) in zone_heights.iter_mut().zip(heights.iter()) {
+          *z = h.as_f64().unwrap();
+        }
+      }
+    }
+
+    let main = SourceCalibration::from_serde_value(v, &default.source(None));
+    let sources = v["handSources"]
+      .as_object()
+      .map(|sources| {
+        sources
+          .iter()
+          .map(|(name, v)| (name.clone(), SourceCalibration::from_serde_value(v, &main)))
+          .collect()
+      })
+      .unwrap_or_default();
+
+    Self {
+      zone_top: main.zone_top,
+      zone_bottom: main.zone_bottom,
+      zone_heights,
+      hysteresis: num("handHysteresis", default.hysteresis),
+      min_confidence: num("handMinConfidence", default.min_confidence),
+      model: v["handModel"]
+        .as_str()
+        .and_then(HandModel::from_str)
+        .unwrap_or(default.model),
+      detection_confidence: num("handDetectionConfidence", default.detection_confidence),
+      presence_confidence: num("handPresenceConfidence", default.presence_confidence),
+      tracking_confidence: num("handTrackingConfidence", default.tracking_confidence),
+      source_timeout_ms: num("handSourceTimeoutMs", default.source_timeout_ms),
+      camera: main.camera,
+      sources,
+      filter_min_cutoff: num("handFilterMinCutoff", default.filter_min_cutoff),
+      filter_beta: num("handFilterBeta", default.filter_beta),
+      filter_d_cutoff: num("handFilterDCutoff", default.filter_d_cutoff),
+      actions: match v["handActions"].as_str() {
+        Some("add") => HandActionMode::Add,
+        Some("only") => HandActionMode::Only,
+        _ => HandActionMode::Off,
+      },
+      flick_speed: num("handFlickSpeed", default.flick_speed),
+      swipe_speed: num("handSwipeSpeed", default.swipe_speed),
+      hold_speed: num("handHoldSpeed", default.hold_speed),
+      hold_ms: num("handHoldMs", default.hold_ms),
+      pulse_ms: num("handPulseMs", default.pulse_ms),
+      flick_air: air_strings(&v["handFlickAir"]).unwrap_or(default.flick_air),
+      swipe_air: air_strings(&v["handSwipeAir"]).unwrap_or(default.swipe_air),
+      hold_air: air_strings(&v["handHoldAir"]).unwrap_or(default.hold_air),
+      gestures: v["handGestures"]
+        .as_array()
+        .map(|gestures| {
+          gestures
+            .iter()
+            .filter_map(GestureBinding::from_serde_value)
+            .collect()
+        })
+        .unwrap_or_default(),
+    }
+  }
+
+  /// Config keys of this calibration, in the same format as read by
+  /// `from_serde_value`.
+  pub fn to_serde_value(&self) -> Value {
+    json!({
+      "handZoneTop": self.zone_top,
+      "handZoneBottom": self.zone_bottom,
+      "handZoneHeights": self.zone_heights,
+      "handHysteresis": self.hysteresis,
+      "handMinConfidence": self.min_confidence,
+      "handModel": self.model.as_str(),
+      "handDetectionConfidence": self.detection_confidence,
+      "handPresenceConfidence": self.presence_confidence,
+      "handTrackingConfidence": self.tracking_confidence,
+      "handSourceTimeoutMs": self.source_timeout_ms,
+      "handCameraRotation": self.camera.rotation,
+      "handCameraMirror": self.camera.mirror,
+      "handSources": self
+        .sources
+        .iter()
+        .map(|(name, source)| (name.clone(), source.to_serde_value()))
+        .collect::<Map<String, Value>>(),
+      "handFilterMinCutoff": self.filter_min_cutoff,
+      "handFilterBeta": self.filter_beta,
+      "handFilterDCutoff": self.filter_d_cutoff,
+      "handActions": match self.actions {
+        HandActionMode::Off => "off",
+        HandActionMode::Add => "add",
+        HandActionMode::Only => "only",
+      },
+      "handFlickSpeed": self.flick_speed,
+      "handSwipeSpeed": self.swipe_speed,
+      "handHoldSpeed": self.hold_speed,
+      "handHoldMs": self.hold_ms,
+      "handPulseMs": self.pulse_ms,
+      "handFlickAir": air_indices(&self.flick_air),
+      "handSwipeAir": air_indices(&self.swipe_air),
+      "handHoldAir": air_indices(&self.hold_air),
+      "handGestures": self
+        .gestures
+        .iter()
+        .map(GestureBinding::to_serde_value)
+        .collect::<Vec<Value>>(),
+    })
+  }
+
+  /// Calibration of a hand tracker source, the main one when the source is
+  /// unnamed or has none of its own.
+  pub fn source(&self, name: Option<&str>) -> SourceCalibration {
+    match name.and_then(|name| self.sources.get(name)) {
+      Some(source) => *source,
+      None => SourceCalibration {
+        camera: self.camera,
+        zone_top: self.zone_top,
+        zone_bottom: self.zone_bottom,
+      },
+    }
+  }
+
+  /// The same calibration for frames that are already upright, as fused by
+  /// `HandFusion`.
+  pub fn upright(&self) -> Self {
+    Self {
+      camera: CameraOrientation::default(),
+      ..self.clone()
+    }
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    let unit = |x: f64| (0.0..=1.0).contains(&x);
+    for source in std::iter::once(self.source(None)).chain(self.sources.values().copied()) {
+      if !(unit(source.zone_top)
+        && unit(source.zone_bottom)
+        && source.zone_top < source.zone_bottom)
+      {
+        return Err("Zone bounds must satisfy 0 <= top < bottom <= 1".to_string());
+      }
+      if !source.camera.is_valid() {
+        return Err("Camera rotation must be 0, 90, 180 or 270".to_string());
+      }
+    }
+    if !(self.hysteresis >= 0.0 && self.hysteresis < 1.0) {
+      return Err("Hysteresis must be between 0 and 1".to_string());
+    }
+    for confidence in [
+      self.min_confidence,
+      self.detection_confidence,
+      self.presence_confidence,
+      self.tracking_confidence,
+    ] {
+      if !unit(confidence) {
+        return Err("Confidences must be between 0 and 1".to_string());
+      }
+    }
+    if self.source_timeout_ms.is_nan() || self.source_timeout_ms <= 0.0 {
+      return Err("Source timeout must be positive".to_string());
+    }
+    if !(self.filter_min_cutoff > 0.0 && self.filter_beta >= 0.0 && self.filter_d_cutoff > 0.0) {
+      return Err("Filter cutoffs must be positive".to_string());
+    }
+    if !(self.flick_speed > 0.0 && self.swipe_speed > 0.0 && self.hold_speed > 0.0) {
+      return Err("Action speeds must be positive".to_string());
+    }
+    if !(self.hold_ms >= 0.0 && self.pulse_ms > 0.0) {
+      return Err("Action times must be positive".to_string());
+    }
+    if !self.gestures.iter().all(|g| g.hold_ms >= 0.0) {
+      return Err("Gesture hold times must be positive".to_string());
+    }
+    Ok(())
+  }
+}
+
+impl Default for HandTrackingConfig {
+  fn default() -> Self {
+    Self {
+      zone_top: 0.0,
+      zone_bottom: 0.4,
+      zone_heights: [1.0; 6],
+      hysteresis: 0.15,
+      min_confidence: 0.5,
+      model: HandModel::FullFloat16,
+      detection_confidence: 0.25,
+      presence_confidence: 0.5,
+      tracking_confidence: 0.1,
+      source_timeout_ms: 250.0,
+      camera: CameraOrientation::default(),
+      sources: BTreeMap::new(),
+      filter_min_cutoff: 1.5,
+      filter_beta: 2.0,
+      filter_d_cutoff: 1.0,
+      actions: HandActionMode::Off,
+      flick_speed: 1.5,
+      swipe_speed: 1.5,
+      hold_speed: 0.2,
+      hold_ms: 250.0,
+      pulse_ms: 80.0,
+      flick_air: [1; 6],
+      swipe_air: [1; 6],
+      hold_air: [1; 6],
+      gestures: vec![],
+    }
+  }
+}
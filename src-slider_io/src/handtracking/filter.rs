@@ -0,0 +1,109 @@
+use std::f64::consts::PI;
+
+/// One-Euro low pass filter, smoothing jitter at low speeds while keeping lag
+/// low at high speeds. See https://gery.casiez.net/1euro/
+pub struct OneEuroFilter {
+  min_cutoff: f64,
+  beta: f64,
+  d_cutoff: f64,
+  /// Last filtered value, its derivative and timestamp in seconds.
+  last: Option<(f64, f64, f64)>,
+}
+
+fn alpha(cutoff: f64, dt: f64) -> f64 {
+  let tau = 1.0 / (2.0 * PI * cutoff);
+  1.0 / (1.0 + tau / dt)
+}
+
+impl OneEuroFilter {
+  pub fn new(min_cutoff: f64, beta: f64, d_cutoff: f64) -> Self {
+    Self {
+      min_cutoff,
+      beta,
+      d_cutoff,
+      last: None,
+    }
+  }
+
+  /// Filters a sample taken at time `t` in seconds.
+  pub fn filter(&mut self, x: f64, t: f64) -> f64 {
+    let (last_x, last_dx, last_t) = match self.last {
+      Some(last) => last,
+      None => {
+        self.last = Some((x, 0.0, t));
+        return x;
+      }
+    };
+
+    let dt = t - last_t;
+    if dt <= 0.0 {
+      return last_x;
+    }
+
+    let dx = (x - last_x) / dt;
+    let a_d = alpha(self.d_cutoff, dt);
+    let dx = a_d * dx + (1.0 - a_d) * last_dx;
+
+    let cutoff = self.min_cutoff + self.beta * dx.abs();
+    let a = alpha(cutoff, dt);
+    let x = a * x + (1.0 - a) * last_x;
+
+    self.last = Some((x, dx, t));
+    x
+  }
+
+  /// Smoothed rate of change per second at the last sample.
+  pub fn velocity(&self) -> f64 {
+    self.last.map_or(0.0, |(_, dx, _)| dx)
+  }
+
+  /// Forgets history, so the next sample is passed through unfiltered.
+  pub fn reset(&mut self) {
+    self.last = None;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn first_sample_passes_through() {
+    let mut f = OneEuroFilter::new(1.0, 0.0, 1.0);
+    assert_eq!(f.filter(0.3, 0.0), 0.3);
+  }
+
+  #[test]
+  fn smooths_jitter() {
+    let mut f = OneEuroFilter::new(1.0, 0.0, 1.0);
+    f.filter(0.5, 0.0);
+    for i in 1..60 {
+      let noise = if i % 2 == 0 { 0.02 } else { -0.02 };
+      let y = f.filter(0.5 + noise, i as f64 / 60.0);
+      assert!((y - 0.5).abs() < 0.01, "{} at {}", y, i);
+    }
+  }
+
+  #[test]
+  fn follows_fast_moves_with_beta() {
+    let mut slow = OneEuroFilter::new(1.0, 0.0, 1.0);
+    let mut fast = OneEuroFilter::new(1.0, 5.0, 1.0);
+    slow.filter(0.8, 0.0);
+    fast.filter(0.8, 0.0);
+    let (mut s, mut f) = (0.0, 0.0);
+    for i in 1..6 {
+      let t = i as f64 / 60.0;
+      s = slow.filter(0.2, t);
+      f = fast.filter(0.2, t);
+    }
+    assert!((f - 0.2).abs() < (s - 0.2).abs());
+  }
+
+  #[test]
+  fn reset_forgets_history() {
+    let mut f = OneEuroFilter::new(1.0, 0.0, 1.0);
+    f.filter(0.9, 0.0);
+    f.reset();
+    assert_eq!(f.filter(0.1, 1.0), 0.1);
+  }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+use crate::handtracking::camera::CameraOrientation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Handedness {
+  Left,
+  Right,
+}
+
+/// One hand reported by the hand tracker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandObservation {
+  /// Hand centre, normalized to the camera image with the origin at the top
+  /// left.
+  pub x: f64,
+  pub y: f64,
+  /// Missing when the tracker could not tell which hand this is.
+  #[serde(default)]
+  pub hand: Option<Handedness>,
+  /// Handedness confidence from 0 to 1.
+  #[serde(default = "full_confidence")]
+  pub score: f64,
+  /// The 21 MediaPipe hand landmarks as x, y and z, only sent when gestures
+  /// are in use.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub landmarks: Vec<[f64; 3]>,
+}
+
+fn full_confidence() -> f64 {
+  1.0
+}
+
+/// All hands seen in one camera frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandFrame {
+  /// Capture time in milliseconds on the client's clock.
+  pub t: f64,
+  pub hands: Vec<HandObservation>,
+  /// Name of the hand tracker source, set on the page when it has its own
+  /// calibration in `handSources`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source: Option<String>,
+}
+
+impl HandFrame {
+  /// Parses the JSON body of a hand tracker `h` message.
+  pub fn from_json(s: &str) -> Option<Self> {
+    let frame: Self = serde_json::from_str(s).ok()?;
+    match frame.t.is_finite()
+      && frame.hands.iter().all(|h| {
+        h.x.is_finite()
+          && h.y.is_finite()
+          && h.score.is_finite()
+          && h.landmarks.iter().flatten().all(|c| c.is_finite())
+      }) {
+      true => Some(frame),
+      false => None,
+    }
+  }
+
+  /// The first confident hand seen on each side, left first, turned upright.
+  /// Hands of unknown side count as the right hand.
+  pub fn by_side(
+    &self,
+    min_confidence: f64,
+    camera: &CameraOrientation,
+  ) -> [Option<HandObservation>; 2] {
+    let mut sides = [None, None];
+    for hand in self.hands.iter() {
+      if hand.score < min_confidence {
+        continue;
+      }
+      let hand = camera.orient(hand);
+      let idx = match hand.hand {
+        Some(Handedness::Left) => 0,
+        _ => 1,
+      };
+      if sides[idx].is_none() {
+        sides[idx] = Some(hand);
+      }
+    }
+    sides
+  }
+}
@@ -0,0 +1,262 @@
+use crate::handtracking::{
+  config::{HandTrackingConfig, SourceCalibration},
+  frame::{HandFrame, HandObservation, Handedness},
+};
+
+/// Latest hands reported by one hand tracker client.
+struct HandSource {
+  id: usize,
+  /// Server time of the last frame in milliseconds.
+  received: f64,
+  /// Hands by side, upright and in the main air zone, left first.
+  hands: [Option<HandObservation>; 2],
+}
+
+/// Fuses the hands seen by several hand tracker clients, such as a webcam in
+/// front and a phone at the side, into one frame. Each hand is averaged over
+/// the sources that see it, weighted by confidence and by how recently the
+/// source reported. Sources that stop reporting are dropped.
+///
+/// Each source is turned upright with its own calibration from `handSources`
+/// and its air zone is stretched onto the main one before averaging, so the
+/// fused frame is upright and uses the main zone. Times are on the server
+/// clock since every client has its own.
+pub struct HandFusion {
+  config: HandTrackingConfig,
+  sources: Vec<HandSource>,
+}
+
+impl HandFusion {
+  pub fn new(config: &HandTrackingConfig) -> Self {
+    Self {
+      config: config.clone(),
+      sources: vec![],
+    }
+  }
+
+  /// Moves an upright hand seen by a source from the source's air zone to the
+  /// same place in the main one.
+  fn normalize(&self, source: &SourceCalibration, hand: HandObservation) -> HandObservation {
+    let main = self.config.source(None);
+    let scale = (main.zone_bottom - main.zone_top) / (source.zone_bottom - source.zone_top);
+    HandObservation {
+      y: main.zone_top + (hand.y - source.zone_top) * scale,
+      ..hand
+    }
+  }
+
+  /// Adds a frame from a client at server time `now` in milliseconds and
+  /// returns the fused frame.
+  pub fn update(&mut self, id: usize, frame: &HandFrame, now: f64) -> HandFrame {
+    let source = self.config.source(frame.source.as_deref());
+    let hands = frame
+      .by_side(self.config.min_confidence, &source.camera)
+      .map(|hand| hand.map(|hand| self.normalize(&source, hand)));
+    match self.sources.iter_mut().find(|s| s.id == id) {
+      Some(source) => {
+        source.received = now;
+        source.hands = hands;
+      }
+      None => self.sources.push(HandSource {
+        id,
+        received: now,
+        hands,
+      }),
+    }
+    self.expire(now);
+    self.fuse(now)
+  }
+
+  /// Removes a client, returning the fused frame of the remaining ones if it
+  /// was a source.
+  pub fn remove(&mut self, id: usize, now: f64) -> Option<HandFrame> {
+    let count = self.sources.len();
+    self.sources.retain(|s| s.id != id);
+    match self.sources.len() < count {
+      true => Some(self.fuse(now)),
+      false => None,
+    }
+  }
+
+  /// Drops sources that stopped reporting, returning the fused frame of the
+  /// remaining ones if any were dropped.
+  pub fn expire(&mut self, now: f64) -> Option<HandFrame> {
+    let count = self.sources.len();
+    let timeout = self.config.source_timeout_ms;
+    self.sources.retain(|s| now - s.received < timeout);
+    match self.sources.len() < count {
+      true => Some(self.fuse(now)),
+      false => None,
+    }
+  }
+
+  pub fn source_count(&self) -> usize {
+    self.sources.len()
+  }
+
+  fn fuse(&self, now: f64) -> HandFrame {
+    let mut hands = vec![];
+    for (side, handedness) in [Handedness::Left, Handedness::Right].iter().enumerate() {
+      let seen = self
+        .sources
+        .iter()
+        .filter_map(|s| {
+          let hand = s.hands[side].as_ref()?;
+          let freshness = 1.0 - (now - s.received) / self.config.source_timeout_ms;
+          Some((hand, hand.score.max(1e-3) * freshness.max(1e-3)))
+        })
+        .collect::<Vec<_>>();
+      if seen.is_empty() {
+        continue;
+      }
+
+      let total: f64 = seen.iter().map(|(_, w)| w).sum();
+      let (best, _) = seen.iter().max_by(|(_, a), (_, b)| a.total_cmp(b)).unwrap();
+      hands.push(HandObservation {
+        x: seen.iter().map(|(h, w)| h.x * w).sum::<f64>() / total,
+        y: seen.iter().map(|(h, w)| h.y * w).sum::<f64>() / total,
+        hand: Some(*handedness),
+        score: seen.iter().map(|(h, _)| h.score).fold(0.0, f64::max),
+        // Landmarks of different cameras cannot be mixed
+        landmarks: best.landmarks.clone(),
+      });
+    }
+
+    HandFrame {
+      t: now,
+      hands,
+      source: None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config() -> HandTrackingConfig {
+    HandTrackingConfig {
+      min_confidence: 0.5,
+      source_timeout_ms: 200.0,
+      ..HandTrackingConfig::default()
+    }
+  }
+
+  fn frame(hands: &[(Handedness, f64, f64)]) -> HandFrame {
+    HandFrame {
+      // Client clocks are ignored
+      t: 123456.0,
+      hands: hands
+        .iter()
+        .map(|(hand, y, score)| HandObservation {
+          x: 0.5,
+          y: *y,
+          hand: Some(*hand),
+          score: *score,
+          landmarks: vec![],
+        })
+        .collect(),
+      source: None,
+    }
+  }
+
+  fn y(frame: &HandFrame, side: Handedness) -> Option<f64> {
+    frame
+      .hands
+      .iter()
+      .find(|h| h.hand == Some(side))
+      .map(|h| (h.y * 1e9).round() / 1e9)
+  }
+
+  #[test]
+  fn single_source_passes_through() {
+    let mut fusion = HandFusion::new(&config());
+    let fused = fusion.update(0, &frame(&[(Handedness::Left, 0.3, 0.9)]), 1000.0);
+    assert_eq!(fused.t, 1000.0);
+    assert_eq!(y(&fused, Handedness::Left), Some(0.3));
+    assert_eq!(y(&fused, Handedness::Right), None);
+  }
+
+  #[test]
+  fn weights_by_confidence_and_freshness() {
+    let mut fusion = HandFusion::new(&config());
+    fusion.update(0, &frame(&[(Handedness::Right, 0.2, 1.0)]), 0.0);
+    // Equally fresh, twice as confident
+    let fused = fusion.update(1, &frame(&[(Handedness::Right, 0.5, 0.5)]), 0.0);
+    assert_eq!(y(&fused, Handedness::Right), Some(0.3));
+
+    // Source 0 is now half way to its timeout, so both weigh the same
+    let fused = fusion.update(1, &frame(&[(Handedness::Right, 0.4, 0.5)]), 100.0);
+    assert_eq!(y(&fused, Handedness::Right), Some(0.3));
+  }
+
+  #[test]
+  fn ignores_unconfident_hands_and_sources_without_the_hand() {
+    let mut fusion = HandFusion::new(&config());
+    fusion.update(0, &frame(&[(Handedness::Left, 0.3, 0.9)]), 0.0);
+    fusion.update(1, &frame(&[]), 0.0);
+    let fused = fusion.update(2, &frame(&[(Handedness::Left, 0.9, 0.2)]), 0.0);
+    assert_eq!(y(&fused, Handedness::Left), Some(0.3));
+  }
+
+  #[test]
+  fn drops_stale_and_removed_sources() {
+    let mut fusion = HandFusion::new(&config());
+    fusion.update(0, &frame(&[(Handedness::Left, 0.3, 0.9)]), 0.0);
+    fusion.update(1, &frame(&[(Handedness::Right, 0.6, 0.9)]), 150.0);
+    assert!(fusion.expire(150.0).is_none());
+
+    let fused = fusion.expire(200.0).unwrap();
+    assert_eq!(fusion.source_count(), 1);
+    assert_eq!(y(&fused, Handedness::Left), None);
+    assert_eq!(y(&fused, Handedness::Right), Some(0.6));
+
+    assert_eq!(fusion.remove(0, 250.0).map(|f| f.hands.len()), None);
+    assert_eq!(fusion.remove(1, 250.0).map(|f| f.hands.len()), Some(0));
+  }
+
+  #[test]
+  fn calibrates_each_source_before_fusing() {
+    let mut config = config();
+    config.zone_top = 0.0;
+    config.zone_bottom = 0.4;
+    // The phone at the side is held sideways and sees the zone lower down
+    config.sources = HandTrackingConfig::from_serde_value(&serde_json::json!({
+      "handSources": {
+        "side": {
+          "handCameraRotation": 90,
+          "handZoneTop": 0.2,
+          "handZoneBottom": 1.0,
+        }
+      }
+    }))
+    .sources;
+    let mut fusion = HandFusion::new(&config);
+
+    fusion.update(0, &frame(&[(Handedness::Left, 0.2, 0.9)]), 0.0);
+    // Rotated 90 degrees, the raw x of the sideways image is the upright y.
+    // Half way down the side zone is half way down the main one.
+    let side = HandFrame {
+      source: Some("side".to_string()),
+      hands: vec![HandObservation {
+        x: 0.6,
+        y: 0.5,
+        ..frame(&[(Handedness::Left, 0.0, 0.9)]).hands[0].clone()
+      }],
+      ..frame(&[])
+    };
+    let fused = fusion.update(1, &side, 0.0);
+    assert_eq!(y(&fused, Handedness::Left), Some(0.2));
+
+    // Unknown sources use the main calibration
+    let fused = fusion.update(
+      1,
+      &HandFrame {
+        source: Some("other".to_string()),
+        ..frame(&[(Handedness::Left, 0.4, 0.9)])
+      },
+      0.0,
+    );
+    assert_eq!(y(&fused, Handedness::Left), Some(0.3));
+  }
+}
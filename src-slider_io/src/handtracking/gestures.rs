@@ -0,0 +1,486 @@
+use log::warn;
+use serde_json::{json, Value};
+
+use crate::{
+  handtracking::{
+    camera::CameraOrientation,
+    config::HandTrackingConfig,
+    frame::{HandFrame, HandObservation},
+  },
+  meta::config::MetaAction,
+};
+
+/// Landmark index of the wrist.
+const WRIST: usize = 0;
+/// Tip and middle joint landmark indices of the index, middle, ring and pinky
+/// fingers. The thumb is left out as it barely moves between an open palm and
+/// a fist from most camera angles.
+const FINGERS: [(usize, usize); 4] = [(8, 6), (12, 10), (16, 14), (20, 18)];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+  /// Both hands above the top of the air zone, out of the way of playing.
+  BothRaised,
+  /// Any hand with all four fingers extended.
+  OpenPalm,
+  /// Any hand with all four fingers curled.
+  Fist,
+}
+
+impl Gesture {
+  pub fn from_str(s: &str) -> Option<Self> {
+    match s {
+      "both-raised" => Some(Gesture::BothRaised),
+      "open-palm" => Some(Gesture::OpenPalm),
+      "fist" => Some(Gesture::Fist),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Gesture::BothRaised => "both-raised",
+      Gesture::OpenPalm => "open-palm",
+      Gesture::Fist => "fist",
+    }
+  }
+
+  fn default_hold_ms(&self) -> f64 {
+    match self {
+      Gesture::BothRaised => 2000.0,
+      _ => 1000.0,
+    }
+  }
+}
+
+/// What a recognised gesture does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GestureTarget {
+  /// Holds an extra button for as long as the gesture is held.
+  Extra(usize),
+  /// Triggers a meta action once each time the gesture is made.
+  Meta(MetaAction),
+}
+
+impl GestureTarget {
+  pub fn from_str(s: &str) -> Option<Self> {
+    if let Some(idx) = s.strip_prefix("extra") {
+      return match idx.parse::<usize>().ok()? {
+        idx if idx < 3 => Some(GestureTarget::Extra(idx)),
+        _ => None,
+      };
+    }
+    MetaAction::from_str(s).map(GestureTarget::Meta)
+  }
+
+  pub fn name(&self) -> String {
+    match self {
+      GestureTarget::Extra(idx) => format!("extra{}", idx),
+      GestureTarget::Meta(action) => action.as_str().to_string(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GestureBinding {
+  pub gesture: Gesture,
+  pub target: GestureTarget,
+  /// How long the gesture must be held before it counts, in milliseconds.
+  pub hold_ms: f64,
+}
+
+impl GestureBinding {
+  pub fn from_serde_value(v: &Value) -> Option<Self> {
+    let gesture = Gesture::from_str(v["gesture"].as_str()?)?;
+    Some(Self {
+      gesture,
+      target: GestureTarget::from_str(v["action"].as_str()?)?,
+      hold_ms: v["holdMs"]
+        .as_f64()
+        .unwrap_or_else(|| gesture.default_hold_ms()),
+    })
+  }
+
+  pub fn to_serde_value(&self) -> Value {
+    json!({
+      "gesture": self.gesture.as_str(),
+      "action": self.target.name(),
+      "holdMs": self.hold_ms,
+    })
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HandShape {
+  Open,
+  Fist,
+}
+
+fn distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+  a.iter()
+    .zip(b.iter())
+    .map(|(a, b)| (a - b) * (a - b))
+    .sum::<f64>()
+    .sqrt()
+}
+
+/// Classifies a hand from its landmarks. A finger is extended when its tip is
+/// clearly further from the wrist than its middle joint, and curled when the
+/// tip folded back closer to the wrist than the joint.
+fn hand_shape(hand: &HandObservation) -> Option<HandShape> {
+  let points = &hand.landmarks;
+  if points.len() < 21 {
+    return None;
+  }
+
+  let wrist = &points[WRIST];
+  let ratios = FINGERS
+    .iter()
+    .map(|(tip, joint)| distance(&points[*tip], wrist) / distance(&points[*joint], wrist));
+
+  let (mut extended, mut curled) = (0, 0);
+  for ratio in ratios {
+    if ratio > 1.15 {
+      extended += 1;
+    } else if ratio < 1.0 {
+      curled += 1;
+    }
+  }
+
+  match (extended, curled) {
+    (4, _) => Some(HandShape::Open),
+    (_, 4) => Some(HandShape::Fist),
+    _ => None,
+  }
+}
+
+/// What recognised gestures do this frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GestureOutput {
+  /// Extra buttons held by gestures.
+  pub extra: [u8; 3],
+  /// Meta actions to trigger, each reported once per gesture.
+  pub actions: Vec<MetaAction>,
+}
+
+struct BindingState {
+  binding: GestureBinding,
+  /// When the gesture started, in seconds.
+  since: Option<f64>,
+  /// Set once the gesture was held long enough, until it is released.
+  fired: bool,
+}
+
+/// Recognises gestures from hand landmarks and maps them to their bindings.
+pub struct GestureRecognizer {
+  min_confidence: f64,
+  camera: CameraOrientation,
+  zone_top: f64,
+  bindings: Vec<BindingState>,
+}
+
+impl GestureRecognizer {
+  pub fn new(config: &HandTrackingConfig) -> Self {
+    let both_raised = config
+      .gestures
+      .iter()
+      .any(|binding| binding.gesture == Gesture::BothRaised);
+    if both_raised && config.zone_top <= 0.0 {
+      warn!("The both-raised gesture needs room above the air zone, set handZoneTop above 0");
+    }
+
+    Self {
+      min_confidence: config.min_confidence,
+      camera: config.camera,
+      zone_top: config.zone_top,
+      bindings: config
+        .gestures
+        .iter()
+        .map(|binding| BindingState {
+          binding: binding.clone(),
+          since: None,
+          fired: false,
+        })
+        .collect(),
+    }
+  }
+
+  fn is_active(
+    &self,
+    gesture: Gesture,
+    frame: &HandFrame,
+    seen: &[Option<HandObservation>; 2],
+  ) -> bool {
+    let any_shape = |shape: HandShape| {
+      frame
+        .hands
+        .iter()
+        .filter(|hand| hand.score >= self.min_confidence)
+        .any(|hand| hand_shape(hand) == Some(shape))
+    };
+
+    // Hands in the air zone are playing air notes, only above it they are
+    // raised on purpose
+    let raised = |hand: &HandObservation| hand.y < self.zone_top;
+
+    match gesture {
+      Gesture::BothRaised => match seen {
+        [Some(left), Some(right)] => raised(left) && raised(right),
+        // Without handedness both hands can land on the same side, so take
+        // the two most confident hands instead
+        _ => {
+          let mut hands = frame
+            .hands
+            .iter()
+            .filter(|hand| hand.score >= self.min_confidence)
+            .collect::<Vec<&HandObservation>>();
+          hands.sort_by(|a, b| b.score.total_cmp(&a.score));
+          hands.len() >= 2
+            && hands[..2]
+              .iter()
+              .all(|hand| raised(&self.camera.orient(hand)))
+        }
+      },
+      Gesture::OpenPalm => any_shape(HandShape::Open),
+      Gesture::Fist => any_shape(HandShape::Fist),
+    }
+  }
+
+  pub fn update(&mut self, frame: &HandFrame) -> GestureOutput {
+    let mut output = GestureOutput::default();
+    if self.bindings.is_empty() {
+      return output;
+    }
+
+    let t = frame.t / 1000.0;
+    let seen = frame.by_side(self.min_confidence, &self.camera);
+    let active = self
+      .bindings
+      .iter()
+      .map(|state| self.is_active(state.binding.gesture, frame, &seen))
+      .collect::<Vec<bool>>();
+
+    for (state, active) in self.bindings.iter_mut().zip(active) {
+      if !active {
+        state.since = None;
+        state.fired = false;
+        continue;
+      }
+
+      let since = *state.since.get_or_insert(t);
+      if (t - since) * 1000.0 < state.binding.hold_ms {
+        continue;
+      }
+
+      match state.binding.target {
+        GestureTarget::Extra(idx) => output.extra[idx] = 1,
+        GestureTarget::Meta(action) => {
+          if !state.fired {
+            output.actions.push(action);
+          }
+        }
+      }
+      state.fired = true;
+    }
+    output
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::handtracking::frame::Handedness;
+
+  /// Landmarks of an upright hand with the fingers pointing up, either
+  /// stretched out or curled back towards the palm.
+  fn landmarks(open: bool) -> Vec<[f64; 3]> {
+    let mut points = vec![[0.5, 0.5, 0.0]; 21];
+    for (finger, (tip, joint)) in FINGERS.iter().enumerate() {
+      let x = 0.45 + finger as f64 * 0.03;
+      points[joint - 1] = [x, 0.42, 0.0];
+      points[*joint] = [x, 0.38, 0.0];
+      points[tip - 1] = [x, if open { 0.34 } else { 0.41 }, 0.0];
+      points[*tip] = [x, if open { 0.3 } else { 0.44 }, 0.0];
+    }
+    points
+  }
+
+  fn hand(side: Handedness, y: f64, landmarks: Vec<[f64; 3]>) -> HandObservation {
+    HandObservation {
+      x: 0.5,
+      y,
+      hand: Some(side),
+      score: 1.0,
+      landmarks,
+    }
+  }
+
+  fn config(gestures: Value) -> HandTrackingConfig {
+    HandTrackingConfig {
+      zone_top: 0.1,
+      zone_bottom: 0.4,
+      gestures: gestures
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(GestureBinding::from_serde_value)
+        .collect(),
+      ..HandTrackingConfig::default()
+    }
+  }
+
+  #[test]
+  fn parses_bindings() {
+    let config = config(json!([
+      { "gesture": "both-raised", "action": "pause-output" },
+      { "gesture": "fist", "action": "extra2", "holdMs": 300 },
+      { "gesture": "fist", "action": "extra3" },
+      { "gesture": "wave", "action": "extra0" },
+    ]));
+    assert_eq!(
+      config.gestures,
+      vec![
+        GestureBinding {
+          gesture: Gesture::BothRaised,
+          target: GestureTarget::Meta(MetaAction::PauseOutput),
+          hold_ms: 2000.0,
+        },
+        GestureBinding {
+          gesture: Gesture::Fist,
+          target: GestureTarget::Extra(2),
+          hold_ms: 300.0,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn classifies_hand_shapes() {
+    let open = hand(Handedness::Left, 0.5, landmarks(true));
+    let fist = hand(Handedness::Left, 0.5, landmarks(false));
+    assert_eq!(hand_shape(&open), Some(HandShape::Open));
+    assert_eq!(hand_shape(&fist), Some(HandShape::Fist));
+    assert_eq!(hand_shape(&hand(Handedness::Left, 0.5, vec![])), None);
+  }
+
+  #[test]
+  fn meta_action_fires_once_after_hold() {
+    let mut recognizer = GestureRecognizer::new(&config(json!([
+      { "gesture": "both-raised", "action": "pause-output" },
+    ])));
+    let raised = |t: f64| HandFrame {
+      t,
+      hands: vec![
+        hand(Handedness::Left, 0.05, vec![]),
+        hand(Handedness::Right, 0.08, vec![]),
+      ],
+      source: None,
+    };
+
+    assert_eq!(recognizer.update(&raised(0.0)).actions, vec![]);
+    assert_eq!(recognizer.update(&raised(1900.0)).actions, vec![]);
+    assert_eq!(
+      recognizer.update(&raised(2000.0)).actions,
+      vec![MetaAction::PauseOutput]
+    );
+    assert_eq!(recognizer.update(&raised(3000.0)).actions, vec![]);
+
+    // Lowering one hand releases the gesture
+    let lowered = HandFrame {
+      t: 3100.0,
+      hands: vec![hand(Handedness::Left, 0.05, vec![])],
+      source: None,
+    };
+    recognizer.update(&lowered);
+    assert_eq!(recognizer.update(&raised(3200.0)).actions, vec![]);
+    assert_eq!(
+      recognizer.update(&raised(5200.0)).actions,
+      vec![MetaAction::PauseOutput]
+    );
+  }
+
+  #[test]
+  fn air_hold_is_not_raised() {
+    let mut recognizer = GestureRecognizer::new(&config(json!([
+      { "gesture": "both-raised", "action": "pause-output" },
+    ])));
+    // Both hands held in the air zone for a long air note
+    let holding = |t: f64| HandFrame {
+      t,
+      hands: vec![
+        hand(Handedness::Left, 0.2, vec![]),
+        hand(Handedness::Right, 0.3, vec![]),
+      ],
+      source: None,
+    };
+    for t in (0..=50).map(|i| i as f64 * 100.0) {
+      assert_eq!(recognizer.update(&holding(t)).actions, vec![]);
+    }
+  }
+
+  #[test]
+  fn both_raised_without_handedness() {
+    let mut recognizer = GestureRecognizer::new(&config(json!([
+      { "gesture": "both-raised", "action": "toggle-air", "holdMs": 0 },
+    ])));
+    let unknown = |y: f64, score: f64| HandObservation {
+      hand: None,
+      score,
+      ..hand(Handedness::Left, y, vec![])
+    };
+    let frame = |hands: Vec<HandObservation>| HandFrame {
+      t: 0.0,
+      hands,
+      source: None,
+    };
+
+    assert_eq!(
+      recognizer
+        .update(&frame(vec![unknown(0.05, 0.9), unknown(0.08, 0.8)]))
+        .actions,
+      vec![MetaAction::ToggleAir]
+    );
+    recognizer.update(&frame(vec![]));
+
+    // A low third hand of little confidence does not count
+    assert_eq!(
+      recognizer
+        .update(&frame(vec![
+          unknown(0.9, 0.6),
+          unknown(0.05, 0.9),
+          unknown(0.08, 0.8),
+        ]))
+        .actions,
+      vec![MetaAction::ToggleAir]
+    );
+    recognizer.update(&frame(vec![]));
+
+    assert_eq!(
+      recognizer
+        .update(&frame(vec![unknown(0.05, 0.9), unknown(0.6, 0.8)]))
+        .actions,
+      vec![]
+    );
+    assert_eq!(
+      recognizer.update(&frame(vec![unknown(0.05, 0.9)])).actions,
+      vec![]
+    );
+  }
+
+  #[test]
+  fn extra_is_held_with_gesture() {
+    let mut recognizer = GestureRecognizer::new(&config(json!([
+      { "gesture": "fist", "action": "extra1", "holdMs": 500 },
+    ])));
+    let frame = |t: f64, open: bool| HandFrame {
+      t,
+      hands: vec![hand(Handedness::Right, 0.6, landmarks(open))],
+      source: None,
+    };
+
+    assert_eq!(recognizer.update(&frame(0.0, false)).extra, [0; 3]);
+    assert_eq!(recognizer.update(&frame(500.0, false)).extra, [0, 1, 0]);
+    assert_eq!(recognizer.update(&frame(800.0, false)).extra, [0, 1, 0]);
+    assert_eq!(recognizer.update(&frame(900.0, true)).extra, [0; 3]);
+  }
+}
@@ -0,0 +1,662 @@
+// Regions and palms are only used by the ONNX models, tested without them
+#![cfg_attr(not(feature = "native-hand-tracking"), allow(dead_code))]
+
+use image::RgbImage;
+
+use crate::handtracking::frame::HandObservation;
+
+/// Finds hands in video frames for native hand tracking.
+pub trait LandmarkModel: Send {
+  /// Hands seen in a frame, with positions normalized to the frame like the
+  /// ones sent by the hand tracker page.
+  fn detect(&mut self, image: &RgbImage) -> Result<Vec<HandObservation>, String>;
+}
+
+/// Part of a frame in pixels, which may reach past its edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Region {
+  x: f64,
+  y: f64,
+  size: f64,
+}
+
+impl Region {
+  /// The default region of a side, the square in the middle of that half of
+  /// the frame.
+  fn half(image: &RgbImage, side: usize) -> Self {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let size = (width / 2.0).min(height);
+    Self {
+      x: side as f64 * width / 2.0 + (width / 2.0 - size) / 2.0,
+      y: (height - size) / 2.0,
+      size,
+    }
+  }
+
+  /// The whole frame padded to a square, as the palm detector sees it.
+  fn frame(image: &RgbImage) -> Self {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let size = width.max(height);
+    Self {
+      x: (width - size) / 2.0,
+      y: (height - size) / 2.0,
+      size,
+    }
+  }
+
+  /// Square around landmarks in pixels, twice as large as their extent so
+  /// that the hand stays inside it until the next frame.
+  fn around(points: &[[f64; 3]]) -> Self {
+    let (mut x0, mut y0, mut x1, mut y1) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for [x, y, _] in points {
+      x0 = x0.min(*x);
+      y0 = y0.min(*y);
+      x1 = x1.max(*x);
+      y1 = y1.max(*y);
+    }
+    let size = (x1 - x0).max(y1 - y0).max(1.0) * 2.0;
+    Self {
+      x: (x0 + x1 - size) / 2.0,
+      y: (y0 + y1 - size) / 2.0,
+      size,
+    }
+  }
+
+  fn contains(&self, x: f64, y: f64) -> bool {
+    (self.x..self.x + self.size).contains(&x) && (self.y..self.y + self.size).contains(&y)
+  }
+
+  /// Colour of pixel `(u, v)` of the region scaled to `side` pixels, black
+  /// past the edges of the frame.
+  fn sample(&self, image: &RgbImage, side: usize, u: usize, v: usize) -> [u8; 3] {
+    let x = self.x + (u as f64 + 0.5) * self.size / side as f64;
+    let y = self.y + (v as f64 + 0.5) * self.size / side as f64;
+    match x >= 0.0 && y >= 0.0 && x < image.width() as f64 && y < image.height() as f64 {
+      true => image.get_pixel(x as u32, y as u32).0,
+      false => [0; 3],
+    }
+  }
+}
+
+/// Anchor grids of the palm detector, as their stride in input pixels and
+/// the number of anchors in each cell.
+const PALM_STRIDES: [(usize, usize); 2] = [(8, 2), (16, 6)];
+/// Hand region around a palm, relative to the palm box, as MediaPipe crops it
+/// for the landmark model.
+const PALM_REGION_SCALE: f64 = 2.6;
+/// Palms overlapping a better one by more than this are the same palm.
+const PALM_MAX_OVERLAP: f64 = 0.3;
+
+/// A palm found by the palm detector, normalized to its input.
+#[derive(Debug, Clone, PartialEq)]
+struct Palm {
+  x: f64,
+  y: f64,
+  width: f64,
+  height: f64,
+  wrist: [f64; 2],
+  /// Base of the middle finger.
+  middle: [f64; 2],
+  score: f64,
+}
+
+impl Palm {
+  fn overlap(&self, other: &Palm) -> f64 {
+    let span = |a: f64, a_size: f64, b: f64, b_size: f64| {
+      ((a + a_size / 2.0).min(b + b_size / 2.0) - (a - a_size / 2.0).max(b - b_size / 2.0)).max(0.0)
+    };
+    let both = span(self.x, self.width, other.x, other.width)
+      * span(self.y, self.height, other.y, other.height);
+    let either = self.width * self.height + other.width * other.height - both;
+    match either > 0.0 {
+      true => both / either,
+      false => 0.0,
+    }
+  }
+
+  /// Region of the hand in frame pixels, with the palm detector having seen
+  /// `input`. The palm box is moved towards the fingers and grown to take
+  /// the whole hand. Regions are not rotated, the landmark model gets the
+  /// hand as it is in the frame.
+  fn hand_region(&self, input: &Region) -> Region {
+    let size = self.width.max(self.height) * input.size;
+    let (dx, dy) = (
+      self.middle[0] - self.wrist[0],
+      self.middle[1] - self.wrist[1],
+    );
+    let len = dx.hypot(dy);
+    let (dx, dy) = match len > 0.0 {
+      true => (dx / len, dy / len),
+      false => (0.0, -1.0),
+    };
+    let x = input.x + self.x * input.size + dx * size / 2.0;
+    let y = input.y + self.y * input.size + dy * size / 2.0;
+    let size = size * PALM_REGION_SCALE;
+    Region {
+      x: x - size / 2.0,
+      y: y - size / 2.0,
+      size,
+    }
+  }
+}
+
+/// Anchor centres of a MediaPipe palm detector with a square input of `side`
+/// pixels, a multiple of 16: two per cell of the grid with a stride of 8 and
+/// six per cell of the grid with a stride of 16, 2016 for the usual 192 pixels.
+fn palm_anchors(side: usize) -> Vec<[f64; 2]> {
+  let mut anchors = vec![];
+  for (stride, per_cell) in PALM_STRIDES {
+    let cells = side / stride;
+    for y in 0..cells {
+      for x in 0..cells {
+        let centre = [
+          (x as f64 + 0.5) / cells as f64,
+          (y as f64 + 0.5) / cells as f64,
+        ];
+        for _ in 0..per_cell {
+          anchors.push(centre);
+        }
+      }
+    }
+  }
+  anchors
+}
+
+/// Decodes the palm detector outputs, 18 values per anchor for the box and
+/// 7 keypoints and one score logit per anchor, into the palms scoring at
+/// least `min_score` with overlapping ones removed, best first.
+fn decode_palms(
+  side: usize,
+  anchors: &[[f64; 2]],
+  boxes: &[f32],
+  scores: &[f32],
+  min_score: f64,
+) -> Vec<Palm> {
+  let side = side as f64;
+  let mut palms = anchors
+    .iter()
+    .zip(boxes.chunks(18))
+    .zip(scores)
+    .filter_map(|((anchor, b), logit)| {
+      let score = 1.0 / (1.0 + (-(*logit as f64).clamp(-100.0, 100.0)).exp());
+      if score.is_nan() || score < min_score {
+        return None;
+      }
+      let point = |idx: usize| {
+        [
+          anchor[0] + b[idx] as f64 / side,
+          anchor[1] + b[idx + 1] as f64 / side,
+        ]
+      };
+      let centre = point(0);
+      Some(Palm {
+        x: centre[0],
+        y: centre[1],
+        width: b[2] as f64 / side,
+        height: b[3] as f64 / side,
+        wrist: point(4),
+        middle: point(8),
+        score,
+      })
+    })
+    .collect::<Vec<_>>();
+  palms.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+  let mut kept: Vec<Palm> = vec![];
+  for palm in palms {
+    if kept.iter().all(|k| k.overlap(&palm) <= PALM_MAX_OVERLAP) {
+      kept.push(palm);
+    }
+  }
+  kept
+}
+
+#[cfg(feature = "native-hand-tracking")]
+pub use onnx::{OnnxLandmarkModel, OnnxPalmDetector};
+
+#[cfg(feature = "native-hand-tracking")]
+mod onnx {
+  use image::RgbImage;
+  use std::path::Path;
+  use tract_onnx::{prelude::*, tract_hir::infer::Factoid};
+
+  use super::{decode_palms, palm_anchors, LandmarkModel, Region};
+  use crate::handtracking::frame::{HandObservation, Handedness};
+
+  type Plan = SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
+
+  /// An ONNX model taking one square RGB image with values from 0 to 1,
+  /// channels first or last, run on the CPU.
+  struct ImageModel {
+    plan: Plan,
+    /// Width and height of the input image.
+    side: usize,
+    channels_first: bool,
+  }
+
+  impl ImageModel {
+    fn load(path: &Path) -> Result<Self, String> {
+      let error = |e: TractError| format!("{:?}: {}", path, e);
+
+      let model = tract_onnx::onnx().model_for_path(path).map_err(error)?;
+      let dims = model
+        .input_fact(0)
+        .map_err(error)?
+        .shape
+        .dims()
+        .map(|d| d.concretize().and_then(|d| d.as_i64()).map(|d| d as usize))
+        .collect::<Vec<_>>();
+      let (side, channels_first) = match dims[..] {
+        [_, Some(3), Some(h), Some(w)] if h == w => (h, true),
+        [_, Some(h), Some(w), Some(3)] if h == w => (h, false),
+        _ => {
+          return Err(format!(
+            "{:?}: expected a square RGB image input, got {:?}",
+            path, dims
+          ))
+        }
+      };
+      let shape = match channels_first {
+        true => [1, 3, side, side],
+        false => [1, side, side, 3],
+      };
+
+      let plan = model
+        .with_input_fact(0, f32::fact(shape).into())
+        .and_then(|model| model.into_optimized())
+        .and_then(|model| model.into_runnable())
+        .map_err(error)?;
+      Ok(Self {
+        plan,
+        side,
+        channels_first,
+      })
+    }
+
+    /// Runs the model on a region of the frame, returning its outputs.
+    fn run(&self, image: &RgbImage, region: &Region) -> Result<Vec<Vec<f32>>, String> {
+      let side = self.side;
+      let mut pixels = vec![[0; 3]; side * side];
+      for v in 0..side {
+        for u in 0..side {
+          pixels[v * side + u] = region.sample(image, side, u, v);
+        }
+      }
+      let input: Tensor = match self.channels_first {
+        true => tract_ndarray::Array4::from_shape_fn((1, 3, side, side), |(_, c, v, u)| {
+          pixels[v * side + u][c] as f32 / 255.0
+        })
+        .into(),
+        false => tract_ndarray::Array4::from_shape_fn((1, side, side, 3), |(_, v, u, c)| {
+          pixels[v * side + u][c] as f32 / 255.0
+        })
+        .into(),
+      };
+
+      let outputs = self
+        .plan
+        .run(tvec!(input.into()))
+        .map_err(|e| e.to_string())?;
+      outputs
+        .iter()
+        .map(|output| {
+          let view = output.to_array_view::<f32>().map_err(|e| e.to_string())?;
+          Ok(view.iter().copied().collect())
+        })
+        .collect()
+    }
+  }
+
+  /// A MediaPipe style palm detection model in ONNX format, such as an export
+  /// of `palm_detection_lite`. It takes the whole frame, padded to a square,
+  /// and outputs the box and 7 keypoints of each anchor as 18 values and the
+  /// score logit of each anchor, in either order.
+  pub struct OnnxPalmDetector {
+    model: ImageModel,
+    anchors: Vec<[f64; 2]>,
+    min_score: f64,
+  }
+
+  impl OnnxPalmDetector {
+    pub fn load(path: &Path, min_score: f64) -> Result<Self, String> {
+      let model = ImageModel::load(path)?;
+      if model.side % 16 != 0 {
+        return Err(format!(
+          "{:?}: expected an input size that is a multiple of 16, got {}",
+          path, model.side
+        ));
+      }
+      Ok(Self {
+        anchors: palm_anchors(model.side),
+        model,
+        min_score,
+      })
+    }
+
+    /// Regions of the hands around the palms in a frame, best first.
+    fn detect(&self, image: &RgbImage) -> Result<Vec<Region>, String> {
+      let input = Region::frame(image);
+      let outputs = self.model.run(image, &input)?;
+      let count = self.anchors.len();
+      let find = |len: usize| {
+        outputs
+          .iter()
+          .find(|o| o.len() == len)
+          .ok_or_else(|| format!("Palm model has no output of {} values", len))
+      };
+      let (boxes, scores) = (find(count * 18)?, find(count)?);
+      Ok(
+        decode_palms(
+          self.model.side,
+          &self.anchors,
+          boxes,
+          scores,
+          self.min_score,
+        )
+        .iter()
+        .map(|palm| palm.hand_region(&input))
+        .collect(),
+      )
+    }
+  }
+
+  /// A hand found by the model.
+  struct Detection {
+    /// Landmarks in frame pixels.
+    points: Vec<[f64; 3]>,
+    /// Probability that it is a right hand.
+    right: f64,
+  }
+
+  /// A MediaPipe style hand landmark model in ONNX format, run on the CPU. It
+  /// outputs the 21 landmarks as x, y and z in input pixels, the hand presence
+  /// and the probability that it is a right hand, in that order. Hands are
+  /// found by the palm detector when there is one, and otherwise looked for
+  /// in their half of the frame. Either way they are then followed around
+  /// where they were last seen.
+  pub struct OnnxLandmarkModel {
+    model: ImageModel,
+    palm_detector: Option<OnnxPalmDetector>,
+    min_presence: f64,
+    /// Where each hand was last seen, without a palm detector the left half
+    /// of the frame first.
+    regions: [Option<Region>; 2],
+  }
+
+  impl OnnxLandmarkModel {
+    pub fn load(path: &Path, min_presence: f64) -> Result<Self, String> {
+      Ok(Self {
+        model: ImageModel::load(path)?,
+        palm_detector: None,
+        min_presence,
+        regions: [None, None],
+      })
+    }
+
+    /// Finds hands with `palm_detector` instead of in each half of the frame.
+    pub fn with_palm_detector(mut self, palm_detector: OnnxPalmDetector) -> Self {
+      self.palm_detector = Some(palm_detector);
+      self
+    }
+
+    /// Runs the model on a region of the frame.
+    fn run(&self, image: &RgbImage, region: &Region) -> Result<Option<Detection>, String> {
+      let outputs = self.model.run(image, region)?;
+      let values = |idx: usize| -> Result<&Vec<f32>, String> {
+        outputs
+          .get(idx)
+          .ok_or_else(|| format!("Model has no output {}", idx))
+      };
+      let (landmarks, presence, right) = (values(0)?, values(1)?, values(2)?);
+      if landmarks.len() < 63 || presence.is_empty() || right.is_empty() {
+        return Err("Model outputs are too short".to_string());
+      }
+
+      let presence = presence[0] as f64;
+      if presence.is_nan() || presence < self.min_presence {
+        return Ok(None);
+      }
+      let scale = region.size / self.model.side as f64;
+      let points = landmarks[..63]
+        .chunks(3)
+        .map(|p| {
+          [
+            region.x + p[0] as f64 * scale,
+            region.y + p[1] as f64 * scale,
+            p[2] as f64 * scale,
+          ]
+        })
+        .collect();
+      Ok(Some(Detection {
+        points,
+        right: right[0] as f64,
+      }))
+    }
+
+    /// Fills the regions of hands that are not followed anymore with palms
+    /// that are not followed yet.
+    fn find_palms(&mut self, image: &RgbImage) -> Result<(), String> {
+      let palm_detector = match &self.palm_detector {
+        Some(palm_detector) if self.regions.iter().any(|r| r.is_none()) => palm_detector,
+        _ => return Ok(()),
+      };
+      for region in palm_detector.detect(image)? {
+        let (x, y) = (region.x + region.size / 2.0, region.y + region.size / 2.0);
+        if self.regions.iter().flatten().any(|r| r.contains(x, y)) {
+          continue;
+        }
+        match self.regions.iter_mut().find(|r| r.is_none()) {
+          Some(free) => *free = Some(region),
+          None => break,
+        }
+      }
+      Ok(())
+    }
+  }
+
+  impl LandmarkModel for OnnxLandmarkModel {
+    fn detect(&mut self, image: &RgbImage) -> Result<Vec<HandObservation>, String> {
+      self.find_palms(image)?;
+
+      let (width, height) = (image.width() as f64, image.height() as f64);
+      let mut hands = vec![];
+      for side in 0..2 {
+        let region = match (self.regions[side], &self.palm_detector) {
+          (Some(region), _) => region,
+          (None, Some(_)) => continue,
+          (None, None) => Region::half(image, side),
+        };
+        let Detection { points, right } = match self.run(image, &region)? {
+          Some(hand) => hand,
+          None => {
+            self.regions[side] = None;
+            continue;
+          }
+        };
+        self.regions[side] = Some(Region::around(&points));
+
+        // Same centre and normalization as the hand tracker page
+        let landmarks = points
+          .iter()
+          .map(|[x, y, z]| [x / width, y / height, z / width])
+          .collect::<Vec<_>>();
+        hands.push(HandObservation {
+          x: (landmarks[0][0] + landmarks[9][0]) / 2.0,
+          y: (landmarks[0][1] + landmarks[9][1]) / 2.0,
+          hand: Some(match right > 0.5 {
+            true => Handedness::Right,
+            false => Handedness::Left,
+          }),
+          score: right.max(1.0 - right),
+          landmarks,
+        });
+      }
+
+      // Both regions followed the same hand, look for the other one again
+      if let (Some(left), Some(right)) = (self.regions[0], self.regions[1]) {
+        if left.contains(right.x + right.size / 2.0, right.y + right.size / 2.0) {
+          self.regions[1] = None;
+          hands.pop();
+        }
+      }
+      Ok(hands)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use image::Rgb;
+
+  #[test]
+  fn samples_regions_with_black_outside() {
+    let image = RgbImage::from_fn(4, 2, |x, _| Rgb([x as u8 * 10, 0, 0]));
+    let left = Region::half(&image, 0);
+    let right = Region::half(&image, 1);
+    assert_eq!(
+      (left, right),
+      (
+        Region {
+          x: 0.0,
+          y: 0.0,
+          size: 2.0
+        },
+        Region {
+          x: 2.0,
+          y: 0.0,
+          size: 2.0
+        }
+      )
+    );
+    assert_eq!(right.sample(&image, 2, 1, 1), [30, 0, 0]);
+
+    let around = Region::around(&[[3.0, 1.0, 0.0], [4.0, 2.0, 0.0]]);
+    assert_eq!(
+      around,
+      Region {
+        x: 2.5,
+        y: 0.5,
+        size: 2.0
+      }
+    );
+    assert_eq!(around.sample(&image, 2, 0, 0), [30, 0, 0]);
+    assert_eq!(around.sample(&image, 2, 1, 1), [0, 0, 0]);
+  }
+
+  #[test]
+  fn lays_out_palm_anchors() {
+    assert_eq!(palm_anchors(192).len(), 2016);
+    let anchors = palm_anchors(16);
+    assert_eq!(anchors.len(), 14);
+    assert_eq!(
+      &anchors[..4],
+      &[[0.25, 0.25], [0.25, 0.25], [0.75, 0.25], [0.75, 0.25]]
+    );
+    assert_eq!(anchors[8], [0.5, 0.5]);
+  }
+
+  #[test]
+  fn decodes_palms_without_overlap() {
+    let anchors = palm_anchors(16);
+    let mut boxes = vec![0.0; 14 * 18];
+    let mut scores = vec![-5.0; 14];
+    // The same palm found by two anchors, and a weaker one elsewhere
+    for (anchor, score) in [(2, 3.0), (3, 5.0), (12, 1.0)] {
+      let b = &mut boxes[anchor * 18..(anchor + 1) * 18];
+      b[2] = 4.0;
+      b[3] = 4.0;
+      b[5] = 2.0;
+      b[9] = -2.0;
+      scores[anchor] = score;
+    }
+
+    let palms = decode_palms(16, &anchors, &boxes, &scores, 0.5);
+    assert_eq!(palms.len(), 2);
+    assert_eq!(
+      palms[0],
+      Palm {
+        x: 0.75,
+        y: 0.25,
+        width: 0.25,
+        height: 0.25,
+        wrist: [0.75, 0.375],
+        middle: [0.75, 0.125],
+        score: 1.0 / (1.0 + (-5.0f64).exp()),
+      }
+    );
+    assert_eq!((palms[1].x, palms[1].y), (0.5, 0.5));
+    assert!(decode_palms(16, &anchors, &boxes, &scores, 0.999).is_empty());
+
+    // Moved towards the fingers, up here, and grown to take the hand
+    let region = palms[0].hand_region(&Region {
+      x: 0.0,
+      y: -8.0,
+      size: 32.0,
+    });
+    assert!((region.x - 13.6).abs() < 1e-9, "{:?}", region);
+    assert!((region.y + 14.4).abs() < 1e-9, "{:?}", region);
+    assert!((region.size - 20.8).abs() < 1e-9, "{:?}", region);
+  }
+
+  #[cfg(feature = "native-hand-tracking")]
+  #[test]
+  fn runs_onnx_model_on_each_half() {
+    // Fake model with an 8x8 channels first input. Its landmarks are all at
+    // (6, 2), the presence is the brightest input value and the hand is
+    // right with a probability of 0.9.
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+      .join("src/handtracking/testdata/fake_hand_landmarks.onnx");
+    let mut model = OnnxLandmarkModel::load(&path, 0.5).unwrap();
+
+    // Only the right half is lit
+    let image = RgbImage::from_fn(32, 16, |x, _| match x >= 16 {
+      true => Rgb([255, 255, 255]),
+      false => Rgb([0, 0, 0]),
+    });
+    let hands = model.detect(&image).unwrap();
+    assert_eq!(hands.len(), 1);
+    let hand = &hands[0];
+    assert_eq!(
+      hand.hand,
+      Some(crate::handtracking::frame::Handedness::Right)
+    );
+    assert!((hand.score - 0.9).abs() < 1e-6);
+    // (6, 2) of the right half scaled from 8 to 16 pixels
+    assert!((hand.x - 28.0 / 32.0).abs() < 1e-6, "{}", hand.x);
+    assert!((hand.y - 4.0 / 16.0).abs() < 1e-6, "{}", hand.y);
+    assert_eq!(hand.landmarks.len(), 21);
+
+    // The hand is then looked for around where it was seen, a 2x2 square
+    // at (27, 3)
+    let hands = model.detect(&image).unwrap();
+    assert!((hands[0].x - 28.5 / 32.0).abs() < 1e-6, "{}", hands[0].x);
+    assert!((hands[0].y - 3.5 / 16.0).abs() < 1e-6, "{}", hands[0].y);
+  }
+
+  #[cfg(feature = "native-hand-tracking")]
+  #[test]
+  fn finds_hands_with_palm_detector() {
+    // Fake palm model with a 16x16 channels first input. It finds one palm
+    // at (0.75, 0.25) of its input with the fingers up when the frame is lit,
+    // and the same palm less surely at the anchor next to it.
+    let testdata =
+      std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/handtracking/testdata");
+    let palms = OnnxPalmDetector::load(&testdata.join("fake_palm_detection.onnx"), 0.5).unwrap();
+    let mut model = OnnxLandmarkModel::load(&testdata.join("fake_hand_landmarks.onnx"), 0.5)
+      .unwrap()
+      .with_palm_detector(palms);
+
+    let dark = RgbImage::new(32, 16);
+    assert!(model.detect(&dark).unwrap().is_empty());
+
+    // The frame is padded to 32x32 for the palm model, the hand region is
+    // the 20.8 pixel square at (13.6, -14.4) and the landmarks at (6, 2) of
+    // it scaled from 8 pixels
+    let lit = RgbImage::from_pixel(32, 16, Rgb([255, 255, 255]));
+    let hands = model.detect(&lit).unwrap();
+    assert_eq!(hands.len(), 1);
+    assert!((hands[0].x - 29.2 / 32.0).abs() < 1e-6, "{}", hands[0].x);
+    assert!((hands[0].y + 9.2 / 16.0).abs() < 1e-6, "{}", hands[0].y);
+  }
+}
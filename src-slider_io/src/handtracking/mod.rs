@@ -0,0 +1,13 @@
+pub mod actions;
+pub mod camera;
+pub mod config;
+
+pub mod filter;
+pub mod frame;
+pub mod fusion;
+pub mod gestures;
+pub mod landmarks;
+pub mod recorder;
+pub mod tracker;
+pub mod video;
+pub mod zones;
@@ -0,0 +1,188 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+  fs::File,
+  io::{self, BufRead, BufReader, BufWriter, Write},
+  path::Path,
+};
+
+use crate::handtracking::{config::HandTrackingConfig, frame::HandFrame};
+
+/// One hand tracker frame as received by the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedFrame {
+  /// Server time in milliseconds since the recording started.
+  pub received: f64,
+  /// Client that sent the frame.
+  pub id: usize,
+  pub frame: HandFrame,
+}
+
+/// A calibration change during a recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedCalibration {
+  /// Server time in milliseconds since the recording started.
+  received: f64,
+  /// The new calibration as config keys.
+  calibration: Value,
+}
+
+/// Frames recorded with one calibration.
+#[derive(Debug, Clone)]
+pub struct RecordedSegment {
+  /// Calibration in use as config keys.
+  pub calibration: Value,
+  pub frames: Vec<RecordedFrame>,
+}
+
+/// Writes the hand tracker stream to a file for offline tuning. The file is
+/// JSON lines: the calibration in use as config keys, then one
+/// `RecordedFrame` per line, with a `RecordedCalibration` line whenever the
+/// calibration changes.
+pub struct HandRecorder {
+  writer: BufWriter<File>,
+  failed: bool,
+}
+
+impl HandRecorder {
+  pub fn create(path: &Path, calibration: &HandTrackingConfig) -> io::Result<Self> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}", calibration.to_serde_value())?;
+    Ok(Self {
+      writer,
+      failed: false,
+    })
+  }
+
+  pub fn record(&mut self, received: f64, id: usize, frame: &HandFrame) {
+    self.write(&RecordedFrame {
+      received,
+      id,
+      frame: frame.clone(),
+    });
+  }
+
+  /// Records a calibration change, which applies to the frames after it.
+  pub fn calibrate(&mut self, received: f64, calibration: &HandTrackingConfig) {
+    self.write(&RecordedCalibration {
+      received,
+      calibration: calibration.to_serde_value(),
+    });
+  }
+
+  fn write<T: Serialize>(&mut self, line: &T) {
+    if self.failed {
+      return;
+    }
+
+    let res = serde_json::to_writer(&mut self.writer, line)
+      .map_err(io::Error::from)
+      .and_then(|_| writeln!(self.writer));
+    if let Err(e) = res {
+      error!("Hand recording stopped: {}", e);
+      self.failed = true;
+    }
+  }
+}
+
+/// Reads a recording made by `HandRecorder`, split where the calibration
+/// changed. There is always at least one segment.
+pub fn read_recording(path: &Path) -> io::Result<Vec<RecordedSegment>> {
+  let invalid = |e: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+
+  let mut lines = BufReader::new(File::open(path)?).lines();
+  let calibration = match lines.next() {
+    Some(line) => serde_json::from_str(&line?).map_err(invalid)?,
+    None => {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Empty recording",
+      ))
+    }
+  };
+
+  let lines = lines.collect::<io::Result<Vec<String>>>()?;
+  let mut segments = vec![RecordedSegment {
+    calibration,
+    frames: vec![],
+  }];
+  for (idx, line) in lines.iter().enumerate() {
+    if let Ok(change) = serde_json::from_str::<RecordedCalibration>(line) {
+      segments.push(RecordedSegment {
+        calibration: change.calibration,
+        frames: vec![],
+      });
+      continue;
+    }
+    match serde_json::from_str(line) {
+      Ok(frame) => segments.last_mut().unwrap().frames.push(frame),
+      // The last line is cut short when slidershim did not stop cleanly
+      Err(_) if idx + 1 == lines.len() => {}
+      Err(e) => return Err(invalid(e)),
+    }
+  }
+  Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{env, fs, process};
+
+  use crate::handtracking::frame::{HandObservation, Handedness};
+
+  fn frame(y: f64) -> HandFrame {
+    HandFrame {
+      t: y * 1000.0,
+      hands: vec![HandObservation {
+        x: 0.5,
+        y,
+        hand: Some(Handedness::Left),
+        score: 0.9,
+        landmarks: vec![],
+      }],
+      source: None,
+    }
+  }
+
+  #[test]
+  fn reads_back_frames_and_calibration_changes() {
+    let path = env::temp_dir().join(format!("slidershim-hands-{}.jsonl", process::id()));
+    let calibration = HandTrackingConfig::default();
+    let changed = HandTrackingConfig {
+      zone_bottom: 0.7,
+      ..HandTrackingConfig::default()
+    };
+
+    let mut recorder = HandRecorder::create(&path, &calibration).unwrap();
+    recorder.record(0.0, 1, &frame(0.1));
+    recorder.record(10.0, 2, &frame(0.2));
+    recorder.calibrate(15.0, &changed);
+    recorder.record(20.0, 1, &frame(0.3));
+    drop(recorder);
+    // Cut the last line short as if slidershim was killed while writing it
+    let mut text = fs::read_to_string(&path).unwrap();
+    text.push_str("{\"received\":30.0,\"id\":1,\"fra");
+    fs::write(&path, text).unwrap();
+
+    let segments = read_recording(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].calibration, calibration.to_serde_value());
+    assert_eq!(segments[1].calibration, changed.to_serde_value());
+    let frames = segments
+      .iter()
+      .map(|s| {
+        s.frames
+          .iter()
+          .map(|f| (f.received, f.id, f.frame.hands[0].y))
+          .collect::<Vec<_>>()
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      frames,
+      vec![vec![(0.0, 1, 0.1), (10.0, 2, 0.2)], vec![(20.0, 1, 0.3)]]
+    );
+  }
+}
@@ -0,0 +1,62 @@
+use crate::{
+  handtracking::{
+    actions::AirActions,
+    config::{HandActionMode, HandTrackingConfig},
+    frame::HandFrame,
+    gestures::GestureRecognizer,
+    zones::AirZones,
+  },
+  meta::config::MetaAction,
+};
+
+/// Input produced by one hand tracker frame.
+pub struct HandOutput {
+  /// Air string state, with index 0 the bottom string.
+  pub air: [u8; 6],
+  /// Extra buttons held by gestures.
+  pub extra: [u8; 3],
+  /// Meta actions triggered by gestures in this frame.
+  pub actions: Vec<MetaAction>,
+}
+
+/// Turns hand tracker frames into air string state for one client.
+pub struct HandTracker {
+  mode: HandActionMode,
+  zones: AirZones,
+  actions: AirActions,
+  gestures: GestureRecognizer,
+}
+
+impl HandTracker {
+  pub fn new(config: &HandTrackingConfig) -> Self {
+    Self {
+      mode: config.actions,
+      zones: AirZones::new(config),
+      actions: AirActions::new(config),
+      gestures: GestureRecognizer::new(config),
+    }
+  }
+
+  pub fn update(&mut self, frame: &HandFrame) -> HandOutput {
+    let zones = match self.mode {
+      HandActionMode::Only => [0; 6],
+      _ => self.zones.update(frame),
+    };
+    let actions = match self.mode {
+      HandActionMode::Off => [0; 6],
+      _ => self.actions.update(frame),
+    };
+
+    let mut air = [0; 6];
+    for ((a, z), x) in air.iter_mut().zip(zones.iter()).zip(actions.iter()) {
+      *a = *z | *x;
+    }
+
+    let gestures = self.gestures.update(frame);
+    HandOutput {
+      air,
+      extra: gestures.extra,
+      actions: gestures.actions,
+    }
+  }
+}
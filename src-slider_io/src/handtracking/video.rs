@@ -0,0 +1,431 @@
+use image::{ImageFormat, RgbImage};
+use log::warn;
+use std::{
+  fs::File,
+  io::{self, BufRead, BufReader, Read, Write},
+  net::TcpStream,
+  path::Path,
+  process::{Child, ChildStdout, Command, Stdio},
+  time::{Duration, Instant},
+};
+
+/// Images larger than this are refused as a corrupt stream.
+const MAX_JPEG_SIZE: usize = 16 << 20;
+/// How long an MJPEG stream may go without sending data.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One decoded video frame.
+pub struct VideoFrame {
+  pub image: RgbImage,
+  /// Capture time in milliseconds, from the frame rate for files and the
+  /// arrival time for streams.
+  pub t: f64,
+}
+
+/// Where native hand tracking reads frames from.
+pub trait FrameSource: Send {
+  /// The next frame, `None` at the end of the video.
+  fn next_frame(&mut self) -> io::Result<Option<VideoFrame>>;
+}
+
+/// Splits concatenated JPEG images into one image each, such as an `.mjpeg`
+/// file or the body of a `multipart/x-mixed-replace` MJPEG stream. Anything
+/// between images, like multipart headers, is skipped.
+pub struct MjpegReader<R> {
+  reader: R,
+}
+
+impl<R: BufRead> MjpegReader<R> {
+  pub fn new(reader: R) -> Self {
+    Self { reader }
+  }
+
+  fn byte(&mut self) -> io::Result<u8> {
+    let byte = match self.reader.fill_buf()?.first() {
+      Some(byte) => *byte,
+      None => return Err(io::ErrorKind::UnexpectedEof.into()),
+    };
+    self.reader.consume(1);
+    Ok(byte)
+  }
+
+  /// The next JPEG image, `None` at the end of the stream. An image cut short
+  /// by the end of the stream is dropped.
+  pub fn next_jpeg(&mut self) -> io::Result<Option<Vec<u8>>> {
+    match self.read_jpeg() {
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+      res => res.map(Some),
+    }
+  }
+
+  fn read_jpeg(&mut self) -> io::Result<Vec<u8>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    // Start of image
+    let mut last = 0;
+    loop {
+      match self.byte()? {
+        0xd8 if last == 0xff => break,
+        byte => last = byte,
+      }
+    }
+
+    let mut jpeg = vec![0xff, 0xd8];
+    let mut next_marker = None;
+    loop {
+      let marker = match next_marker.take() {
+        Some(marker) => marker,
+        None => {
+          if self.byte()? != 0xff {
+            return Err(invalid("Expected a JPEG marker"));
+          }
+          // Markers can be padded with any number of 0xff
+          let mut marker = self.byte()?;
+          while marker == 0xff {
+            marker = self.byte()?;
+          }
+          marker
+        }
+      };
+      jpeg.extend([0xff, marker]);
+
+      match marker {
+        // End of image
+        0xd9 => return Ok(jpeg),
+        // Markers without a segment
+        0x01 | 0xd0..=0xd7 => continue,
+        _ => {}
+      }
+
+      let len = u16::from_be_bytes([self.byte()?, self.byte()?]) as usize;
+      if len < 2 {
+        return Err(invalid("JPEG segment too short"));
+      }
+      jpeg.extend((len as u16).to_be_bytes());
+      for _ in 2..len {
+        jpeg.push(self.byte()?);
+      }
+
+      // The compressed data after a start of scan runs until the next marker
+      // that is not a stuffed 0xff or a restart marker
+      if marker == 0xda {
+        loop {
+          let byte = self.byte()?;
+          if byte != 0xff {
+            jpeg.push(byte);
+            continue;
+          }
+          let mut marker = self.byte()?;
+          while marker == 0xff {
+            marker = self.byte()?;
+          }
+          match marker {
+            0x00 | 0xd0..=0xd7 => jpeg.extend([0xff, marker]),
+            _ => {
+              next_marker = Some(marker);
+              break;
+            }
+          }
+        }
+      }
+
+      if jpeg.len() > MAX_JPEG_SIZE {
+        return Err(invalid("JPEG image too large"));
+      }
+    }
+  }
+}
+
+/// Opens an MJPEG over HTTP stream, returning its body.
+fn http_get(url: &str) -> io::Result<BufReader<TcpStream>> {
+  let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+  let rest = url
+    .strip_prefix("http://")
+    .ok_or_else(|| invalid(format!("Only http:// streams are supported: {}", url)))?;
+  let (host, path) = match rest.find('/') {
+    Some(idx) => (&rest[..idx], &rest[idx..]),
+    None => (rest, "/"),
+  };
+  // Default port unless one is given after the host or a bracketed IPv6 address
+  let address = match host.rsplit_once(':') {
+    Some((_, port)) if !port.ends_with(']') => host.to_string(),
+    _ => format!("{}:80", host),
+  };
+
+  let mut stream = TcpStream::connect(address)?;
+  stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+  // HTTP/1.0 so that the body is not chunked
+  write!(
+    stream,
+    "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: multipart/x-mixed-replace, image/jpeg\r\n\r\n",
+    path, host
+  )?;
+
+  let mut reader = BufReader::new(stream);
+  let mut status = String::new();
+  reader.read_line(&mut status)?;
+  if status.split_whitespace().nth(1) != Some("200") {
+    return Err(io::Error::other(format!(
+      "{} answered {}",
+      url,
+      status.trim()
+    )));
+  }
+  // The content type is not checked, the body is scanned for images anyway
+  let mut line = String::new();
+  while reader.read_line(&mut line)? > 0 && !line.trim().is_empty() {
+    line.clear();
+  }
+  Ok(reader)
+}
+
+/// `ffmpeg` writing a video as MJPEG to its standard output, stopped when
+/// dropped.
+struct FfmpegOutput {
+  child: Child,
+  stdout: ChildStdout,
+}
+
+impl FfmpegOutput {
+  fn spawn(args: &[String]) -> io::Result<Self> {
+    let mut command = Command::new("ffmpeg");
+    command
+      .args(args)
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::null());
+    #[cfg(windows)]
+    {
+      use std::os::windows::process::CommandExt;
+      // CREATE_NO_WINDOW, no console flashing up behind the app
+      command.creation_flags(0x08000000);
+    }
+
+    let mut child = command.spawn().map_err(|e| match e.kind() {
+      io::ErrorKind::NotFound => io::Error::new(
+        e.kind(),
+        "ffmpeg was not found, it is needed for cameras and videos that are not MJPEG files",
+      ),
+      _ => e,
+    })?;
+    let stdout = child.stdout.take().unwrap();
+    Ok(Self { child, stdout })
+  }
+}
+
+impl Read for FfmpegOutput {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.stdout.read(buf)
+  }
+}
+
+impl Drop for FfmpegOutput {
+  fn drop(&mut self) {
+    self.child.kill().ok();
+    self.child.wait().ok();
+  }
+}
+
+/// `ffmpeg` arguments converting `input` to MJPEG on its standard output.
+fn ffmpeg_args(input: &[&str], filter: Option<String>) -> Vec<String> {
+  let mut args = vec!["-loglevel", "error", "-nostdin"]
+    .into_iter()
+    .chain(input.iter().copied())
+    .map(String::from)
+    .collect::<Vec<_>>();
+  if let Some(filter) = filter {
+    args.extend(["-vf".to_string(), filter]);
+  }
+  args.extend(
+    ["-an", "-f", "mjpeg", "-q:v", "5", "-"]
+      .iter()
+      .map(|a| a.to_string()),
+  );
+  args
+}
+
+/// Whether a source is a V4L2 camera, such as `/dev/video0`.
+fn is_camera(source: &str) -> bool {
+  source.starts_with("/dev/")
+}
+
+/// Whether a file is MJPEG already, which is read without `ffmpeg`.
+fn is_mjpeg(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|e| e.to_str())
+    .is_some_and(|e| e.eq_ignore_ascii_case("mjpeg") || e.eq_ignore_ascii_case("mjpg"))
+}
+
+enum FrameClock {
+  /// Frames are `interval` milliseconds apart.
+  Fixed { interval: f64, frames: u64 },
+  /// Frames are timed when they arrive.
+  Arrival(Instant),
+}
+
+/// Frames of an MJPEG file or MJPEG over HTTP stream, as served by most IP
+/// webcam apps, or of a V4L2 camera or another video file converted to MJPEG
+/// by `ffmpeg`.
+pub struct MjpegSource {
+  reader: MjpegReader<Box<dyn BufRead + Send>>,
+  clock: FrameClock,
+}
+
+impl MjpegSource {
+  /// Reads an MJPEG file, whose frames are `fps` per second.
+  pub fn file(path: &Path, fps: f64) -> io::Result<Self> {
+    Ok(Self {
+      reader: MjpegReader::new(Box::new(BufReader::new(File::open(path)?))),
+      clock: FrameClock::Fixed {
+        interval: 1000.0 / fps,
+        frames: 0,
+      },
+    })
+  }
+
+  /// Connects to an MJPEG over HTTP stream.
+  pub fn http(url: &str) -> io::Result<Self> {
+    Ok(Self {
+      reader: MjpegReader::new(Box::new(http_get(url)?)),
+      clock: FrameClock::Arrival(Instant::now()),
+    })
+  }
+
+  /// Captures a V4L2 camera through `ffmpeg`.
+  pub fn camera(device: &str) -> io::Result<Self> {
+    let args = ffmpeg_args(&["-f", "v4l2", "-i", device], None);
+    Ok(Self {
+      reader: MjpegReader::new(Box::new(BufReader::new(FfmpegOutput::spawn(&args)?))),
+      clock: FrameClock::Arrival(Instant::now()),
+    })
+  }
+
+  /// Decodes a video file in any format `ffmpeg` reads, at `fps` frames per
+  /// second.
+  pub fn video(path: &Path, fps: f64) -> io::Result<Self> {
+    // ffmpeg only reports a missing file on its error output
+    if !path.is_file() {
+      return Err(io::ErrorKind::NotFound.into());
+    }
+    let input = path.to_string_lossy();
+    let args = ffmpeg_args(&["-i", &input], Some(format!("fps={}", fps)));
+    Ok(Self {
+      reader: MjpegReader::new(Box::new(BufReader::new(FfmpegOutput::spawn(&args)?))),
+      clock: FrameClock::Fixed {
+        interval: 1000.0 / fps,
+        frames: 0,
+      },
+    })
+  }
+
+  /// Connects to `source` if it is an `http://` URL, captures it if it is a
+  /// camera and reads it as a video file otherwise.
+  pub fn open(source: &str, fps: f64) -> io::Result<Self> {
+    let path = Path::new(source);
+    if source.starts_with("http://") {
+      Self::http(source)
+    } else if is_camera(source) {
+      Self::camera(source)
+    } else if is_mjpeg(path) {
+      Self::file(path, fps)
+    } else {
+      Self::video(path, fps)
+    }
+  }
+}
+
+impl FrameSource for MjpegSource {
+  fn next_frame(&mut self) -> io::Result<Option<VideoFrame>> {
+    loop {
+      let jpeg = match self.reader.next_jpeg()? {
+        Some(jpeg) => jpeg,
+        None => return Ok(None),
+      };
+      let t = match &mut self.clock {
+        FrameClock::Fixed { interval, frames } => {
+          *frames += 1;
+          (*frames - 1) as f64 * *interval
+        }
+        FrameClock::Arrival(start) => start.elapsed().as_secs_f64() * 1000.0,
+      };
+
+      match image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg) {
+        Ok(image) => {
+          return Ok(Some(VideoFrame {
+            image: image.to_rgb8(),
+            t,
+          }))
+        }
+        Err(e) => warn!("Video frame could not be decoded: {}", e),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use image::{codecs::jpeg::JpegEncoder, ColorType, Rgb};
+  use std::io::Cursor;
+
+  fn jpeg(color: [u8; 3]) -> Vec<u8> {
+    let image = RgbImage::from_pixel(16, 8, Rgb(color));
+    let mut data = vec![];
+    JpegEncoder::new(&mut data)
+      .encode(&image, 16, 8, ColorType::Rgb8)
+      .unwrap();
+    data
+  }
+
+  #[test]
+  fn splits_multipart_stream() {
+    let images = [jpeg([255, 0, 0]), jpeg([0, 0, 255])];
+    let mut stream = vec![];
+    for image in images.iter() {
+      stream.extend(b"--frame\r\nContent-Type: image/jpeg\r\n\r\n");
+      stream.extend(image);
+      stream.extend(b"\r\n");
+    }
+    // Cut short by a dropped connection
+    stream.extend(b"--frame\r\n\r\n");
+    stream.extend(&images[0][..images[0].len() / 2]);
+
+    let mut reader = MjpegReader::new(Cursor::new(stream));
+    assert_eq!(reader.next_jpeg().unwrap().as_ref(), Some(&images[0]));
+    assert_eq!(reader.next_jpeg().unwrap().as_ref(), Some(&images[1]));
+    assert_eq!(reader.next_jpeg().unwrap(), None);
+  }
+
+  #[test]
+  fn reads_clip_frames_at_fps() {
+    let path =
+      Path::new(env!("CARGO_MANIFEST_DIR")).join("src/handtracking/testdata/raise_left.mjpeg");
+    let mut source = MjpegSource::file(&path, 20.0).unwrap();
+    let mut times = vec![];
+    while let Some(frame) = source.next_frame().unwrap() {
+      assert_eq!(frame.image.dimensions(), (32, 24));
+      times.push(frame.t);
+    }
+    assert_eq!(times.len(), 24);
+    assert_eq!(&times[..3], &[0.0, 50.0, 100.0]);
+  }
+
+  #[test]
+  fn converts_other_sources_with_ffmpeg() {
+    assert!(is_camera("/dev/video0"));
+    assert!(!is_camera("clips/hands.mp4"));
+    assert!(is_mjpeg(Path::new("hands.MJPEG")));
+    assert!(is_mjpeg(Path::new("hands.mjpg")));
+    assert!(!is_mjpeg(Path::new("hands.mp4")));
+    assert_eq!(
+      ffmpeg_args(&["-i", "hands.mp4"], Some("fps=30".to_string())).join(" "),
+      "-loglevel error -nostdin -i hands.mp4 -vf fps=30 -an -f mjpeg -q:v 5 -"
+    );
+    assert!(matches!(
+      MjpegSource::open("/nonexistent/hands.mp4", 30.0),
+      Err(e) if e.kind() == io::ErrorKind::NotFound
+    ));
+  }
+}
@@ -0,0 +1,230 @@
+use crate::handtracking::{
+  camera::CameraOrientation, config::HandTrackingConfig, filter::OneEuroFilter, frame::HandFrame,
+};
+
+/// Smoothed position and current zone of one hand.
+struct TrackedHand {
+  filter: OneEuroFilter,
+  /// Zone index counted from the top, `6` when below the air zone.
+  zone: usize,
+}
+
+impl TrackedHand {
+  fn new(config: &HandTrackingConfig) -> Self {
+    Self {
+      filter: OneEuroFilter::new(
+        config.filter_min_cutoff,
+        config.filter_beta,
+        config.filter_d_cutoff,
+      ),
+      zone: 6,
+    }
+  }
+}
+
+/// Maps hand positions to the 6 air strings.
+pub struct AirZones {
+  /// Zone boundaries from the top edge to the bottom edge, 7 in total.
+  bounds: [f64; 7],
+  margin: f64,
+  min_confidence: f64,
+  camera: CameraOrientation,
+  hands: [TrackedHand; 2],
+}
+
+impl AirZones {
+  pub fn new(config: &HandTrackingConfig) -> Self {
+    let total: f64 = config.zone_heights.iter().sum();
+    let span = config.zone_bottom - config.zone_top;
+
+    let mut bounds = [config.zone_top; 7];
+    let mut acc = 0.0;
+    for (idx, h) in config.zone_heights.iter().enumerate() {
+      acc += h;
+      bounds[idx + 1] = config.zone_top + span * acc / total;
+    }
+
+    Self {
+      bounds,
+      margin: config.hysteresis * span / 6.0,
+      min_confidence: config.min_confidence,
+      camera: config.camera,
+      hands: [TrackedHand::new(config), TrackedHand::new(config)],
+    }
+  }
+
+  /// Zone for a position without hysteresis.
+  fn raw_zone(&self, y: f64) -> usize {
+    (1..7).find(|z| y < self.bounds[*z]).map_or(6, |z| z - 1)
+  }
+
+  /// Whether `y` is still inside `zone` widened by the hysteresis margin.
+  fn holds(&self, zone: usize, y: f64) -> bool {
+    let (top, bottom) = match zone {
+      0 => (f64::NEG_INFINITY, self.bounds[1] + self.margin),
+      6 => (self.bounds[6] - self.margin, f64::INFINITY),
+      z => (
+        self.bounds[z] - self.margin,
+        self.bounds[z + 1] + self.margin,
+      ),
+    };
+    top <= y && y < bottom
+  }
+
+  /// Updates with a new frame and returns air string state, with index 0 the
+  /// bottom string.
+  pub fn update(&mut self, frame: &HandFrame) -> [u8; 6] {
+    let t = frame.t / 1000.0;
+    let seen = frame.by_side(self.min_confidence, &self.camera);
+
+    for (idx, seen) in seen.iter().enumerate() {
+      let zone = match seen {
+        Some(hand) => {
+          let y = self.hands[idx].filter.filter(hand.y, t);
+          match self.holds(self.hands[idx].zone, y) {
+            true => self.hands[idx].zone,
+            false => self.raw_zone(y),
+          }
+        }
+        None => {
+          self.hands[idx].filter.reset();
+          6
+        }
+      };
+      self.hands[idx].zone = zone;
+    }
+
+    let mut air = [0; 6];
+    for hand in self.hands.iter() {
+      if hand.zone < 6 {
+        air[5 - hand.zone] = 1;
+      }
+    }
+    air
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::handtracking::frame::{HandObservation, Handedness};
+
+  fn config() -> HandTrackingConfig {
+    HandTrackingConfig {
+      zone_top: 0.0,
+      zone_bottom: 0.6,
+      zone_heights: [1.0; 6],
+      hysteresis: 0.2,
+      min_confidence: 0.5,
+      // Effectively no smoothing
+      filter_min_cutoff: 1e9,
+      filter_beta: 0.0,
+      filter_d_cutoff: 1.0,
+      ..HandTrackingConfig::default()
+    }
+  }
+
+  fn frame(t: f64, hands: &[(Option<Handedness>, f64, f64)]) -> HandFrame {
+    HandFrame {
+      t,
+      hands: hands
+        .iter()
+        .map(|(hand, y, score)| HandObservation {
+          x: 0.5,
+          y: *y,
+          hand: *hand,
+          score: *score,
+          landmarks: vec![],
+        })
+        .collect(),
+      source: None,
+    }
+  }
+
+  #[test]
+  fn maps_height_to_string() {
+    let mut zones = AirZones::new(&config());
+    assert_eq!(
+      zones.update(&frame(0.0, &[(None, 0.05, 1.0)])),
+      [0, 0, 0, 0, 0, 1]
+    );
+    assert_eq!(
+      zones.update(&frame(10.0, &[(None, 0.55, 1.0)])),
+      [1, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(zones.update(&frame(20.0, &[(None, 0.9, 1.0)])), [0; 6]);
+  }
+
+  #[test]
+  fn uneven_zone_heights() {
+    let mut config = config();
+    config.zone_heights = [3.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+    let mut zones = AirZones::new(&config);
+    // Top string covers the top 3/8 of the zone
+    assert_eq!(
+      zones.update(&frame(0.0, &[(None, 0.2, 1.0)])),
+      [0, 0, 0, 0, 0, 1]
+    );
+    assert_eq!(
+      zones.update(&frame(10.0, &[(None, 0.27, 1.0)])),
+      [0, 0, 0, 0, 1, 0]
+    );
+  }
+
+  #[test]
+  fn hysteresis_holds_string_near_boundary() {
+    let mut zones = AirZones::new(&config());
+    // Strings are 0.1 tall with a 0.02 margin
+    assert_eq!(
+      zones.update(&frame(0.0, &[(None, 0.15, 1.0)])),
+      [0, 0, 0, 0, 1, 0]
+    );
+    assert_eq!(
+      zones.update(&frame(10.0, &[(None, 0.21, 1.0)])),
+      [0, 0, 0, 0, 1, 0]
+    );
+    assert_eq!(
+      zones.update(&frame(20.0, &[(None, 0.23, 1.0)])),
+      [0, 0, 0, 1, 0, 0]
+    );
+    assert_eq!(
+      zones.update(&frame(30.0, &[(None, 0.19, 1.0)])),
+      [0, 0, 0, 1, 0, 0]
+    );
+    // Leaving the bottom of the air zone also needs the margin
+    assert_eq!(
+      zones.update(&frame(40.0, &[(None, 0.55, 1.0)])),
+      [1, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(
+      zones.update(&frame(50.0, &[(None, 0.61, 1.0)])),
+      [1, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(zones.update(&frame(60.0, &[(None, 0.63, 1.0)])), [0; 6]);
+    assert_eq!(zones.update(&frame(70.0, &[(None, 0.59, 1.0)])), [0; 6]);
+  }
+
+  #[test]
+  fn both_hands_press_separate_strings() {
+    let mut zones = AirZones::new(&config());
+    let air = zones.update(&frame(
+      0.0,
+      &[
+        (Some(Handedness::Left), 0.05, 0.9),
+        (Some(Handedness::Right), 0.45, 0.9),
+      ],
+    ));
+    assert_eq!(air, [0, 1, 0, 0, 0, 1]);
+  }
+
+  #[test]
+  fn ignores_low_confidence_and_missing_hands() {
+    let mut zones = AirZones::new(&config());
+    assert_eq!(zones.update(&frame(0.0, &[(None, 0.05, 0.2)])), [0; 6]);
+    assert_eq!(
+      zones.update(&frame(10.0, &[(None, 0.05, 0.9)])),
+      [0, 0, 0, 0, 0, 1]
+    );
+    assert_eq!(zones.update(&frame(20.0, &[])), [0; 6]);
+  }
+}
@@ -10,7 +10,9 @@ pub mod shared;
 pub mod state;
 
 pub mod device;
+pub mod handtracking;
 pub mod lighting;
+pub mod meta;
 pub mod output;
 
 pub mod system;
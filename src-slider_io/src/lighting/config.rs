@@ -1,5 +1,7 @@
 use serde_json::Value;
 
+use crate::shared::{access::ServerAccessConfig, mdns::mdns_name};
+
 #[derive(Debug, Clone, Copy)]
 pub enum ReactiveLayout {
   Even { splits: usize },
@@ -83,6 +85,8 @@ pub enum LightsMode {
   UmgrWebsocket {
     faster: bool,
     port: u16,
+    access: ServerAccessConfig,
+    mdns_name: Option<String>,
   },
   Serial {
     faster: bool,
@@ -91,6 +95,41 @@ pub enum LightsMode {
 }
 
 impl LightsMode {
+  /// Returns the mode `steps` entries along the meta-button lighting cycle,
+  /// where entry 0 is this mode. Modes driven by an external source are never
+  /// cycled.
+  pub fn cycled(&self, steps: usize) -> Self {
+    let (faster, sensitivity, color) = match self {
+      LightsMode::Reactive {
+        faster,
+        sensitivity,
+        color,
+        ..
+      } => (*faster, *sensitivity, color.clone()),
+      LightsMode::Attract { faster } => (*faster, 20, ColorScheme::default()),
+      _ => return self.clone(),
+    };
+
+    let reactive = |layout: ReactiveLayout| LightsMode::Reactive {
+      faster,
+      layout,
+      sensitivity,
+      color: color.clone(),
+    };
+    let cycle = [
+      reactive(ReactiveLayout::Even { splits: 16 }),
+      reactive(ReactiveLayout::Even { splits: 8 }),
+      reactive(ReactiveLayout::Six),
+      reactive(ReactiveLayout::Rainbow),
+      LightsMode::Attract { faster },
+    ];
+
+    match steps % (cycle.len() + 1) {
+      0 => self.clone(),
+      idx => cycle[idx - 1].clone(),
+    }
+  }
+
   pub fn from_serde_value(v: &Value) -> Option<Self> {
     Some(match v["ledMode"].as_str()? {
       "none" => LightsMode::None,
@@ -146,6 +185,8 @@ impl LightsMode {
       "umgr-websocket" => LightsMode::UmgrWebsocket {
         faster: v["ledFaster"].as_bool()?,
         port: u16::try_from(v["ledUmgrWebsocketPort"].as_i64()?).ok()?,
+        access: ServerAccessConfig::from_serde_value(v, "ledUmgr"),
+        mdns_name: mdns_name(v),
       },
       "serial" => LightsMode::Serial {
         faster: v["ledFaster"].as_bool()?,
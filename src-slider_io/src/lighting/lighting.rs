@@ -32,7 +32,9 @@ fn get_rainbow(phase: f64, desaturate: bool) -> Rgb<SrgbEncoding, u8> {
 
 pub struct LightsJob {
   state: SliderState,
+  base_mode: LightsMode,
   mode: LightsMode,
+  cycle: usize,
   serial_port: Option<Box<dyn SerialPort>>,
   started: Instant,
   timer: Interval,
@@ -40,9 +42,12 @@ pub struct LightsJob {
 
 impl LightsJob {
   pub fn new(state: &SliderState, mode: &LightsMode) -> Self {
+    let cycle = state.control.get_lights_cycle();
     Self {
       state: state.clone(),
-      mode: mode.clone(),
+      base_mode: mode.clone(),
+      mode: mode.cycled(cycle),
+      cycle,
       serial_port: None,
       started: Instant::now(),
       timer: match match mode {
@@ -61,6 +66,7 @@ impl LightsJob {
     &self,
     flat_input: Option<&Vec<bool>>,
     serial_buffer: Option<&Buffer>,
+    brightness: u8,
     lights: &mut SliderLights,
   ) {
     match &self.mode {
@@ -314,12 +320,17 @@ impl LightsJob {
             {
               lights.paint(idx, &[(*buf_chunk)[1], (*buf_chunk)[2], (*buf_chunk)[0]]);
             }
+            lights.scale(brightness);
           }
         }
       }
       _ => panic!("Not implemented"),
     }
 
+    // Serial frames are only painted (and dimmed) when new data arrives
+    if !matches!(self.mode, LightsMode::Serial { .. }) {
+      lights.scale(brightness);
+    }
     lights.dirty = true;
   }
 }
@@ -352,6 +363,13 @@ impl AsyncJob for LightsJob {
   }
 
   async fn tick(&mut self) -> bool {
+    let cycle = self.state.control.get_lights_cycle();
+    if cycle != self.cycle {
+      self.cycle = cycle;
+      self.mode = self.base_mode.cycled(cycle);
+      info!("Lighting mode cycled to {:?}", self.mode);
+    }
+
     let mut flat_input: Option<Vec<bool>> = None;
     let mut serial_buffer: Option<Buffer> = None;
 
@@ -391,6 +409,7 @@ impl AsyncJob for LightsJob {
       self.calc_lights(
         flat_input.as_ref(),
         serial_buffer.as_ref(),
+        self.state.control.get_brightness(),
         lights_handle.deref_mut(),
       );
     }
@@ -8,7 +8,7 @@ use hyper::{
   Body, Method, Request, Response, Server, StatusCode,
 };
 use log::{error, info};
-use std::{convert::Infallible, future::Future, net::SocketAddr};
+use std::{convert::Infallible, future::Future, net::SocketAddr, sync::Arc};
 use tokio::{
   select,
   sync::mpsc,
@@ -17,7 +17,14 @@ use tokio::{
 use tokio_tungstenite::WebSocketStream;
 use tungstenite::{handshake, Message};
 
-use crate::{shared::worker::AsyncHaltableJob, state::SliderState};
+use crate::{
+  shared::{
+    access::{refused, ServerAccess, ServerAccessConfig},
+    mdns::Advertisement,
+    worker::{AsyncHaltableJob, WorkerStatus},
+  },
+  state::SliderState,
+};
 
 async fn error_response() -> Result<Response<Body>, Infallible> {
   Ok(
@@ -204,12 +211,20 @@ async fn handle_umgr_leds(ws_stream: WebSocketStream<Upgraded>, state: SliderSta
 
 async fn handle_websocket(
   mut request: Request<Body>,
+  remote_addr: SocketAddr,
   state: SliderState,
   faster: bool,
+  access: Arc<ServerAccess>,
 ) -> Result<Response<Body>, Infallible> {
+  let connection = match access.connect(&remote_addr.ip()) {
+    Ok(connection) => connection,
+    Err(status) => return Ok(refused(status)),
+  };
   let res = match handshake::server::create_response_with_body(&request, || Body::empty()) {
     Ok(res) => {
       tokio::spawn(async move {
+        // Counted until the connection closes
+        let _connection = connection;
         match upgrade::on(&mut request).await {
           Ok(upgraded) => {
             let ws_stream = WebSocketStream::from_raw_socket(
@@ -246,7 +261,11 @@ async fn handle_request(
   remote_addr: SocketAddr,
   state: SliderState,
   faster: bool,
+  access: Arc<ServerAccess>,
 ) -> Result<Response<Body>, Infallible> {
+  if let Err(status) = access.check_request(&remote_addr.ip()) {
+    return Ok(refused(status));
+  }
   let method = request.method();
   let path = request.uri().path();
   if method != Method::GET {
@@ -262,7 +281,7 @@ async fn handle_request(
     request.uri().path(),
     request.headers().contains_key(header::UPGRADE),
   ) {
-    ("/", true) => handle_websocket(request, state, faster).await,
+    ("/", true) => handle_websocket(request, remote_addr, state, faster, access).await,
     _ => error_response().await,
   }
 }
@@ -271,14 +290,24 @@ pub struct UmgrWebsocketJob {
   state: SliderState,
   faster: bool,
   port: u16,
+  access: ServerAccessConfig,
+  mdns_name: Option<String>,
 }
 
 impl UmgrWebsocketJob {
-  pub fn new(state: &SliderState, faster: &bool, port: &u16) -> Self {
+  pub fn new(
+    state: &SliderState,
+    faster: &bool,
+    port: &u16,
+    access: &ServerAccessConfig,
+    mdns_name: &Option<String>,
+  ) -> Self {
     Self {
       state: state.clone(),
       faster: *faster,
       port: *port,
+      access: access.clone(),
+      mdns_name: mdns_name.clone(),
     }
   }
 }
@@ -288,26 +317,75 @@ impl AsyncHaltableJob for UmgrWebsocketJob {
   async fn run<F: Future<Output = ()> + Send>(self, stop_signal: F) {
     let state = self.state.clone();
     let faster = self.faster;
+    let access = ServerAccess::new(&self.access);
     let make_svc = make_service_fn(|conn: &AddrStream| {
       let remote_addr = conn.remote_addr();
       let make_svc_state = state.clone();
+      let make_svc_access = access.clone();
       async move {
         Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
           let svc_state = make_svc_state.clone();
-          handle_request(request, remote_addr, svc_state, faster)
+          handle_request(
+            request,
+            remote_addr,
+            svc_state,
+            faster,
+            make_svc_access.clone(),
+          )
         }))
       }
     });
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], self.port));
-    info!("UMGR LED websocket server listening on {}", addr);
+    // UMIGURI connects to a fixed port, so there is no fallback
+    let server = match self.access.bind(self.port, 0).await {
+      Ok(listener) => Server::from_tcp(listener),
+      Err(e) => {
+        error!(
+          "UMGR LED websocket server could not listen on port {}: {}",
+          self.port, e
+        );
+        self.state.control.set_worker_status(
+          "lights",
+          WorkerStatus::failed(format!("Port {} could not be used: {}", self.port, e)),
+        );
+        return;
+      }
+    };
+    let server = match server {
+      Ok(server) => server.serve(make_svc),
+      Err(e) => {
+        error!("UMGR LED websocket server error: {}", e);
+        return;
+      }
+    };
+    info!(
+      "UMGR LED websocket server listening on {}",
+      server.local_addr()
+    );
+    let mut status = WorkerStatus::default();
+    status
+      .ports
+      .insert("ledUmgrWebsocketPort", server.local_addr().port());
+    self.state.control.set_worker_status("lights", status);
 
-    let server = Server::bind(&addr)
-      .serve(make_svc)
-      .with_graceful_shutdown(stop_signal);
+    // Withdrawn when the server stops
+    let _advertisement = self.mdns_name.as_ref().and_then(|name| {
+      Advertisement::new(
+        "_ws._tcp",
+        name,
+        &format!("{} UMIGURI LED", name),
+        self.access.bind,
+        server.local_addr().port(),
+        &[("path", "/")],
+      )
+    });
 
-    if let Err(e) = server.await {
-      info!("UMGR LED websocket server stopped: {}", e);
+    if let Err(e) = server.with_graceful_shutdown(stop_signal).await {
+      error!("UMGR LED websocket server stopped: {}", e);
+      self.state.control.set_worker_status(
+        "lights",
+        WorkerStatus::failed(format!("Server stopped: {}", e)),
+      );
     }
   }
 }
@@ -1,7 +1,8 @@
-use log::info;
+use log::{info, warn};
 use parking_lot::Mutex;
+use serde_json::Value;
 use std::{
-  sync::Arc,
+  sync::{atomic::Ordering, Arc},
   thread::{self, JoinHandle},
 };
 use tokio::{
@@ -9,11 +10,18 @@ use tokio::{
   sync::{mpsc, oneshot},
 };
 
-use crate::{config::Config, context::Context, state::SliderState};
+use crate::{
+  config::Config,
+  context::Context,
+  device::brokenithm_pairing::revoke_paired_device,
+  state::{SliderControl, SliderState},
+};
 
 pub struct Manager {
   state: Arc<Mutex<Option<SliderState>>>,
   context: Arc<Mutex<Option<Context>>>,
+  control: Arc<SliderControl>,
+  saved_config: Arc<Mutex<Option<Config>>>,
   join_handle: Option<JoinHandle<()>>,
   tx_config: mpsc::UnboundedSender<Config>,
   tx_stop: Option<oneshot::Sender<()>>,
@@ -26,9 +34,13 @@ impl Manager {
     let (tx_stop, rx_stop) = oneshot::channel::<()>();
 
     let context: Arc<Mutex<Option<Context>>> = Arc::new(Mutex::new(None));
+    let control = Arc::new(SliderControl::new());
+    let saved_config = Arc::new(Mutex::new(None));
 
     let state_cloned = Arc::clone(&state);
     let context_cloned = Arc::clone(&context);
+    let control_cloned = Arc::clone(&control);
+    let saved_config_cloned = Arc::clone(&saved_config);
 
     let join_handle = thread::spawn(move || {
       info!("Manager thread started");
@@ -42,25 +54,66 @@ impl Manager {
 
         select! {
           _ = async {
+            let mut base_config: Option<Config> = None;
+            let mut profile: usize = 0;
+
             loop {
-              match rx_config.recv().await {
-                Some(config) => {
-                  info!("Rebuilding context");
-                  let mut context_handle = context_cloned.lock();
-                  context_handle.take();
-
-                  let new_context = Context::new(config);
-                  let new_state = new_context.clone_state();
-                  context_handle.replace(new_context);
-
-                  let mut state_handle = state_cloned.lock();
-                  state_handle.replace(new_state);
+              let config = select! {
+                config = rx_config.recv() => match config {
+                  Some(config) => {
+                    profile = 0;
+                    control_cloned.lights_cycle.store(0, Ordering::SeqCst);
+                    base_config.replace(config.clone());
+                    config
+                  },
+                  None => {
+                    let mut context_handle = context_cloned.lock();
+                    context_handle.take();
+                    continue;
+                  }
                 },
-                None => {
-                  let mut context_handle = context_cloned.lock();
-                  context_handle.take();
+                _ = control_cloned.profile_switch.notified() => {
+                  let base = match base_config.as_ref() {
+                    Some(base) if !base.profiles.is_empty() => base,
+                    _ => {
+                      warn!("No profiles to switch to");
+                      continue;
+                    }
+                  };
+                  profile = (profile + 1) % (base.profiles.len() + 1);
+                  match base.with_profile(profile) {
+                    Some(config) => {
+                      info!("Switching to profile {}", profile);
+                      config
+                    },
+                    None => {
+                      warn!("Profile {} is not a valid config", profile);
+                      continue;
+                    }
+                  }
                 }
-              }
+                _ = control_cloned.config_saved.notified() => {
+                  // The running context already uses the new settings, only
+                  // pick them up for later rebuilds and for the UI
+                  if let Some(config) = Config::load_saved() {
+                    info!("Saved config changed");
+                    base_config.replace(config.clone());
+                    saved_config_cloned.lock().replace(config);
+                  }
+                  continue;
+                }
+              };
+
+              info!("Rebuilding context");
+              let mut context_handle = context_cloned.lock();
+              context_handle.take();
+
+              let new_context = Context::new(config, &control_cloned);
+              let new_state = new_context.clone_state();
+              context_handle.replace(new_context);
+
+              let mut state_handle = state_cloned.lock();
+              state_handle.replace(new_state);
             }
           } => {},
           _ = rx_stop => {}
@@ -71,6 +124,8 @@ impl Manager {
     Self {
       state,
       context,
+      control,
+      saved_config,
       join_handle: Some(join_handle),
       tx_config,
       tx_stop: Some(tx_stop),
@@ -81,11 +136,67 @@ impl Manager {
     self.tx_config.send(config).unwrap();
   }
 
+  /// Returns the config if it was saved from outside the UI since the last
+  /// call.
+  pub fn take_saved_config(&self) -> Option<Config> {
+    self.saved_config.lock().take()
+  }
+
   pub fn try_get_state(&self) -> Option<SliderState> {
     let state_handle = self.state.lock();
     state_handle.as_ref().map(|x| x.clone())
   }
 
+  /// Releases all held keys and buttons and stops forwarding input to the
+  /// output. The device and lighting keep running.
+  pub fn pause_output(&self) {
+    info!("Pausing output");
+    self.control.set_output_paused(true);
+  }
+
+  pub fn resume_output(&self) {
+    info!("Resuming output");
+    self.control.set_output_paused(false);
+  }
+
+  pub fn is_output_paused(&self) -> bool {
+    self.control.is_output_paused()
+  }
+
+  /// Calls `callback` with the new state whenever output is paused or
+  /// resumed, whether from the UI, a meta chord or a hand gesture.
+  pub fn watch_output_paused<F: Fn(bool) + Send + 'static>(&self, callback: F) {
+    let control = Arc::clone(&self.control);
+    thread::spawn(move || loop {
+      futures::executor::block_on(control.output_paused_changed.notified());
+      callback(control.is_output_paused());
+    });
+  }
+
+  /// PIN for pairing devices with the Brokenithm server, once pairing is on.
+  pub fn get_brokenithm_pin(&self) -> Option<u32> {
+    self.control.get_brokenithm_pin()
+  }
+
+  /// Forgets a device paired with the Brokenithm server and closes its open
+  /// connections.
+  pub fn revoke_paired_device(&self, id: &str) -> bool {
+    let revoked = revoke_paired_device(id);
+    self.control.devices_revoked.notify_waiters();
+    revoked
+  }
+
+  /// Status reported by workers of the running context, such as the ports
+  /// the Brokenithm server bound.
+  pub fn get_worker_status(&self) -> Value {
+    self.control.get_worker_status()
+  }
+
+  /// Websocket clients connected to the Brokenithm server.
+  pub fn get_brokenithm_clients(&self) -> Value {
+    self.control.brokenithm_clients.lock().to_serde_value()
+  }
+
   pub fn get_timer_state(&self) -> String {
     let context_handle = self.context.lock();
     context_handle
@@ -0,0 +1,136 @@
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetaAction {
+  SwitchProfile,
+  ToggleAir,
+  PauseOutput,
+  CycleLights,
+  BrightnessUp,
+  BrightnessDown,
+}
+
+impl MetaAction {
+  pub fn from_str(s: &str) -> Option<Self> {
+    match s {
+      "switch-profile" => Some(MetaAction::SwitchProfile),
+      "toggle-air" => Some(MetaAction::ToggleAir),
+      "pause-output" => Some(MetaAction::PauseOutput),
+      "cycle-lights" => Some(MetaAction::CycleLights),
+      "brightness-up" => Some(MetaAction::BrightnessUp),
+      "brightness-down" => Some(MetaAction::BrightnessDown),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      MetaAction::SwitchProfile => "switch-profile",
+      MetaAction::ToggleAir => "toggle-air",
+      MetaAction::PauseOutput => "pause-output",
+      MetaAction::CycleLights => "cycle-lights",
+      MetaAction::BrightnessUp => "brightness-up",
+      MetaAction::BrightnessDown => "brightness-down",
+    }
+  }
+}
+
+/// A set of buttons that must be held together to trigger an action.
+#[derive(Debug, Clone)]
+pub struct MetaChord {
+  /// Indices into the flat input, see `SliderInput::to_flat`.
+  pub buttons: Vec<usize>,
+  pub action: MetaAction,
+}
+
+impl MetaChord {
+  /// Parses a button name such as `ground12`, `air3` or `extra0` into an index
+  /// of the flat input.
+  fn parse_button(s: &str) -> Option<usize> {
+    let (idx, count, offset) = if let Some(idx) = s.strip_prefix("ground") {
+      (idx, 32, 0)
+    } else if let Some(idx) = s.strip_prefix("air") {
+      (idx, 6, 32)
+    } else {
+      (s.strip_prefix("extra")?, 3, 38)
+    };
+
+    let idx = idx.parse::<usize>().ok()?;
+    match idx < count {
+      true => Some(offset + idx),
+      false => None,
+    }
+  }
+
+  pub fn from_serde_value(v: &Value) -> Option<Self> {
+    let buttons = v["buttons"]
+      .as_array()?
+      .iter()
+      .map(|x| Self::parse_button(x.as_str()?))
+      .collect::<Option<Vec<usize>>>()?;
+    if buttons.is_empty() {
+      return None;
+    }
+
+    Some(Self {
+      buttons,
+      action: MetaAction::from_str(v["action"].as_str()?)?,
+    })
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetaConfig {
+  pub chords: Vec<MetaChord>,
+}
+
+impl MetaConfig {
+  pub fn from_serde_value(v: &Value) -> Self {
+    Self {
+      chords: v["metaChords"]
+        .as_array()
+        .map(|chords| {
+          chords
+            .iter()
+            .filter_map(MetaChord::from_serde_value)
+            .collect()
+        })
+        .unwrap_or_default(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn parses_buttons() {
+    assert_eq!(MetaChord::parse_button("ground0"), Some(0));
+    assert_eq!(MetaChord::parse_button("ground31"), Some(31));
+    assert_eq!(MetaChord::parse_button("air5"), Some(37));
+    assert_eq!(MetaChord::parse_button("extra2"), Some(40));
+    assert_eq!(MetaChord::parse_button("ground32"), None);
+    assert_eq!(MetaChord::parse_button("air6"), None);
+    assert_eq!(MetaChord::parse_button("extra"), None);
+    assert_eq!(MetaChord::parse_button("extra-1"), None);
+    assert_eq!(MetaChord::parse_button("button1"), None);
+  }
+
+  #[test]
+  fn parses_chords() {
+    let config = MetaConfig::from_serde_value(&json!({
+      "metaChords": [
+        { "buttons": ["extra0", "air1"], "action": "toggle-air" },
+        { "buttons": [], "action": "toggle-air" },
+        { "buttons": ["extra0", "air9"], "action": "toggle-air" },
+        { "buttons": ["extra0"], "action": "explode" },
+      ],
+    }));
+    assert_eq!(config.chords.len(), 1);
+    assert_eq!(config.chords[0].buttons, vec![38, 33]);
+    assert_eq!(config.chords[0].action, MetaAction::ToggleAir);
+    assert!(MetaConfig::from_serde_value(&json!({})).chords.is_empty());
+  }
+}
@@ -0,0 +1,237 @@
+use log::info;
+use std::{
+  sync::{atomic::Ordering, Arc},
+  time::{Duration, Instant},
+};
+
+use crate::state::{SliderControl, SliderState};
+
+use super::config::{MetaAction, MetaChord, MetaConfig};
+
+const BRIGHTNESS_STEP: u8 = 32;
+
+/// How long the first buttons of a chord are held back waiting for the rest.
+const CHORD_WINDOW: Duration = Duration::from_millis(150);
+
+/// Flat input index of `extra0`. Ground pads and air strings come before it
+/// and are played on, so they are never held back or swallowed.
+const FIRST_EXTRA: usize = 38;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChordState {
+  /// No member is held.
+  Idle,
+  /// Some members are held since the given time and kept from the output
+  /// until the chord completes or the window runs out.
+  Pending(Instant),
+  /// The chord fired, members are swallowed until all are released.
+  Latched,
+  /// The window ran out, members reach the output until all are released.
+  Passed,
+}
+
+/// Watches the flat input for meta-button chords. Extra buttons belonging to a
+/// chord are held back while the chord is being pressed and swallowed from
+/// the moment it is completed until all of them are released, so they never
+/// reach an output. Extra buttons that do not complete the chord within
+/// `CHORD_WINDOW` reach the output late instead. Ground pads and air strings
+/// of a chord always reach the output right away, so that playing is never
+/// delayed.
+pub struct MetaLayer {
+  chords: Vec<MetaChord>,
+  states: Vec<ChordState>,
+  control: Arc<SliderControl>,
+}
+
+impl MetaLayer {
+  pub fn new(state: &SliderState, config: &MetaConfig) -> Self {
+    Self {
+      chords: config.chords.clone(),
+      states: vec![ChordState::Idle; config.chords.len()],
+      control: Arc::clone(&state.control),
+    }
+  }
+
+  pub fn process(&mut self, flat_input: &mut [bool]) {
+    self.process_at(flat_input, Instant::now());
+  }
+
+  fn process_at(&mut self, flat_input: &mut [bool], now: Instant) {
+    let mut held_back = vec![false; flat_input.len()];
+    for (chord, state) in self.chords.iter().zip(self.states.iter_mut()) {
+      let all_held = chord.buttons.iter().all(|x| flat_input[*x]);
+      let any_held = chord.buttons.iter().any(|x| flat_input[*x]);
+
+      *state = match (*state, all_held, any_held) {
+        (_, _, false) => ChordState::Idle,
+        (ChordState::Latched, _, _) => ChordState::Latched,
+        (_, true, _) => {
+          apply_action(&self.control, chord.action);
+          ChordState::Latched
+        }
+        (ChordState::Idle, false, true) => ChordState::Pending(now),
+        (ChordState::Pending(start), false, true) if now - start >= CHORD_WINDOW => {
+          ChordState::Passed
+        }
+        (state, false, true) => state,
+      };
+
+      if matches!(*state, ChordState::Pending(_) | ChordState::Latched) {
+        for x in chord.buttons.iter().filter(|x| **x >= FIRST_EXTRA) {
+          held_back[*x] = true;
+        }
+      }
+    }
+
+    for (x, held_back) in flat_input.iter_mut().zip(held_back) {
+      if held_back {
+        *x = false;
+      }
+    }
+  }
+}
+
+pub fn apply_action(control: &SliderControl, action: MetaAction) {
+  info!("Meta action {:?}", action);
+  match action {
+    MetaAction::SwitchProfile => {
+      control.profile_switch.notify_one();
+    }
+    MetaAction::ToggleAir => {
+      control.air_disabled.fetch_xor(true, Ordering::SeqCst);
+    }
+    MetaAction::PauseOutput => {
+      control.toggle_output_paused();
+    }
+    MetaAction::CycleLights => {
+      control.lights_cycle.fetch_add(1, Ordering::SeqCst);
+    }
+    MetaAction::BrightnessUp => {
+      control
+        .brightness
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| {
+          Some(x.saturating_add(BRIGHTNESS_STEP))
+        })
+        .ok();
+    }
+    MetaAction::BrightnessDown => {
+      control
+        .brightness
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| {
+          Some(x.saturating_sub(BRIGHTNESS_STEP))
+        })
+        .ok();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn layer(buttons: &[&str], action: &str) -> (SliderState, MetaLayer) {
+    let state = SliderState::new();
+    let config = MetaConfig::from_serde_value(&json!({
+      "metaChords": [{ "buttons": buttons, "action": action }],
+    }));
+    let layer = MetaLayer::new(&state, &config);
+    (state, layer)
+  }
+
+  fn input(held: &[usize]) -> Vec<bool> {
+    let mut flat_input = vec![false; 41];
+    for x in held {
+      flat_input[*x] = true;
+    }
+    flat_input
+  }
+
+  #[test]
+  fn swallows_completed_chord() {
+    let (state, mut layer) = layer(&["extra0", "extra1"], "pause-output");
+    let start = Instant::now();
+
+    let mut flat_input = input(&[38, 3]);
+    layer.process_at(&mut flat_input, start);
+    assert_eq!(flat_input, input(&[3]));
+
+    let mut flat_input = input(&[38, 39]);
+    layer.process_at(&mut flat_input, start + Duration::from_millis(50));
+    assert_eq!(flat_input, input(&[]));
+    assert!(state.control.output_paused.load(Ordering::SeqCst));
+
+    // Still swallowed while being released, and fires only once
+    let mut flat_input = input(&[39]);
+    layer.process_at(&mut flat_input, start + Duration::from_millis(400));
+    assert_eq!(flat_input, input(&[]));
+    let mut flat_input = input(&[38, 39]);
+    layer.process_at(&mut flat_input, start + Duration::from_millis(450));
+    assert!(state.control.output_paused.load(Ordering::SeqCst));
+
+    let mut flat_input = input(&[]);
+    layer.process_at(&mut flat_input, start + Duration::from_millis(500));
+    let mut flat_input = input(&[38]);
+    layer.process_at(&mut flat_input, start + Duration::from_millis(550));
+    assert_eq!(flat_input, input(&[]));
+  }
+
+  #[test]
+  fn passes_incomplete_chord_after_window() {
+    let (state, mut layer) = layer(&["extra0", "extra1"], "toggle-air");
+    let start = Instant::now();
+
+    let mut flat_input = input(&[38]);
+    layer.process_at(&mut flat_input, start);
+    assert_eq!(flat_input, input(&[]));
+
+    let mut flat_input = input(&[38]);
+    layer.process_at(&mut flat_input, start + CHORD_WINDOW);
+    assert_eq!(flat_input, input(&[38]));
+
+    // Completing the chord late still fires it
+    let mut flat_input = input(&[38, 39]);
+    layer.process_at(&mut flat_input, start + CHORD_WINDOW * 2);
+    assert_eq!(flat_input, input(&[]));
+    assert!(state.control.air_disabled.load(Ordering::SeqCst));
+  }
+
+  #[test]
+  fn passes_playing_buttons_right_away() {
+    let (state, mut layer) = layer(&["extra0", "extra1", "ground0"], "pause-output");
+    let start = Instant::now();
+
+    let mut flat_input = input(&[0, 38]);
+    layer.process_at(&mut flat_input, start);
+    assert_eq!(flat_input, input(&[0]));
+
+    let mut flat_input = input(&[0, 38, 39]);
+    layer.process_at(&mut flat_input, start + Duration::from_millis(50));
+    assert_eq!(flat_input, input(&[0]));
+    assert!(state.control.output_paused.load(Ordering::SeqCst));
+
+    // Playing the pad on its own is never held back
+    let mut flat_input = input(&[]);
+    layer.process_at(&mut flat_input, start + Duration::from_millis(100));
+    let mut flat_input = input(&[0]);
+    layer.process_at(&mut flat_input, start + Duration::from_millis(150));
+    assert_eq!(flat_input, input(&[0]));
+  }
+
+  #[test]
+  fn releases_pending_chord() {
+    let (_, mut layer) = layer(&["extra0", "extra1"], "cycle-lights");
+    let start = Instant::now();
+
+    let mut flat_input = input(&[38]);
+    layer.process_at(&mut flat_input, start);
+    let mut flat_input = input(&[]);
+    layer.process_at(&mut flat_input, start + Duration::from_millis(50));
+
+    // A new press starts a new window
+    let mut flat_input = input(&[39]);
+    layer.process_at(&mut flat_input, start + CHORD_WINDOW);
+    assert_eq!(flat_input, input(&[]));
+    assert_eq!(layer.states[0], ChordState::Pending(start + CHORD_WINDOW));
+  }
+}
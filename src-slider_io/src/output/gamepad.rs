@@ -1,10 +1,15 @@
+#[cfg(not(windows))]
 use log::error;
-use std::error::Error;
-use vigem_client::{Client, TargetId, XButtons, XGamepad, Xbox360Wired};
 
 use crate::shared::voltex::VoltexState;
 
-use super::{config::GamepadLayout, output::OutputHandler};
+#[cfg(windows)]
+use super::windows;
+use super::{
+  config::GamepadLayout,
+  output::OutputHandler,
+  sink::{XButtons, XboxReport, XboxSink},
+};
 
 struct LastWind {
   left: bool,
@@ -43,53 +48,37 @@ impl LastWind {
 }
 
 pub struct GamepadOutput {
-  target: Xbox360Wired<Client>,
+  sink: Box<dyn XboxSink>,
   use_air: bool,
-  gamepad: XGamepad,
+  report: XboxReport,
   left_wind: LastWind,
   right_wind: LastWind,
 }
 
 impl GamepadOutput {
+  #[cfg(windows)]
   pub fn new(layout: GamepadLayout) -> Option<Self> {
-    let target = Self::get_target();
+    Some(Self::with_sink(layout, windows::xbox_sink()?))
+  }
+
+  #[cfg(not(windows))]
+  pub fn new(_layout: GamepadLayout) -> Option<Self> {
+    error!("Gamepad emulation is only supported on Windows");
+    None
+  }
+
+  pub fn with_sink(layout: GamepadLayout, sink: Box<dyn XboxSink>) -> Self {
     let use_air = match layout {
       GamepadLayout::Neardayo => true,
       _ => false,
     };
 
-    match target {
-      Ok(target) => Some(Self {
-        target,
-        use_air,
-        gamepad: XGamepad::default(),
-        left_wind: LastWind::new(),
-        right_wind: LastWind::new(),
-      }),
-      Err(e) => {
-        error!("Gamepad connection error: {}", e);
-        error!("Gamepad connection error: Is ViGEMBus missing?");
-        None
-      }
-    }
-  }
-
-  fn get_target() -> Result<Xbox360Wired<Client>, Box<dyn Error>> {
-    let client = Client::connect()?;
-
-    let mut target = Xbox360Wired::new(client, TargetId::XBOX360_WIRED);
-    target.plugin()?;
-    target.wait_ready()?;
-    Ok(target)
-  }
-
-  fn update(&mut self) -> bool {
-    match self.target.update(&self.gamepad) {
-      Ok(_) => true,
-      Err(e) => {
-        error!("Gamepad update error: {}", e);
-        false
-      }
+    Self {
+      sink,
+      use_air,
+      report: XboxReport::default(),
+      left_wind: LastWind::new(),
+      right_wind: LastWind::new(),
     }
   }
 }
@@ -132,42 +121,23 @@ impl OutputHandler for GamepadOutput {
       voltex_state.laser[3] || (self.use_air && flat_input[37]),
     ) * 20000;
 
-    let mut dirty = false;
-    if self.gamepad.buttons.raw != buttons {
-      self.gamepad.buttons.raw = buttons;
-      dirty = true;
-    }
-    if self.gamepad.thumb_lx != lx {
-      self.gamepad.thumb_lx = lx;
-      dirty = true;
-    }
-    if self.gamepad.thumb_rx != rx {
-      self.gamepad.thumb_rx = rx;
-      dirty = true;
-    }
+    let next = XboxReport {
+      buttons,
+      thumb_lx: lx,
+      thumb_rx: rx,
+    };
 
-    match dirty {
-      true => self.update(),
+    match next != self.report {
+      true => {
+        self.report = next;
+        self.sink.update_xbox(&self.report)
+      }
       false => true,
     }
   }
 
   fn reset(&mut self) {
-    self.gamepad = XGamepad::default();
-    self.update();
+    self.report = XboxReport::default();
+    self.sink.update_xbox(&self.report);
   }
 }
-
-impl Drop for GamepadOutput {
-  fn drop(&mut self) {
-    match self.target.unplug() {
-      Ok(_) => {}
-      Err(e) => {
-        error!("Gamepad unplug error: {}", e);
-      }
-    }
-  }
-}
-
-// dammit vigem_client::Event
-unsafe impl Send for GamepadOutput {}
@@ -1,56 +1,44 @@
+#[cfg(not(windows))]
 use log::error;
-use std::error::Error;
-use vigem_client::{Client, DS4Report, DualShock4Wired, TargetId};
 
 use crate::shared::hori::HoriState;
 
-use super::{config::HoriLayout, output::OutputHandler};
+#[cfg(windows)]
+use super::windows;
+use super::{
+  config::HoriLayout,
+  output::OutputHandler,
+  sink::{Ds4Report, Ds4Sink},
+};
 
 pub struct HoriOutput {
-  target: DualShock4Wired<Client>,
+  sink: Box<dyn Ds4Sink>,
   slider_only: bool,
-  gamepad: DS4Report,
+  report: Ds4Report,
 }
 
 impl HoriOutput {
+  #[cfg(windows)]
   pub fn new(layout: HoriLayout) -> Option<Self> {
-    let target = Self::get_target();
+    Some(Self::with_sink(layout, windows::ds4_sink()?))
+  }
+
+  #[cfg(not(windows))]
+  pub fn new(_layout: HoriLayout) -> Option<Self> {
+    error!("Gamepad emulation is only supported on Windows");
+    None
+  }
 
+  pub fn with_sink(layout: HoriLayout, sink: Box<dyn Ds4Sink>) -> Self {
     let slider_only = match layout {
       HoriLayout::Full => false,
       HoriLayout::SliderOnly => true,
     };
 
-    match target {
-      Ok(target) => Some(Self {
-        target,
-        slider_only,
-        gamepad: DS4Report::default(),
-      }),
-      Err(e) => {
-        error!("Gamepad connection error: {}", e);
-        error!("Gamepad connection error: Is ViGEMBus missing?");
-        None
-      }
-    }
-  }
-
-  fn get_target() -> Result<DualShock4Wired<Client>, Box<dyn Error>> {
-    let client = Client::connect()?;
-
-    let mut target = DualShock4Wired::new(client, TargetId::DUALSHOCK4_WIRED);
-    target.plugin()?;
-    target.wait_ready()?;
-    Ok(target)
-  }
-
-  fn update(&mut self) -> bool {
-    match self.target.update(&self.gamepad) {
-      Ok(_) => true,
-      Err(e) => {
-        error!("Gamepad update error: {}", e);
-        false
-      }
+    Self {
+      sink,
+      slider_only,
+      report: Ds4Report::default(),
     }
   }
 }
@@ -98,16 +86,16 @@ impl OutputHandler for HoriOutput {
       ^ 0x80808080;
 
     let mut dirty = false;
-    if self.gamepad.buttons != buttons {
-      self.gamepad.buttons = buttons;
+    if self.report.buttons != buttons {
+      self.report.buttons = buttons;
       dirty = true;
     }
 
     for (idx, state) in [
-      &mut self.gamepad.thumb_lx,
-      &mut self.gamepad.thumb_ly,
-      &mut self.gamepad.thumb_rx,
-      &mut self.gamepad.thumb_ry,
+      &mut self.report.thumb_lx,
+      &mut self.report.thumb_ly,
+      &mut self.report.thumb_rx,
+      &mut self.report.thumb_ry,
     ]
     .into_iter()
     .enumerate()
@@ -120,27 +108,13 @@ impl OutputHandler for HoriOutput {
     }
 
     match dirty {
-      true => self.update(),
+      true => self.sink.update_ds4(&self.report),
       false => true,
     }
   }
 
   fn reset(&mut self) {
-    self.gamepad = DS4Report::default();
-    self.update();
-  }
-}
-
-impl Drop for HoriOutput {
-  fn drop(&mut self) {
-    match self.target.unplug() {
-      Ok(_) => {}
-      Err(e) => {
-        error!("Gamepad unplug error: {}", e);
-      }
-    }
+    self.report = Ds4Report::default();
+    self.sink.update_ds4(&self.report);
   }
 }
-
-// dammit vigem_client::Event
-unsafe impl Send for HoriOutput {}
@@ -1,15 +1,14 @@
-use interception::{Interception, KeyState, ScanCode, Stroke};
-use log::{error, info};
-use std::mem;
-use winapi::{
-  ctypes::c_int,
-  um::winuser::{
-    MapVirtualKeyA, SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, MAPVK_VK_TO_VSC,
-  },
+#[cfg(not(windows))]
+use log::error;
+
+#[cfg(windows)]
+use super::windows;
+use super::{
+  config::KeyboardLayout,
+  output::OutputHandler,
+  sink::{KeyEvent, KeyboardSink},
 };
 
-use super::{config::KeyboardLayout, output::OutputHandler};
-
 #[rustfmt::skip]
 const TASOLLER_KB_MAP: [usize; 41] = [
     0x41 /* A */, 0x31 /* 1 */, 0x5a /* Z */, 0x51 /* Q */, 0x53 /* S */, 0x32 /* 2 */, 0x58 /* X */, 0x57 /* W */, 
@@ -129,23 +128,29 @@ const VOLTEX_KB_MAP_NEARDAYO: [usize; 41] = [
 pub struct KeyboardOutput {
   input_to_idx: [usize; 41],
   key_idx_to_keycode: [u16; 41],
-  key_idx_to_scancode: [Option<ScanCode>; 41],
   next_keys: [bool; 41],
   last_keys: [bool; 41],
 
-  direct_input: bool,
-  interception_handle: Option<Interception>,
-
-  kb_buf: [INPUT; 41],
-  kb_direct_buf: [Stroke; 41],
-  n_kb_buf: u32,
+  sink: Box<dyn KeyboardSink>,
+  events: Vec<KeyEvent>,
 }
 
-// interception isn't send, but lazy to wrap
-unsafe impl Send for KeyboardOutput {}
-
 impl KeyboardOutput {
-  pub fn new(layout: KeyboardLayout, direct_input: bool) -> Self {
+  #[cfg(windows)]
+  pub fn new(layout: KeyboardLayout, direct_input: bool) -> Option<Self> {
+    Some(Self::with_sink(
+      layout,
+      windows::keyboard_sink(direct_input),
+    ))
+  }
+
+  #[cfg(not(windows))]
+  pub fn new(_layout: KeyboardLayout, _direct_input: bool) -> Option<Self> {
+    error!("Keyboard emulation is only supported on Windows");
+    None
+  }
+
+  pub fn with_sink(layout: KeyboardLayout, sink: Box<dyn KeyboardSink>) -> Self {
     let kb_map = match layout {
       KeyboardLayout::Tasoller => &TASOLLER_KB_MAP,
       KeyboardLayout::Yuancon => &YUANCON_KB_MAP,
@@ -160,7 +165,6 @@ impl KeyboardOutput {
 
     let mut input_to_key_idx = [0 as usize; 41];
     let mut key_idx_to_keycode = [0 as u16; 41];
-    let mut key_idx_to_scancode = [None as Option<ScanCode>; 41];
     let mut keycode_to_idx = [0xffff as usize; 256];
     let mut keycode_count: usize = 0;
 
@@ -168,71 +172,24 @@ impl KeyboardOutput {
       if keycode_to_idx[*keycode] == 0xffff {
         keycode_to_idx[*keycode] = keycode_count;
         key_idx_to_keycode[keycode_count] = *keycode as u16;
-        key_idx_to_scancode[keycode_count] =
-          ScanCode::try_from(unsafe { MapVirtualKeyA((*keycode) as u32, MAPVK_VK_TO_VSC) as u16 })
-            .ok();
-        // info!(
-        //   "mapped {:?} to {:?}",
-        //   key_idx_to_keycode[keycode_count], key_idx_to_scancode[keycode_count]
-        // );
         keycode_count += 1;
       }
       input_to_key_idx[ground] = keycode_to_idx[*keycode]
     }
 
-    let interception_handle = match direct_input {
-      true => {
-        let inner_handle = Interception::new();
-
-        if inner_handle.is_some() {
-          info!("Keyboard emulation with interception loaded");
-        } else {
-          error!("Keyboard emulation cannot load interception, falling back to SendKeys()");
-        }
-        inner_handle
-      }
-      false => None,
-    };
-    let direct_input = interception_handle.is_some();
-
-    let mut kb_buf = [INPUT {
-      type_: INPUT_KEYBOARD,
-      u: unsafe { mem::zeroed() },
-    }; 41];
-
-    for i in kb_buf.iter_mut() {
-      let mut inner = unsafe { i.u.ki_mut() };
-      inner.wVk = 0;
-      inner.wScan = 0;
-      inner.dwFlags = 0;
-      inner.time = 0;
-      inner.dwExtraInfo = 0;
-    }
-
-    let kb_direct_buf = [Stroke::Keyboard {
-      code: ScanCode::Esc,
-      state: KeyState::UP,
-      information: 0,
-    }; 41];
-
     Self {
       input_to_idx: input_to_key_idx,
       key_idx_to_keycode,
-      key_idx_to_scancode,
       next_keys: [false; 41],
       last_keys: [false; 41],
 
-      direct_input,
-      interception_handle,
-
-      kb_buf,
-      kb_direct_buf,
-      n_kb_buf: 0,
+      sink,
+      events: Vec::with_capacity(41),
     }
   }
 
   fn send(&mut self) {
-    self.n_kb_buf = 0;
+    self.events.clear();
 
     for (i, (n, l)) in self
       .next_keys
@@ -241,72 +198,26 @@ impl KeyboardOutput {
       .enumerate()
     {
       let keycode = self.key_idx_to_keycode[i];
-      let scancode = self.key_idx_to_scancode[i];
 
-      if (!self.direct_input && keycode == 0) || (self.direct_input && scancode.is_none()) {
+      if keycode == 0 {
         continue;
       }
-      match (self.direct_input, *n, *l) {
-        (false, true, false) => {
-          let inner: &mut KEYBDINPUT = unsafe { self.kb_buf[self.n_kb_buf as usize].u.ki_mut() };
-          inner.wVk = keycode;
-          inner.dwFlags = 0;
-          self.n_kb_buf += 1;
-        }
-        (false, false, true) => {
-          let inner: &mut KEYBDINPUT = unsafe { self.kb_buf[self.n_kb_buf as usize].u.ki_mut() };
-          inner.wVk = keycode;
-          inner.dwFlags = KEYEVENTF_KEYUP;
-          self.n_kb_buf += 1;
-        }
-        (true, true, false) => {
-          // info!("keydown {:?}", scancode);
-          let inner: &mut Stroke = &mut self.kb_direct_buf[self.n_kb_buf as usize];
-          if let Stroke::Keyboard {
-            code,
-            state,
-            information: _,
-          } = inner
-          {
-            *code = scancode.unwrap();
-            *state = KeyState::DOWN;
-            self.n_kb_buf += 1;
-          }
-        }
-        (true, false, true) => {
-          // info!("keyup {:?}", scancode);
-          let inner: &mut Stroke = &mut self.kb_direct_buf[self.n_kb_buf as usize];
-          if let Stroke::Keyboard {
-            code,
-            state,
-            information: _,
-          } = inner
-          {
-            *code = scancode.unwrap();
-            *state = KeyState::UP;
-            self.n_kb_buf += 1;
-          }
-        }
+      match (*n, *l) {
+        (true, false) => self.events.push(KeyEvent {
+          keycode,
+          down: true,
+        }),
+        (false, true) => self.events.push(KeyEvent {
+          keycode,
+          down: false,
+        }),
         _ => {}
       }
       *l = *n;
     }
 
-    if self.n_kb_buf != 0 {
-      match self.direct_input {
-        false => unsafe {
-          SendInput(
-            self.n_kb_buf,
-            self.kb_buf.as_mut_ptr(),
-            mem::size_of::<INPUT>() as c_int,
-          );
-        },
-        true => {
-          if let Some(handle) = self.interception_handle.as_mut() {
-            handle.send(1, &self.kb_direct_buf[0..self.n_kb_buf as usize]);
-          }
-        }
-      }
+    if !self.events.is_empty() {
+      self.sink.send_keys(&self.events);
     }
   }
 }
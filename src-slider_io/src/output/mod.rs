@@ -1,7 +1,10 @@
 pub mod config;
 
-mod gamepad;
-mod hori;
-mod keyboard;
+pub mod gamepad;
+pub mod hori;
+pub mod keyboard;
+#[cfg(windows)]
+mod windows;
 
 pub mod output;
+pub mod sink;
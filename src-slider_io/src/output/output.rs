@@ -1,9 +1,13 @@
 use async_trait::async_trait;
-use log::error;
+use log::{error, info};
 use std::time::Duration;
 use tokio::time::{interval, Interval};
 
-use crate::{shared::worker::AsyncJob, state::SliderState};
+use crate::{
+  meta::{config::MetaConfig, meta::MetaLayer},
+  shared::worker::AsyncJob,
+  state::SliderState,
+};
 
 use super::{
   config::OutputMode, gamepad::GamepadOutput, hori::HoriOutput, keyboard::KeyboardOutput,
@@ -19,16 +23,22 @@ pub struct OutputJob {
   mode: OutputMode,
   sensitivity: u8,
   handler: Option<Box<dyn OutputHandler>>,
+  meta: MetaLayer,
+  paused: bool,
+  failsafe: usize,
   timer: Interval,
 }
 
 impl OutputJob {
-  pub fn new(state: &SliderState, mode: &OutputMode) -> Self {
+  pub fn new(state: &SliderState, mode: &OutputMode, meta: &MetaConfig) -> Self {
     Self {
       state: state.clone(),
       mode: mode.clone(),
       sensitivity: 0,
       handler: None,
+      meta: MetaLayer::new(state, meta),
+      paused: false,
+      failsafe: state.control.get_failsafe(),
       timer: interval(Duration::MAX),
     }
   }
@@ -45,10 +55,16 @@ impl AsyncJob for OutputJob {
         direct_input,
       } => {
         self.sensitivity = sensitivity;
-        self.handler = Some(Box::new(KeyboardOutput::new(layout.clone(), direct_input)));
+        let handler = KeyboardOutput::new(layout.clone(), direct_input);
         self.timer = interval(Duration::from_micros(polling.to_t_u64()));
 
-        true
+        match handler {
+          Some(handler) => {
+            self.handler = Some(Box::new(handler));
+            true
+          }
+          None => false,
+        }
       }
       OutputMode::Gamepad {
         layout,
@@ -92,14 +108,40 @@ impl AsyncJob for OutputJob {
   }
 
   async fn tick(&mut self) -> bool {
-    let flat_input = {
+    let mut flat_input = {
       let input_handle = self.state.input.lock();
       input_handle.to_flat(&self.sensitivity)
     };
 
+    // Chords are still processed while paused so that output can be resumed
+    self.meta.process(&mut flat_input);
+    if self.state.control.is_air_disabled() {
+      flat_input[32..38].fill(false);
+    }
+
+    let paused = self.state.control.is_output_paused();
+    let failsafe = self.state.control.get_failsafe();
     if let Some(handler) = self.handler.as_mut() {
-      handler.tick(&flat_input);
+      if failsafe != self.failsafe {
+        handler.reset();
+      }
+
+      match (paused, self.paused) {
+        (false, was_paused) => {
+          if was_paused {
+            info!("Output resumed");
+          }
+          handler.tick(&flat_input);
+        }
+        (true, false) => {
+          info!("Output paused");
+          handler.reset();
+        }
+        (true, true) => {}
+      }
     }
+    self.paused = paused;
+    self.failsafe = failsafe;
     self.timer.tick().await;
 
     true
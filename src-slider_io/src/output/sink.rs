@@ -0,0 +1,265 @@
+use parking_lot::Mutex;
+use std::{
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+/// A single key press or release, identified by its virtual key code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+  pub keycode: u16,
+  pub down: bool,
+}
+
+/// XInput button bits of `XboxReport::buttons`.
+pub struct XButtons;
+
+impl XButtons {
+  pub const START: u16 = 0x0010;
+  pub const BACK: u16 = 0x0020;
+  pub const LB: u16 = 0x0100;
+  pub const RB: u16 = 0x0200;
+  pub const GUIDE: u16 = 0x0400;
+  pub const A: u16 = 0x1000;
+  pub const B: u16 = 0x2000;
+  pub const X: u16 = 0x4000;
+  pub const Y: u16 = 0x8000;
+}
+
+/// Button and stick state of an emulated XBOX 360 gamepad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct XboxReport {
+  pub buttons: u16,
+  pub thumb_lx: i16,
+  pub thumb_rx: i16,
+}
+
+/// Button and stick state of an emulated DS4 gamepad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ds4Report {
+  pub buttons: u16,
+  pub thumb_lx: u8,
+  pub thumb_ly: u8,
+  pub thumb_rx: u8,
+  pub thumb_ry: u8,
+}
+
+impl Default for Ds4Report {
+  /// Sticks centered and dpad released.
+  fn default() -> Self {
+    Self {
+      buttons: 0x8,
+      thumb_lx: 0x80,
+      thumb_ly: 0x80,
+      thumb_rx: 0x80,
+      thumb_ry: 0x80,
+    }
+  }
+}
+
+/// Receives key events from `KeyboardOutput`.
+pub trait KeyboardSink: Send {
+  fn send_keys(&mut self, events: &[KeyEvent]);
+}
+
+/// Receives reports from `GamepadOutput`.
+pub trait XboxSink: Send {
+  fn update_xbox(&mut self, report: &XboxReport) -> bool;
+}
+
+/// Receives reports from `HoriOutput`.
+pub trait Ds4Sink: Send {
+  fn update_ds4(&mut self, report: &Ds4Report) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkEvent {
+  Key(KeyEvent),
+  Xbox(XboxReport),
+  Ds4(Ds4Report),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordedEvent {
+  /// Time since the sink was created.
+  pub at: Duration,
+  pub event: SinkEvent,
+}
+
+/// In-memory sink that records everything sent to it. Clones share the same
+/// recording, so a clone can be kept to inspect events sent to an output.
+#[derive(Clone)]
+pub struct RecordingSink {
+  started: Instant,
+  events: Arc<Mutex<Vec<RecordedEvent>>>,
+}
+
+impl RecordingSink {
+  pub fn new() -> Self {
+    Self {
+      started: Instant::now(),
+      events: Arc::new(Mutex::new(vec![])),
+    }
+  }
+
+  /// Removes and returns all events recorded so far.
+  pub fn take(&self) -> Vec<RecordedEvent> {
+    let mut events_handle = self.events.lock();
+    std::mem::take(&mut *events_handle)
+  }
+
+  fn record(&self, event: SinkEvent) {
+    self.events.lock().push(RecordedEvent {
+      at: self.started.elapsed(),
+      event,
+    });
+  }
+}
+
+impl KeyboardSink for RecordingSink {
+  fn send_keys(&mut self, events: &[KeyEvent]) {
+    for event in events.iter() {
+      self.record(SinkEvent::Key(*event));
+    }
+  }
+}
+
+impl XboxSink for RecordingSink {
+  fn update_xbox(&mut self, report: &XboxReport) -> bool {
+    self.record(SinkEvent::Xbox(*report));
+    true
+  }
+}
+
+impl Ds4Sink for RecordingSink {
+  fn update_ds4(&mut self, report: &Ds4Report) -> bool {
+    self.record(SinkEvent::Ds4(*report));
+    true
+  }
+}
+
+/// Runs an output through every input one at a time, then all at once, then a
+/// reset, and renders one line per step with everything the sink received.
+#[cfg(test)]
+fn run_golden(handler: &mut dyn super::output::OutputHandler, sink: &RecordingSink) -> String {
+  let mut out = String::new();
+  let mut step = |name: String, events: Vec<RecordedEvent>| {
+    out.push_str(&name);
+    out.push(':');
+    for recorded in events.iter() {
+      out.push(' ');
+      out.push_str(&match recorded.event {
+        SinkEvent::Key(e) => format!("{}{:02x}", if e.down { '+' } else { '-' }, e.keycode),
+        SinkEvent::Xbox(r) => format!("b={:04x} lx={} rx={}", r.buttons, r.thumb_lx, r.thumb_rx),
+        SinkEvent::Ds4(r) => format!(
+          "b={:04x} lx={:02x} ly={:02x} rx={:02x} ry={:02x}",
+          r.buttons, r.thumb_lx, r.thumb_ly, r.thumb_rx, r.thumb_ry
+        ),
+      });
+    }
+    out.push('\n');
+  };
+
+  for i in 0..41 {
+    let mut flat_input = vec![false; 41];
+    flat_input[i] = true;
+    handler.tick(&flat_input);
+    step(format!("press {}", i), sink.take());
+
+    handler.tick(&vec![false; 41]);
+    step(format!("release {}", i), sink.take());
+  }
+
+  handler.tick(&vec![true; 41]);
+  step("all".to_string(), sink.take());
+
+  handler.reset();
+  step("reset".to_string(), sink.take());
+
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::BTreeSet, fs, path::Path};
+
+  use super::*;
+  use crate::output::{
+    config::{GamepadLayout, HoriLayout, KeyboardLayout},
+    gamepad::GamepadOutput,
+    hori::HoriOutput,
+    keyboard::KeyboardOutput,
+    output::OutputHandler,
+  };
+
+  #[derive(Clone, Copy)]
+  enum Layout {
+    Keyboard(KeyboardLayout),
+    Gamepad(GamepadLayout),
+    Hori(HoriLayout),
+  }
+
+  /// Every golden file in `golden/` with the layout it was recorded from.
+  const GOLDEN: [(&str, Layout); 13] = [
+    (
+      "keyboard_tasoller",
+      Layout::Keyboard(KeyboardLayout::Tasoller),
+    ),
+    (
+      "keyboard_yuancon",
+      Layout::Keyboard(KeyboardLayout::Yuancon),
+    ),
+    (
+      "keyboard_umiguri",
+      Layout::Keyboard(KeyboardLayout::Umiguri),
+    ),
+    (
+      "keyboard_tasoller_half",
+      Layout::Keyboard(KeyboardLayout::TasollerHalf),
+    ),
+    ("keyboard_eight_k", Layout::Keyboard(KeyboardLayout::EightK)),
+    ("keyboard_six_k", Layout::Keyboard(KeyboardLayout::SixK)),
+    ("keyboard_four_k", Layout::Keyboard(KeyboardLayout::FourK)),
+    ("keyboard_voltex", Layout::Keyboard(KeyboardLayout::Voltex)),
+    (
+      "keyboard_neardayo",
+      Layout::Keyboard(KeyboardLayout::Neardayo),
+    ),
+    ("gamepad_voltex", Layout::Gamepad(GamepadLayout::Voltex)),
+    ("gamepad_neardayo", Layout::Gamepad(GamepadLayout::Neardayo)),
+    ("hori_full", Layout::Hori(HoriLayout::Full)),
+    ("hori_slider_only", Layout::Hori(HoriLayout::SliderOnly)),
+  ];
+
+  fn output(layout: Layout, sink: &RecordingSink) -> Box<dyn OutputHandler> {
+    let sink = Box::new(sink.clone());
+    match layout {
+      Layout::Keyboard(layout) => Box::new(KeyboardOutput::with_sink(layout, sink)),
+      Layout::Gamepad(layout) => Box::new(GamepadOutput::with_sink(layout, sink)),
+      Layout::Hori(layout) => Box::new(HoriOutput::with_sink(layout, sink)),
+    }
+  }
+
+  #[test]
+  fn golden() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/output/golden");
+    let files = fs::read_dir(&dir)
+      .unwrap()
+      .map(|entry| entry.unwrap().path())
+      .filter(|path| path.extension().map_or(false, |ext| ext == "txt"))
+      .map(|path| path.file_stem().unwrap().to_string_lossy().to_string())
+      .collect::<BTreeSet<String>>();
+    let names = GOLDEN
+      .iter()
+      .map(|(name, _)| name.to_string())
+      .collect::<BTreeSet<String>>();
+    assert_eq!(files, names, "golden files and outputs differ");
+
+    for (name, layout) in GOLDEN.iter() {
+      let sink = RecordingSink::new();
+      let mut output = output(*layout, &sink);
+      let expected = fs::read_to_string(dir.join(format!("{}.txt", name))).unwrap();
+      assert_eq!(run_golden(output.as_mut(), &sink), expected, "{}", name);
+    }
+  }
+}
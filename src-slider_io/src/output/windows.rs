@@ -0,0 +1,275 @@
+use interception::{Interception, KeyState, ScanCode, Stroke};
+use log::{error, info};
+use std::{error::Error, mem};
+use vigem_client::{Client, DS4Report, DualShock4Wired, TargetId, XGamepad, Xbox360Wired};
+use winapi::{
+  ctypes::c_int,
+  um::winuser::{
+    MapVirtualKeyA, SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, MAPVK_VK_TO_VSC,
+  },
+};
+
+use super::sink::{Ds4Report, Ds4Sink, KeyEvent, KeyboardSink, XboxReport, XboxSink};
+
+/// Keyboard sink through interception when `direct_input` is set and the
+/// driver loads, otherwise through `SendInput()`.
+pub fn keyboard_sink(direct_input: bool) -> Box<dyn KeyboardSink> {
+  let interception_handle = match direct_input {
+    true => {
+      let inner_handle = Interception::new();
+
+      if inner_handle.is_some() {
+        info!("Keyboard emulation with interception loaded");
+      } else {
+        error!("Keyboard emulation cannot load interception, falling back to SendKeys()");
+      }
+      inner_handle
+    }
+    false => None,
+  };
+
+  match interception_handle {
+    Some(handle) => Box::new(InterceptionSink::new(handle)),
+    None => Box::new(SendInputSink::new()),
+  }
+}
+
+pub fn xbox_sink() -> Option<Box<dyn XboxSink>> {
+  Some(Box::new(ViGEmXboxSink::new()?))
+}
+
+pub fn ds4_sink() -> Option<Box<dyn Ds4Sink>> {
+  Some(Box::new(ViGEmDs4Sink::new()?))
+}
+
+/// Sends key events with `SendInput()`.
+struct SendInputSink {
+  kb_buf: [INPUT; 41],
+}
+
+impl SendInputSink {
+  fn new() -> Self {
+    let mut kb_buf = [INPUT {
+      type_: INPUT_KEYBOARD,
+      u: unsafe { mem::zeroed() },
+    }; 41];
+
+    for i in kb_buf.iter_mut() {
+      let mut inner = unsafe { i.u.ki_mut() };
+      inner.wVk = 0;
+      inner.wScan = 0;
+      inner.dwFlags = 0;
+      inner.time = 0;
+      inner.dwExtraInfo = 0;
+    }
+
+    Self { kb_buf }
+  }
+}
+
+impl KeyboardSink for SendInputSink {
+  fn send_keys(&mut self, events: &[KeyEvent]) {
+    for (buf, event) in self.kb_buf.iter_mut().zip(events.iter()) {
+      let inner: &mut KEYBDINPUT = unsafe { buf.u.ki_mut() };
+      inner.wVk = event.keycode;
+      inner.dwFlags = match event.down {
+        true => 0,
+        false => KEYEVENTF_KEYUP,
+      };
+    }
+
+    let n_kb_buf = events.len().min(self.kb_buf.len());
+    if n_kb_buf != 0 {
+      unsafe {
+        SendInput(
+          n_kb_buf as u32,
+          self.kb_buf.as_mut_ptr(),
+          mem::size_of::<INPUT>() as c_int,
+        );
+      }
+    }
+  }
+}
+
+/// Sends key events as DirectInput scancodes through interception.
+struct InterceptionSink {
+  handle: Interception,
+  keycode_to_scancode: [Option<ScanCode>; 256],
+  kb_direct_buf: [Stroke; 41],
+}
+
+// interception isn't send, but lazy to wrap
+unsafe impl Send for InterceptionSink {}
+
+impl InterceptionSink {
+  fn new(handle: Interception) -> Self {
+    let mut keycode_to_scancode = [None as Option<ScanCode>; 256];
+    for (keycode, scancode) in keycode_to_scancode.iter_mut().enumerate().skip(1) {
+      *scancode =
+        ScanCode::try_from(unsafe { MapVirtualKeyA(keycode as u32, MAPVK_VK_TO_VSC) as u16 }).ok();
+    }
+
+    Self {
+      handle,
+      keycode_to_scancode,
+      kb_direct_buf: [Stroke::Keyboard {
+        code: ScanCode::Esc,
+        state: KeyState::UP,
+        information: 0,
+      }; 41],
+    }
+  }
+}
+
+impl KeyboardSink for InterceptionSink {
+  fn send_keys(&mut self, events: &[KeyEvent]) {
+    let mut n_kb_buf = 0;
+    for event in events.iter() {
+      let scancode = match self.keycode_to_scancode[event.keycode as usize & 0xff] {
+        Some(scancode) => scancode,
+        None => continue,
+      };
+      if let Some(Stroke::Keyboard {
+        code,
+        state,
+        information: _,
+      }) = self.kb_direct_buf.get_mut(n_kb_buf)
+      {
+        *code = scancode;
+        *state = match event.down {
+          true => KeyState::DOWN,
+          false => KeyState::UP,
+        };
+        n_kb_buf += 1;
+      }
+    }
+
+    if n_kb_buf != 0 {
+      self.handle.send(1, &self.kb_direct_buf[0..n_kb_buf]);
+    }
+  }
+}
+
+/// Emulated XBOX 360 gamepad through ViGEmBus.
+struct ViGEmXboxSink {
+  target: Xbox360Wired<Client>,
+  gamepad: XGamepad,
+}
+
+impl ViGEmXboxSink {
+  fn new() -> Option<Self> {
+    match Self::get_target() {
+      Ok(target) => Some(Self {
+        target,
+        gamepad: XGamepad::default(),
+      }),
+      Err(e) => {
+        error!("Gamepad connection error: {}", e);
+        error!("Gamepad connection error: Is ViGEMBus missing?");
+        None
+      }
+    }
+  }
+
+  fn get_target() -> Result<Xbox360Wired<Client>, Box<dyn Error>> {
+    let client = Client::connect()?;
+
+    let mut target = Xbox360Wired::new(client, TargetId::XBOX360_WIRED);
+    target.plugin()?;
+    target.wait_ready()?;
+    Ok(target)
+  }
+}
+
+impl XboxSink for ViGEmXboxSink {
+  fn update_xbox(&mut self, report: &XboxReport) -> bool {
+    self.gamepad.buttons.raw = report.buttons;
+    self.gamepad.thumb_lx = report.thumb_lx;
+    self.gamepad.thumb_rx = report.thumb_rx;
+
+    match self.target.update(&self.gamepad) {
+      Ok(_) => true,
+      Err(e) => {
+        error!("Gamepad update error: {}", e);
+        false
+      }
+    }
+  }
+}
+
+impl Drop for ViGEmXboxSink {
+  fn drop(&mut self) {
+    match self.target.unplug() {
+      Ok(_) => {}
+      Err(e) => {
+        error!("Gamepad unplug error: {}", e);
+      }
+    }
+  }
+}
+
+// dammit vigem_client::Event
+unsafe impl Send for ViGEmXboxSink {}
+
+/// Emulated DS4 gamepad through ViGEmBus.
+struct ViGEmDs4Sink {
+  target: DualShock4Wired<Client>,
+  gamepad: DS4Report,
+}
+
+impl ViGEmDs4Sink {
+  fn new() -> Option<Self> {
+    match Self::get_target() {
+      Ok(target) => Some(Self {
+        target,
+        gamepad: DS4Report::default(),
+      }),
+      Err(e) => {
+        error!("Gamepad connection error: {}", e);
+        error!("Gamepad connection error: Is ViGEMBus missing?");
+        None
+      }
+    }
+  }
+
+  fn get_target() -> Result<DualShock4Wired<Client>, Box<dyn Error>> {
+    let client = Client::connect()?;
+
+    let mut target = DualShock4Wired::new(client, TargetId::DUALSHOCK4_WIRED);
+    target.plugin()?;
+    target.wait_ready()?;
+    Ok(target)
+  }
+}
+
+impl Ds4Sink for ViGEmDs4Sink {
+  fn update_ds4(&mut self, report: &Ds4Report) -> bool {
+    self.gamepad.buttons = report.buttons;
+    self.gamepad.thumb_lx = report.thumb_lx;
+    self.gamepad.thumb_ly = report.thumb_ly;
+    self.gamepad.thumb_rx = report.thumb_rx;
+    self.gamepad.thumb_ry = report.thumb_ry;
+
+    match self.target.update(&self.gamepad) {
+      Ok(_) => true,
+      Err(e) => {
+        error!("Gamepad update error: {}", e);
+        false
+      }
+    }
+  }
+}
+
+impl Drop for ViGEmDs4Sink {
+  fn drop(&mut self) {
+    match self.target.unplug() {
+      Ok(_) => {}
+      Err(e) => {
+        error!("Gamepad unplug error: {}", e);
+      }
+    }
+  }
+}
+
+// dammit vigem_client::Event
+unsafe impl Send for ViGEmDs4Sink {}
@@ -0,0 +1,462 @@
+use hyper::{Body, Response, StatusCode};
+use log::{error, info, warn};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::{
+  collections::HashMap,
+  io,
+  net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+  sync::Arc,
+  time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+/// Peers not seen for this long are forgotten, in seconds.
+const PEER_EXPIRY: f64 = 60.0;
+
+/// Wrong guesses of a secret, such as the pairing PIN, that an address can
+/// make before it is locked out.
+const FREE_FAILURES: u32 = 5;
+/// Lockout after the first failure past the free ones, doubled with each
+/// further one up to `LOCKOUT_MAX`.
+const LOCKOUT_BASE: Duration = Duration::from_secs(30);
+const LOCKOUT_MAX: Duration = Duration::from_secs(60 * 60);
+/// Failures are forgotten this long after the last one.
+const FAILURE_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Times the configured port is tried, 100 ms apart, as the server of the
+/// previous config may still be releasing it.
+const BIND_ATTEMPTS: usize = 10;
+
+/// An address range such as `192.168.1.0/24` or `fe80::/10`. A plain address
+/// is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+  addr: IpAddr,
+  prefix: u8,
+}
+
+impl Cidr {
+  pub fn parse(s: &str) -> Option<Self> {
+    let (addr, prefix) = match s.trim().split_once('/') {
+      Some((addr, prefix)) => (
+        addr.parse::<IpAddr>().ok()?,
+        Some(prefix.parse::<u8>().ok()?),
+      ),
+      None => (s.trim().parse::<IpAddr>().ok()?, None),
+    };
+    let max = match addr {
+      IpAddr::V4(_) => 32,
+      IpAddr::V6(_) => 128,
+    };
+    match prefix.unwrap_or(max) {
+      prefix if prefix <= max => Some(Self { addr, prefix }),
+      _ => None,
+    }
+  }
+
+  pub fn contains(&self, ip: &IpAddr) -> bool {
+    match (self.addr, canonical(ip)) {
+      (IpAddr::V4(net), IpAddr::V4(ip)) => {
+        let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+        u32::from(net) & mask == u32::from(ip) & mask
+      }
+      (IpAddr::V6(net), IpAddr::V6(ip)) => {
+        let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+        u128::from(net) & mask == u128::from(ip) & mask
+      }
+      _ => false,
+    }
+  }
+}
+
+/// IPv4 clients of a dual stack socket show up as IPv4 mapped IPv6 addresses.
+fn canonical(ip: &IpAddr) -> IpAddr {
+  match ip {
+    IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+      Some(v4) => IpAddr::V4(v4),
+      None => *ip,
+    },
+    _ => *ip,
+  }
+}
+
+/// Parses a list of ranges from a comma separated string or an array of
+/// strings, skipping invalid entries.
+fn cidr_list(v: &Value) -> Vec<Cidr> {
+  let entries: Vec<&str> = match v {
+    Value::String(s) => s.split(',').filter(|e| !e.trim().is_empty()).collect(),
+    Value::Array(a) => a.iter().filter_map(|e| e.as_str()).collect(),
+    _ => vec![],
+  };
+  entries
+    .into_iter()
+    .filter_map(|e| match Cidr::parse(e) {
+      Some(cidr) => Some(cidr),
+      None => {
+        error!("Invalid address range {}", e);
+        None
+      }
+    })
+    .collect()
+}
+
+/// Who can reach an embedded server and how much, read from config keys that
+/// start with the server's prefix, such as `brokenithmBindAddress`.
+#[derive(Debug, Clone)]
+pub struct ServerAccessConfig {
+  /// Address the server listens on, `0.0.0.0` for every IPv4 interface.
+  pub bind: IpAddr,
+  /// Remote addresses allowed to connect, everyone when empty.
+  pub allow: Vec<Cidr>,
+  /// Remote addresses refused even when allowed.
+  pub deny: Vec<Cidr>,
+  /// Open websocket connections per remote address, 0 for no limit.
+  pub max_connections: usize,
+  /// Sustained HTTP requests per second per remote address, 0 for no limit.
+  /// Twice as many can come in a burst.
+  pub max_request_rate: f64,
+}
+
+impl ServerAccessConfig {
+  pub fn from_serde_value(v: &Value, prefix: &str) -> Self {
+    let key = |name: &str| format!("{}{}", prefix, name);
+
+    Self {
+      bind: match v[key("BindAddress")].as_str().filter(|s| !s.is_empty()) {
+        Some(s) => s.trim().parse().unwrap_or_else(|_| {
+          error!("Invalid bind address {}, listening on all interfaces", s);
+          IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        }),
+        None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+      },
+      allow: cidr_list(&v[key("Allow")]),
+      deny: cidr_list(&v[key("Deny")]),
+      max_connections: v[key("MaxConnections")].as_u64().map_or(8, |n| n as usize),
+      max_request_rate: v[key("MaxRequestRate")]
+        .as_f64()
+        .filter(|r| *r >= 0.0)
+        .unwrap_or(20.0),
+    }
+  }
+
+  pub fn bind_addr(&self, port: u16) -> SocketAddr {
+    SocketAddr::new(self.bind, port)
+  }
+
+  /// Binds `port`, or else the first free one of the `fallback` ports after
+  /// it. The error is that of the configured port.
+  pub async fn bind(&self, port: u16, fallback: u16) -> io::Result<TcpListener> {
+    let addr = self.bind_addr(port);
+    let mut attempts = 0;
+    let error = loop {
+      match TcpListener::bind(addr) {
+        Ok(listener) => return nonblocking(listener),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse && attempts + 1 < BIND_ATTEMPTS => {
+          attempts += 1;
+          sleep(Duration::from_millis(100)).await;
+        }
+        Err(e) => break e,
+      }
+    };
+
+    for next in port.saturating_add(1)..=port.saturating_add(fallback) {
+      if let Ok(listener) = TcpListener::bind(self.bind_addr(next)) {
+        info!("Port {} is taken, using {} instead", port, next);
+        return nonblocking(listener);
+      }
+    }
+    Err(error)
+  }
+
+  /// Whether the lists let a remote address in.
+  pub fn permits(&self, ip: &IpAddr) -> bool {
+    (self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip)))
+      && !self.deny.iter().any(|c| c.contains(ip))
+  }
+}
+
+fn nonblocking(listener: TcpListener) -> io::Result<TcpListener> {
+  listener.set_nonblocking(true)?;
+  Ok(listener)
+}
+
+struct Peer {
+  connections: usize,
+  /// Requests that can still be made right now.
+  tokens: f64,
+  last: Instant,
+  /// Wrong guesses since the last right one, and when the last was made.
+  failures: u32,
+  last_failure: Instant,
+}
+
+impl Peer {
+  fn new(tokens: f64, now: Instant) -> Self {
+    Self {
+      connections: 0,
+      tokens,
+      last: now,
+      failures: 0,
+      last_failure: now,
+    }
+  }
+
+  /// When the address may guess again, `None` when it is not locked out.
+  fn locked_until(&self) -> Option<Instant> {
+    let extra = self.failures.checked_sub(FREE_FAILURES)?;
+    let lockout = LOCKOUT_BASE
+      .checked_mul(1 << extra.min(16))
+      .map_or(LOCKOUT_MAX, |lockout| lockout.min(LOCKOUT_MAX));
+    Some(self.last_failure + lockout)
+  }
+
+  fn expired(&self, now: Instant) -> bool {
+    self.connections == 0
+      && (now - self.last).as_secs_f64() >= PEER_EXPIRY
+      && (self.failures == 0 || now - self.last_failure >= FAILURE_EXPIRY)
+  }
+}
+
+/// Enforces a `ServerAccessConfig` for one server.
+pub struct ServerAccess {
+  config: ServerAccessConfig,
+  peers: Mutex<HashMap<IpAddr, Peer>>,
+}
+
+impl ServerAccess {
+  pub fn new(config: &ServerAccessConfig) -> Arc<Self> {
+    Arc::new(Self {
+      config: config.clone(),
+      peers: Mutex::new(HashMap::new()),
+    })
+  }
+
+  pub fn permits(&self, ip: &IpAddr) -> bool {
+    self.config.permits(ip)
+  }
+
+  fn burst(&self) -> f64 {
+    self.config.max_request_rate * 2.0
+  }
+
+  /// Checks a request before it is served or upgraded, returning the status
+  /// to refuse it with.
+  pub fn check_request(&self, ip: &IpAddr) -> Result<(), StatusCode> {
+    if !self.permits(ip) {
+      warn!("Refused request from {}", ip);
+      return Err(StatusCode::FORBIDDEN);
+    }
+    if self.config.max_request_rate <= 0.0 {
+      return Ok(());
+    }
+
+    let now = Instant::now();
+    let mut peers = self.peers.lock();
+    peers.retain(|_, p| !p.expired(now));
+    let peer = peers
+      .entry(canonical(ip))
+      .or_insert_with(|| Peer::new(self.burst(), now));
+    peer.tokens = (peer.tokens + (now - peer.last).as_secs_f64() * self.config.max_request_rate)
+      .min(self.burst());
+    peer.last = now;
+    match peer.tokens >= 1.0 {
+      true => {
+        peer.tokens -= 1.0;
+        Ok(())
+      }
+      false => {
+        warn!("Too many requests from {}", ip);
+        Err(StatusCode::TOO_MANY_REQUESTS)
+      }
+    }
+  }
+
+  /// Counts a new websocket connection, returning a guard that releases it
+  /// when dropped, or the status to refuse it with when the address has too
+  /// many open.
+  pub fn connect(self: &Arc<Self>, ip: &IpAddr) -> Result<ConnectionGuard, StatusCode> {
+    let ip = canonical(ip);
+    let mut peers = self.peers.lock();
+    let peer = peers
+      .entry(ip)
+      .or_insert_with(|| Peer::new(self.burst(), Instant::now()));
+    if self.config.max_connections > 0 && peer.connections >= self.config.max_connections {
+      warn!("Too many connections from {}", ip);
+      return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+    peer.connections += 1;
+    Ok(ConnectionGuard {
+      access: self.clone(),
+      ip,
+    })
+  }
+
+  /// How long an address still has to wait before guessing again after too
+  /// many wrong guesses, `None` when it may guess now.
+  pub fn locked_out(&self, ip: &IpAddr) -> Option<Duration> {
+    let until = self.peers.lock().get(&canonical(ip))?.locked_until()?;
+    until.checked_duration_since(Instant::now())
+  }
+
+  /// Counts a wrong guess from an address, returning how long it is locked
+  /// out for when that was one too many.
+  pub fn record_failure(&self, ip: &IpAddr) -> Option<Duration> {
+    let now = Instant::now();
+    let mut peers = self.peers.lock();
+    let peer = peers
+      .entry(canonical(ip))
+      .or_insert_with(|| Peer::new(self.burst(), now));
+    peer.failures += 1;
+    peer.last_failure = now;
+    let lockout = peer.locked_until()? - now;
+    warn!(
+      "{} wrong guesses from {}, locked out for {} s",
+      peer.failures,
+      ip,
+      lockout.as_secs()
+    );
+    Some(lockout)
+  }
+
+  /// Forgets the wrong guesses of an address after a right one.
+  pub fn clear_failures(&self, ip: &IpAddr) {
+    if let Some(peer) = self.peers.lock().get_mut(&canonical(ip)) {
+      peer.failures = 0;
+    }
+  }
+}
+
+/// An open connection counted against its remote address.
+pub struct ConnectionGuard {
+  access: Arc<ServerAccess>,
+  ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+  fn drop(&mut self) {
+    if let Some(peer) = self.access.peers.lock().get_mut(&self.ip) {
+      peer.connections = peer.connections.saturating_sub(1);
+      peer.last = Instant::now();
+    }
+  }
+}
+
+/// Response for a refused request or connection.
+pub fn refused(status: StatusCode) -> Response<Body> {
+  Response::builder()
+    .status(status)
+    .body(Body::from(status.canonical_reason().unwrap_or("Refused")))
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+  }
+
+  fn config(allow: &str, deny: &str) -> ServerAccessConfig {
+    ServerAccessConfig::from_serde_value(
+      &serde_json::json!({
+        "testAllow": allow,
+        "testDeny": deny,
+        "testMaxConnections": 2,
+        "testMaxRequestRate": 1.0,
+      }),
+      "test",
+    )
+  }
+
+  #[test]
+  fn parses_ranges() {
+    assert_eq!(
+      Cidr::parse("10.0.0.1"),
+      Some(Cidr {
+        addr: ip("10.0.0.1"),
+        prefix: 32
+      })
+    );
+    assert_eq!(Cidr::parse("fe80::/10").map(|c| c.prefix), Some(10));
+    assert_eq!(Cidr::parse("10.0.0.0/33"), None);
+    assert_eq!(Cidr::parse("10.0.0/8"), None);
+  }
+
+  #[test]
+  fn matches_ranges() {
+    let lan = Cidr::parse("192.168.1.0/24").unwrap();
+    assert!(lan.contains(&ip("192.168.1.77")));
+    assert!(lan.contains(&ip("::ffff:192.168.1.77")));
+    assert!(!lan.contains(&ip("192.168.2.1")));
+    assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(&ip("8.8.8.8")));
+    assert!(Cidr::parse("fe80::/10")
+      .unwrap()
+      .contains(&ip("fe80::1234")));
+  }
+
+  #[test]
+  fn deny_wins_over_allow() {
+    let config = config("192.168.1.0/24, 127.0.0.1", "192.168.1.13");
+    assert!(config.permits(&ip("127.0.0.1")));
+    assert!(config.permits(&ip("192.168.1.12")));
+    assert!(!config.permits(&ip("192.168.1.13")));
+    assert!(!config.permits(&ip("10.0.0.1")));
+    assert!(ServerAccessConfig::from_serde_value(&Value::Null, "test").permits(&ip("10.0.0.1")));
+  }
+
+  #[test]
+  fn limits_requests_and_connections() {
+    let access = ServerAccess::new(&config("", ""));
+    let peer = ip("10.0.0.1");
+    assert!(access.check_request(&peer).is_ok());
+    assert!(access.check_request(&peer).is_ok());
+    assert!(access.check_request(&peer).is_err());
+    assert!(access.check_request(&ip("10.0.0.2")).is_ok());
+
+    let first = access.connect(&peer).unwrap();
+    let _second = access.connect(&peer).unwrap();
+    assert!(access.connect(&peer).is_err());
+    drop(first);
+    assert!(access.connect(&peer).is_ok());
+  }
+
+  #[test]
+  fn locks_out_after_failures() {
+    let access = ServerAccess::new(&config("", ""));
+    let peer = ip("10.0.0.1");
+    for _ in 1..FREE_FAILURES {
+      assert_eq!(access.record_failure(&peer), None);
+    }
+    assert_eq!(access.locked_out(&peer), None);
+    assert_eq!(access.record_failure(&peer), Some(LOCKOUT_BASE));
+    assert!(access.locked_out(&peer).unwrap() > LOCKOUT_BASE / 2);
+    assert!(access.locked_out(&ip("::ffff:10.0.0.1")).is_some());
+    assert_eq!(access.locked_out(&ip("10.0.0.2")), None);
+
+    // Each further failure doubles the lockout, up to the maximum
+    assert_eq!(access.record_failure(&peer), Some(LOCKOUT_BASE * 2));
+    for _ in 0..40 {
+      access.record_failure(&peer);
+    }
+    assert_eq!(access.record_failure(&peer), Some(LOCKOUT_MAX));
+
+    access.clear_failures(&peer);
+    assert_eq!(access.locked_out(&peer), None);
+  }
+
+  #[tokio::test]
+  async fn falls_back_to_free_port() {
+    let config = ServerAccessConfig::from_serde_value(
+      &serde_json::json!({ "testBindAddress": "127.0.0.1" }),
+      "test",
+    );
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+
+    assert!(config.bind(port, 0).await.is_err());
+    let listener = config.bind(port, 20).await.unwrap();
+    assert_ne!(listener.local_addr().unwrap().port(), port);
+  }
+}
@@ -0,0 +1,105 @@
+use log::{error, info};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use serde_json::Value;
+use std::{net::IpAddr, sync::OnceLock};
+
+/// How often the daemon looks for added or removed network interfaces and
+/// announces the services on new ones, in seconds.
+const INTERFACE_CHECK_SECS: u32 = 5;
+
+/// Shared by every advertised service, started on first use.
+static DAEMON: OnceLock<Option<ServiceDaemon>> = OnceLock::new();
+
+fn daemon() -> Option<&'static ServiceDaemon> {
+  DAEMON
+    .get_or_init(|| match ServiceDaemon::new() {
+      Ok(daemon) => {
+        daemon.set_ip_check_interval(INTERFACE_CHECK_SECS).ok();
+        Some(daemon)
+      }
+      Err(e) => {
+        error!("mDNS could not start: {}", e);
+        None
+      }
+    })
+    .as_ref()
+}
+
+/// Reads `mdnsName`, the instance name services are advertised with and the
+/// host name they are found at under `.local`. Empty turns advertising off.
+pub fn mdns_name(v: &Value) -> Option<String> {
+  let name = v["mdnsName"]
+    .as_str()
+    .unwrap_or("slidershim")
+    .trim()
+    .to_lowercase()
+    .chars()
+    .map(|c| match c.is_ascii_alphanumeric() {
+      true => c,
+      false => '-',
+    })
+    .take(63)
+    .collect::<String>();
+  let name = name.trim_matches('-');
+  match name.is_empty() {
+    true => None,
+    false => Some(name.to_string()),
+  }
+}
+
+/// A service advertised over mDNS/DNS-SD until dropped.
+pub struct Advertisement {
+  fullname: String,
+}
+
+impl Advertisement {
+  /// Advertises `instance` of `service_type`, such as `_http._tcp`, on the
+  /// host `name.local`. A server bound to every interface is advertised on
+  /// all of them, one bound to loopback is not advertised.
+  pub fn new(
+    service_type: &str,
+    name: &str,
+    instance: &str,
+    bind: IpAddr,
+    port: u16,
+    properties: &[(&str, &str)],
+  ) -> Option<Self> {
+    if bind.is_loopback() {
+      return None;
+    }
+    let ty_domain = format!("{}.local.", service_type);
+    let host = format!("{}.local.", name);
+    let info = match bind.is_unspecified() {
+      true => ServiceInfo::new(&ty_domain, instance, &host, (), port, properties)
+        .map(|info| info.enable_addr_auto()),
+      false => ServiceInfo::new(&ty_domain, instance, &host, bind, port, properties),
+    };
+    let info = match info {
+      Ok(info) => info,
+      Err(e) => {
+        error!("mDNS service {} is invalid: {}", instance, e);
+        return None;
+      }
+    };
+
+    let fullname = info.get_fullname().to_string();
+    match daemon()?.register(info) {
+      Ok(_) => {
+        info!("mDNS advertising {} on {}:{}", fullname, host, port);
+        Some(Self { fullname })
+      }
+      Err(e) => {
+        error!("mDNS could not advertise {}: {}", fullname, e);
+        None
+      }
+    }
+  }
+}
+
+impl Drop for Advertisement {
+  fn drop(&mut self) {
+    if let Some(daemon) = daemon() {
+      daemon.unregister(&self.fullname).ok();
+    }
+  }
+}
@@ -1,4 +1,6 @@
+pub mod access;
 pub mod hori;
+pub mod mdns;
 pub mod serial;
 pub mod utils;
 pub mod voltex;
@@ -1,18 +1,111 @@
 use async_trait::async_trait;
-use log::info;
+use futures::FutureExt;
+use log::{error, info};
+use parking_lot::Mutex;
+use serde_json::Value;
 use std::{
+  any::Any,
+  collections::BTreeMap,
   future::Future,
+  panic::{self, AssertUnwindSafe},
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
   },
   thread,
+  time::{Duration, Instant},
 };
 
-use tokio::{sync::oneshot, task};
+use tokio::{
+  sync::oneshot,
+  task,
+  time::{interval, Interval},
+};
+
+use crate::state::SliderState;
 
 use super::utils::LoopTimer;
 
+/// Extracts a readable message from a caught panic payload.
+pub fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+  match (
+    payload.downcast_ref::<&'static str>(),
+    payload.downcast_ref::<String>(),
+  ) {
+    (Some(s), _) => s.to_string(),
+    (_, Some(s)) => s.clone(),
+    (None, None) => "unknown panic".to_string(),
+  }
+}
+
+/// Records that a worker stopped for good after a panic, so that the UI shows
+/// it instead of a device or output that silently does nothing.
+fn report_panic(state: &SliderState, name: &'static str, message: String) {
+  state.failsafe();
+  state.control.set_worker_status(
+    name,
+    WorkerStatus::failed(format!(
+      "Stopped after an internal error, save the settings again to restart: {}",
+      message
+    )),
+  );
+}
+
+/// Time of the latest tick of a worker, or `None` while the worker is not
+/// looping (before setup completes or after it stops).
+#[derive(Clone)]
+pub struct WorkerHeartbeat {
+  name: &'static str,
+  last_tick: Arc<Mutex<Option<Instant>>>,
+}
+
+impl WorkerHeartbeat {
+  fn new(name: &'static str) -> Self {
+    Self {
+      name,
+      last_tick: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  fn beat(&self) {
+    self.last_tick.lock().replace(Instant::now());
+  }
+
+  fn stop(&self) {
+    self.last_tick.lock().take();
+  }
+
+  fn elapsed(&self) -> Option<Duration> {
+    self.last_tick.lock().map(|x| x.elapsed())
+  }
+}
+
+/// What a worker reports about itself for the UI, such as the ports a server
+/// bound or why it could not start.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerStatus {
+  /// Why the worker is not running.
+  pub error: Option<String>,
+  /// Ports bound by a server, named after the config key they stand in for.
+  pub ports: BTreeMap<&'static str, u16>,
+}
+
+impl WorkerStatus {
+  pub fn failed(error: String) -> Self {
+    Self {
+      error: Some(error),
+      ..Self::default()
+    }
+  }
+
+  pub fn to_serde_value(&self) -> Value {
+    serde_json::json!({
+      "error": self.error,
+      "ports": self.ports,
+    })
+  }
+}
+
 pub trait ThreadJob: Send {
   fn setup(&mut self) -> bool;
   fn tick(&mut self) -> bool;
@@ -22,15 +115,24 @@ pub struct ThreadWorker {
   name: &'static str,
   thread: Option<thread::JoinHandle<()>>,
   stop_signal: Arc<AtomicBool>,
+  heartbeat: WorkerHeartbeat,
 }
 
 impl ThreadWorker {
-  pub fn new<T: 'static + ThreadJob>(name: &'static str, mut job: T, mut timer: LoopTimer) -> Self {
+  pub fn new<T: 'static + ThreadJob>(
+    name: &'static str,
+    mut job: T,
+    mut timer: LoopTimer,
+    state: &SliderState,
+  ) -> Self {
     info!("Thread worker starting {}", name);
 
     let stop_signal = Arc::new(AtomicBool::new(false));
+    let heartbeat = WorkerHeartbeat::new(name);
 
     let stop_signal_clone = Arc::clone(&stop_signal);
+    let heartbeat_clone = heartbeat.clone();
+    let state = state.clone();
     Self {
       name,
       thread: Some(thread::spawn(move || {
@@ -41,15 +143,29 @@ impl ThreadWorker {
           if stop_signal_clone.load(Ordering::SeqCst) {
             break;
           }
-          if job.tick() {
-            timer.tick();
+          heartbeat_clone.beat();
+          match panic::catch_unwind(AssertUnwindSafe(|| job.tick())) {
+            Ok(true) => timer.tick(),
+            Ok(false) => {}
+            Err(e) => {
+              let message = panic_message(&e);
+              error!("Thread worker panicked {}: {}", name, message);
+              report_panic(&state, name, message);
+              break;
+            }
           }
         }
+        heartbeat_clone.stop();
         info!("Thread worker received stop {}", name);
       })),
       stop_signal,
+      heartbeat,
     }
   }
+
+  pub fn heartbeat(&self) -> WorkerHeartbeat {
+    self.heartbeat.clone()
+  }
 }
 
 impl Drop for ThreadWorker {
@@ -75,18 +191,22 @@ pub struct AsyncWorker {
   name: &'static str,
   task: Option<task::JoinHandle<()>>,
   stop_signal: Arc<AtomicBool>,
+  heartbeat: WorkerHeartbeat,
 }
 
 impl AsyncWorker {
-  pub fn new<T>(name: &'static str, mut job: T, mut timer: LoopTimer) -> Self
+  pub fn new<T>(name: &'static str, mut job: T, mut timer: LoopTimer, state: &SliderState) -> Self
   where
     T: AsyncJob,
   {
     info!("Async worker starting {}", name);
 
     let stop_signal = Arc::new(AtomicBool::new(false));
+    let heartbeat = WorkerHeartbeat::new(name);
 
     let stop_signal_clone = Arc::clone(&stop_signal);
+    let heartbeat_clone = heartbeat.clone();
+    let state = state.clone();
     let task = tokio::spawn(async move {
       let setup_res = job.setup().await;
       stop_signal_clone.store(!setup_res, Ordering::SeqCst);
@@ -95,10 +215,19 @@ impl AsyncWorker {
         if stop_signal_clone.load(Ordering::SeqCst) {
           break;
         }
-        if job.tick().await {
-          timer.tick();
+        heartbeat_clone.beat();
+        match AssertUnwindSafe(job.tick()).catch_unwind().await {
+          Ok(true) => timer.tick(),
+          Ok(false) => {}
+          Err(e) => {
+            let message = panic_message(&e);
+            error!("Async worker panicked {}: {}", name, message);
+            report_panic(&state, name, message);
+            break;
+          }
         }
       }
+      heartbeat_clone.stop();
       info!("Async worker received stop {}", name);
     });
 
@@ -106,8 +235,13 @@ impl AsyncWorker {
       name,
       task: Some(task),
       stop_signal,
+      heartbeat,
     }
   }
+
+  pub fn heartbeat(&self) -> WorkerHeartbeat {
+    self.heartbeat.clone()
+  }
 }
 
 impl Drop for AsyncWorker {
@@ -133,7 +267,7 @@ pub struct AsyncHaltableWorker {
 }
 
 impl AsyncHaltableWorker {
-  pub fn new<T>(name: &'static str, job: T) -> Self
+  pub fn new<T>(name: &'static str, job: T, state: &SliderState) -> Self
   where
     T: AsyncHaltableJob,
   {
@@ -141,13 +275,20 @@ impl AsyncHaltableWorker {
 
     let (send_stop, recv_stop) = oneshot::channel::<()>();
 
+    let state = state.clone();
     let task = tokio::spawn(async move {
-      job
-        .run(async move {
-          recv_stop.await.ok();
-          info!("AsyncHaltable worker received stop  {}", name);
-        })
-        .await;
+      let res = AssertUnwindSafe(job.run(async move {
+        recv_stop.await.ok();
+        info!("AsyncHaltable worker received stop  {}", name);
+      }))
+      .catch_unwind()
+      .await;
+
+      if let Err(e) = res {
+        let message = panic_message(&e);
+        error!("AsyncHaltable worker panicked {}: {}", name, message);
+        report_panic(&state, name, message);
+      }
     });
 
     Self {
@@ -169,3 +310,90 @@ impl Drop for AsyncHaltableWorker {
     info!("AsyncHaltable worker stopped {}", self.name);
   }
 }
+
+const STALL_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Watches the heartbeats of other workers and triggers the failsafe once
+/// when any of them stops ticking for too long.
+pub struct StallJob {
+  state: SliderState,
+  heartbeats: Vec<WorkerHeartbeat>,
+  stalled: Vec<bool>,
+  timer: Interval,
+}
+
+impl StallJob {
+  pub fn new(state: &SliderState, heartbeats: Vec<WorkerHeartbeat>) -> Self {
+    Self {
+      state: state.clone(),
+      stalled: vec![false; heartbeats.len()],
+      heartbeats,
+      timer: interval(Duration::from_millis(250)),
+    }
+  }
+}
+
+#[async_trait]
+impl AsyncJob for StallJob {
+  async fn setup(&mut self) -> bool {
+    true
+  }
+
+  async fn tick(&mut self) -> bool {
+    for (heartbeat, stalled) in self.heartbeats.iter().zip(self.stalled.iter_mut()) {
+      match (heartbeat.elapsed(), *stalled) {
+        (Some(elapsed), false) if elapsed > STALL_TIMEOUT => {
+          error!(
+            "Worker stalled {}: no tick for {:?}",
+            heartbeat.name, elapsed
+          );
+          *stalled = true;
+          self.state.failsafe();
+        }
+        (Some(elapsed), true) if elapsed <= STALL_TIMEOUT => {
+          info!("Worker recovered {}", heartbeat.name);
+          *stalled = false;
+        }
+        _ => {}
+      }
+    }
+    self.timer.tick().await;
+
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct PanicJob;
+
+  impl ThreadJob for PanicJob {
+    fn setup(&mut self) -> bool {
+      true
+    }
+
+    fn tick(&mut self) -> bool {
+      panic!("broken device")
+    }
+  }
+
+  #[test]
+  fn reports_panicked_worker() {
+    let state = SliderState::new();
+    let worker = ThreadWorker::new("device", PanicJob, LoopTimer::new(), &state);
+    let start = Instant::now();
+    while state.control.get_worker_status()["device"].is_null() {
+      assert!(start.elapsed() < Duration::from_secs(5));
+      thread::sleep(Duration::from_millis(10));
+    }
+    let error = state.control.get_worker_status()["device"]["error"]
+      .as_str()
+      .unwrap()
+      .to_string();
+    assert!(error.contains("broken device"), "{}", error);
+    assert_eq!(state.control.failsafe.load(Ordering::SeqCst), 1);
+    drop(worker);
+  }
+}
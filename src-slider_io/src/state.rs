@@ -1,5 +1,20 @@
+use log::{info, warn};
 use parking_lot::Mutex;
-use std::{sync::Arc, time::Instant};
+use serde_json::Value;
+use std::{
+  collections::BTreeMap,
+  sync::{
+    atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering},
+    Arc, Weak,
+  },
+  time::Instant,
+};
+use tokio::sync::Notify;
+
+use crate::{
+  device::{brokenithm_clients::ClientInputs, brokenithm_status::ClientList},
+  shared::worker::WorkerStatus,
+};
 
 /// Stores the input state of a slider controller, including ground touch pads,
 /// air strings and extra buttons.
@@ -28,6 +43,13 @@ impl SliderInput {
     }
   }
 
+  /// Releases all ground, air and extra inputs.
+  pub fn clear(&mut self) {
+    self.ground.fill(0);
+    self.air.fill(0);
+    self.extra.fill(0);
+  }
+
   /// Converts an input state to a `Vec<bool>`, used for output simulation and
   /// visualisation.
   pub fn to_flat(&self, sensitivity: &u8) -> Vec<bool> {
@@ -116,6 +138,169 @@ impl SliderLights {
     self.air_right.fill(0);
     self.dirty = true;
   }
+
+  /// Scales every pixel by `brightness / 255`.
+  pub fn scale(&mut self, brightness: u8) {
+    if brightness == 255 {
+      return;
+    }
+    for x in self
+      .ground
+      .iter_mut()
+      .chain(self.air_left.iter_mut())
+      .chain(self.air_right.iter_mut())
+    {
+      *x = ((*x as u16 * brightness as u16) / 255) as u8;
+    }
+  }
+}
+
+/// Runtime switches that can be flipped while a context is running, either by
+/// the meta-button layer or by the UI. These outlive a single context so that
+/// they survive config rebuilds.
+pub struct SliderControl {
+  /// When set, outputs are released and no input is forwarded to them.
+  /// Changed through `set_output_paused` and `toggle_output_paused`.
+  pub output_paused: AtomicBool,
+
+  /// Signalled after output was paused or resumed, so that the tray can
+  /// follow changes made by meta chords and hand gestures.
+  pub output_paused_changed: Notify,
+
+  /// When set, air strings are masked out before reaching outputs.
+  pub air_disabled: AtomicBool,
+
+  /// Number of times the lighting mode has been cycled.
+  pub lights_cycle: AtomicUsize,
+
+  /// Global lighting brightness from 0 to 255.
+  pub brightness: AtomicU8,
+
+  /// Signalled when the next config profile should be loaded.
+  pub profile_switch: Notify,
+
+  /// Signalled after a device saved settings to the config file itself, such
+  /// as a hand tracking calibration.
+  pub config_saved: Notify,
+
+  /// Number of times the failsafe has been triggered. Outputs reset themselves
+  /// whenever this changes.
+  pub failsafe: AtomicUsize,
+
+  /// PIN that pairs a device with the Brokenithm server, 0 until pairing is
+  /// first enabled. Kept across config rebuilds so that it does not change
+  /// while a user is typing it. Wrong guesses lock out the address that made
+  /// them instead of replacing it.
+  pub brokenithm_pin: AtomicU32,
+
+  /// Signalled after a paired device was revoked, so that its open
+  /// connections are closed.
+  pub devices_revoked: Notify,
+
+  /// Status reported by workers, by worker name. Cleared when a context is
+  /// built.
+  pub worker_status: Mutex<BTreeMap<&'static str, WorkerStatus>>,
+
+  /// Websocket clients connected to the Brokenithm server.
+  pub brokenithm_clients: Mutex<ClientList>,
+}
+
+impl SliderControl {
+  pub fn new() -> Self {
+    Self {
+      output_paused: AtomicBool::new(false),
+      output_paused_changed: Notify::new(),
+      air_disabled: AtomicBool::new(false),
+      lights_cycle: AtomicUsize::new(0),
+      brightness: AtomicU8::new(255),
+      profile_switch: Notify::new(),
+      config_saved: Notify::new(),
+      failsafe: AtomicUsize::new(0),
+      brokenithm_pin: AtomicU32::new(0),
+      devices_revoked: Notify::new(),
+      worker_status: Mutex::new(BTreeMap::new()),
+      brokenithm_clients: Mutex::new(ClientList::new()),
+    }
+  }
+
+  pub fn is_output_paused(&self) -> bool {
+    self.output_paused.load(Ordering::SeqCst)
+  }
+
+  pub fn set_output_paused(&self, paused: bool) {
+    if self.output_paused.swap(paused, Ordering::SeqCst) != paused {
+      self.output_paused_changed.notify_one();
+    }
+  }
+
+  pub fn toggle_output_paused(&self) {
+    self.output_paused.fetch_xor(true, Ordering::SeqCst);
+    self.output_paused_changed.notify_one();
+  }
+
+  pub fn is_air_disabled(&self) -> bool {
+    self.air_disabled.load(Ordering::SeqCst)
+  }
+
+  pub fn get_lights_cycle(&self) -> usize {
+    self.lights_cycle.load(Ordering::SeqCst)
+  }
+
+  pub fn get_brightness(&self) -> u8 {
+    self.brightness.load(Ordering::SeqCst)
+  }
+
+  pub fn get_failsafe(&self) -> usize {
+    self.failsafe.load(Ordering::SeqCst)
+  }
+
+  pub fn get_brokenithm_pin(&self) -> Option<u32> {
+    match self.brokenithm_pin.load(Ordering::SeqCst) {
+      0 => None,
+      pin => Some(pin),
+    }
+  }
+
+  pub fn set_worker_status(&self, name: &'static str, status: WorkerStatus) {
+    self.worker_status.lock().insert(name, status);
+  }
+
+  /// Status of each worker that reported one, as an object keyed by worker
+  /// name.
+  pub fn get_worker_status(&self) -> Value {
+    Value::Object(
+      self
+        .worker_status
+        .lock()
+        .iter()
+        .map(|(name, status)| (name.to_string(), status.to_serde_value()))
+        .collect(),
+    )
+  }
+}
+
+/// Tracks when the input device last delivered a report or heartbeat, used by
+/// the stale input watchdog.
+pub struct InputFreshness {
+  /// Time of the latest report, `None` until the device first reports.
+  pub last_report: Option<Instant>,
+
+  /// Set by the watchdog once the input has been cleared for being stale.
+  pub stale: bool,
+
+  /// Input buffers of devices with several clients, whose staleness the
+  /// watchdog checks for each client instead.
+  pub clients: Option<Weak<Mutex<ClientInputs>>>,
+}
+
+impl InputFreshness {
+  pub fn new() -> Self {
+    Self {
+      last_report: None,
+      stale: false,
+      clients: None,
+    }
+  }
 }
 
 /// Stores data required for a single slider controller. Data and lighting
@@ -127,14 +312,52 @@ pub struct SliderState {
 
   /// Lighting data for the slider controller.
   pub lights: Arc<Mutex<SliderLights>>,
+
+  /// Runtime switches shared with the manager.
+  pub control: Arc<SliderControl>,
+
+  /// Freshness of the input data for the stale input watchdog.
+  pub freshness: Arc<Mutex<InputFreshness>>,
 }
 
 impl SliderState {
   /// Creates a blank slider controller state
   pub fn new() -> Self {
+    Self::with_control(&Arc::new(SliderControl::new()))
+  }
+
+  /// Creates a blank slider controller state that shares existing runtime
+  /// switches.
+  pub fn with_control(control: &Arc<SliderControl>) -> Self {
     Self {
       input: Arc::new(Mutex::new(SliderInput::new())),
       lights: Arc::new(Mutex::new(SliderLights::new())),
+      control: Arc::clone(control),
+      freshness: Arc::new(Mutex::new(InputFreshness::new())),
+    }
+  }
+
+  /// Clears all input and asks every output to release held keys and buttons.
+  /// Used when a worker panics or stalls.
+  pub fn failsafe(&self) {
+    warn!("Failsafe triggered, releasing all inputs and outputs");
+    self.input.lock().clear();
+    self.control.failsafe.fetch_add(1, Ordering::SeqCst);
+  }
+
+  /// Records that the device delivered a fresh report or heartbeat. Returns
+  /// `true` if the input had been cleared as stale, in which case the device
+  /// should write its full state again even if it did not change.
+  pub fn mark_report(&self) -> bool {
+    let mut freshness_handle = self.freshness.lock();
+    freshness_handle.last_report = Some(Instant::now());
+    match freshness_handle.stale {
+      true => {
+        info!("Device input fresh again");
+        freshness_handle.stale = false;
+        true
+      }
+      false => false,
     }
   }
 
@@ -164,6 +387,8 @@ impl Clone for SliderState {
     Self {
       input: Arc::clone(&self.input),
       lights: Arc::clone(&self.lights),
+      control: Arc::clone(&self.control),
+      freshness: Arc::clone(&self.freshness),
     }
   }
 }
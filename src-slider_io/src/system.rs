@@ -2,7 +2,13 @@ use directories::ProjectDirs;
 use image::Luma;
 use log::info;
 use qrcode::QrCode;
-use std::{error::Error, fs, path::PathBuf};
+use std::{
+  error::Error,
+  fs,
+  net::Ipv4Addr,
+  path::PathBuf,
+  time::{SystemTime, UNIX_EPOCH},
+};
 
 pub fn list_ips() -> Result<Vec<String>, Box<dyn Error>> {
   let mut ips = vec![];
@@ -25,21 +31,39 @@ fn get_config_dir() -> Option<Box<PathBuf>> {
   Some(Box::new(config_dir.to_path_buf()))
 }
 
-/// Generates a helper QR for connecting with brokenithm
-pub fn get_brokenithm_qr_path(port: Option<u16>) -> Option<Box<PathBuf>> {
+/// Generates a helper QR for connecting with brokenithm. The helper page
+/// only links to plain HTTP, so a server that only speaks HTTPS gets a direct
+/// link to its LAN address instead.
+pub fn get_brokenithm_qr_path(port: Option<u16>, https_only: bool) -> Option<Box<PathBuf>> {
   let config_dir = get_config_dir()?;
   let brokenithm_qr_path = config_dir.join("brokenithm.png");
 
   let ips = list_ips().ok()?;
-  let link = "http://imp.ress.me/t/sshelper?d=".to_string()
-    + &ips
-      .into_iter()
-      .filter(|s| s.as_str().chars().filter(|x| *x == '.').count() == 3)
-      .map(|s| base64::encode_config(s, base64::URL_SAFE_NO_PAD))
-      .collect::<Vec<String>>()
-      .join(";")
-    + "&p="
-    + port.or(Some(1606)).unwrap().to_string().as_str();
+  let port = port.unwrap_or(1606);
+  let link = match https_only {
+    true => {
+      let ips = ips
+        .iter()
+        .filter_map(|s| s.parse::<Ipv4Addr>().ok())
+        .collect::<Vec<_>>();
+      let ip = ips
+        .iter()
+        .find(|ip| !ip.is_loopback() && !ip.is_link_local())
+        .or_else(|| ips.first())?;
+      format!("https://{}:{}/", ip, port)
+    }
+    false => {
+      "http://imp.ress.me/t/sshelper?d=".to_string()
+        + &ips
+          .into_iter()
+          .filter(|s| s.as_str().chars().filter(|x| *x == '.').count() == 3)
+          .map(|s| base64::encode_config(s, base64::URL_SAFE_NO_PAD))
+          .collect::<Vec<String>>()
+          .join(";")
+        + "&p="
+        + port.to_string().as_str()
+    }
+  };
   info!("Url generated {}", link);
   let qr = QrCode::new(link).ok()?;
   let image = qr.render::<Luma<u8>>().build();
@@ -48,6 +72,40 @@ pub fn get_brokenithm_qr_path(port: Option<u16>) -> Option<Box<PathBuf>> {
   return Some(Box::new(brokenithm_qr_path));
 }
 
+/// Paths of the generated Brokenithm TLS certificate and private key, along
+/// with a list of the addresses the certificate was issued for.
+pub fn get_brokenithm_cert_paths() -> Option<(PathBuf, PathBuf, PathBuf)> {
+  let config_dir = get_config_dir()?;
+
+  Some((
+    config_dir.join("brokenithm-cert.pem"),
+    config_dir.join("brokenithm-key.pem"),
+    config_dir.join("brokenithm-cert-hosts.txt"),
+  ))
+}
+
+/// Folder of user skins for the Brokenithm web controller, one folder per skin.
+pub fn get_skins_dir() -> Option<PathBuf> {
+  let skins_dir = get_config_dir()?.join("skins");
+  fs::create_dir_all(&skins_dir).ok()?;
+
+  Some(skins_dir)
+}
+
+/// Devices paired with the Brokenithm server and their tokens.
+pub fn get_paired_devices_path() -> Option<PathBuf> {
+  Some(get_config_dir()?.join("brokenithm-devices.json"))
+}
+
+/// Path of a new hand tracking recording, named after the current time.
+pub fn get_hand_recording_path() -> Option<PathBuf> {
+  let recording_dir = get_config_dir()?.join("hand-recordings");
+  fs::create_dir_all(&recording_dir).ok()?;
+  let secs = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+  Some(recording_dir.join(format!("hands-{}.jsonl", secs)))
+}
+
 pub fn get_log_file_path() -> Option<Box<PathBuf>> {
   let config_dir = get_config_dir()?;
   let log_path = config_dir.join("log.txt");
@@ -31,6 +31,35 @@ fn quit_app() {
   std::process::exit(0);
 }
 
+fn set_output_paused(manager: &Arc<Mutex<slider_io::Manager>>, paused: bool) {
+  let manager_handle = manager.lock();
+  match paused {
+    true => manager_handle.pause_output(),
+    false => manager_handle.resume_output(),
+  }
+}
+
+/// Shows whether output is paused in the tray and the UI.
+fn show_output_paused<R: Runtime>(handle: &AppHandle<R>, paused: bool) {
+  handle
+    .tray_handle()
+    .get_item("pause")
+    .set_title(match paused {
+      true => "Resume Output",
+      false => "Pause Output",
+    })
+    .ok();
+  handle.emit_all("showOutputPaused", paused).ok();
+}
+
+fn show_paired_devices<R: Runtime>(handle: &AppHandle<R>) {
+  let devices = slider_io::device::brokenithm_pairing::load_paired_devices()
+    .iter()
+    .map(|d| d.to_serde_value())
+    .collect::<Vec<_>>();
+  handle.emit_all("showPairedDevices", devices).ok();
+}
+
 fn main() {
   // Setup logger
 
@@ -59,6 +88,7 @@ fn main() {
   }
 
   info!("Running tauri");
+  let manager_clone = Arc::clone(&manager);
   tauri::Builder::default()
     .system_tray(
       // System tray content
@@ -66,10 +96,11 @@ fn main() {
         SystemTrayMenu::new()
           .add_item(CustomMenuItem::new("slidershim".to_string(), "slidershim").disabled())
           .add_item(CustomMenuItem::new("show".to_string(), "Show"))
+          .add_item(CustomMenuItem::new("pause".to_string(), "Pause Output"))
           .add_item(CustomMenuItem::new("quit".to_string(), "Quit")),
       ),
     )
-    .on_system_tray_event(|app_handle, event| match event {
+    .on_system_tray_event(move |app_handle, event| match event {
       // System tray events
       SystemTrayEvent::LeftClick {
         position: _,
@@ -82,6 +113,10 @@ fn main() {
         "show" => {
           show_window(app_handle);
         }
+        "pause" => {
+          let paused = manager_clone.lock().is_output_paused();
+          set_output_paused(&manager_clone, !paused);
+        }
         "quit" => {
           quit_app();
         }
@@ -105,6 +140,24 @@ fn main() {
         quit_app();
       });
 
+      // Follow pausing from the tray, the UI, meta chords and hand gestures
+      let app_handle = app.handle();
+      manager.lock().watch_output_paused(move |paused| {
+        show_output_paused(&app_handle, paused);
+      });
+
+      // Pause output event
+      let manager_clone = Arc::clone(&manager);
+      app.listen_global("pauseOutput", move |_| {
+        set_output_paused(&manager_clone, true);
+      });
+
+      // Resume output event
+      let manager_clone = Arc::clone(&manager);
+      app.listen_global("resumeOutput", move |_| {
+        set_output_paused(&manager_clone, false);
+      });
+
       // Show logs
       app.listen_global("openLogfile", |_| {
         let log_file_path = slider_io::get_log_file_path();
@@ -115,13 +168,18 @@ fn main() {
 
       // Show brokenithm qr
       let config_clone = Arc::clone(&config);
+      let manager_clone = Arc::clone(&manager);
       app.listen_global("openBrokenithmQr", move |_| {
+        // The server may have fallen back to another port
+        let bound_port = manager_clone.lock().get_worker_status()["brokenithm"]["ports"]
+          ["brokenithmPort"]
+          .as_u64()
+          .map(|port| port as u16);
         let config_handle = config_clone.lock();
+        let device_mode = config_handle.as_ref().map(|c| &c.device_mode);
         let brokenithm_qr_path = slider_io::get_brokenithm_qr_path(
-          config_handle
-            .as_ref()
-            .map(|c| c.device_mode.get_port())
-            .unwrap_or(None),
+          bound_port.or_else(|| device_mode.and_then(|mode| mode.get_port())),
+          device_mode.is_some_and(|mode| mode.is_https_only()),
         );
         if let Some(brokenithm_qr_path) = brokenithm_qr_path {
           open::that(brokenithm_qr_path.as_path()).ok();
@@ -154,16 +212,29 @@ fn main() {
 
       // UI update event
       let app_handle = app.handle();
+      let config_clone = Arc::clone(&config);
       let manager_clone = Arc::clone(&manager);
       app.listen_global("queryState", move |_| {
         // app_handle.emit_all("showState", "@@@");
-        let (snapshot, timer) = {
+        let (snapshot, timer, paused, saved_config, pin, worker_status, clients) = {
           let manager_handle = manager_clone.lock();
           (
             manager_handle.try_get_state().map(|x| x.snapshot()),
             manager_handle.get_timer_state(),
+            manager_handle.is_output_paused(),
+            manager_handle.take_saved_config(),
+            manager_handle.get_brokenithm_pin(),
+            manager_handle.get_worker_status(),
+            manager_handle.get_brokenithm_clients(),
           )
         };
+        if let Some(saved_config) = saved_config {
+          // Keep keys saved from outside the UI when the UI saves next
+          app_handle
+            .emit_all("reloadConfig", Some(saved_config.raw.clone()))
+            .ok();
+          config_clone.lock().replace(saved_config);
+        }
         match snapshot {
           Some(snapshot) => {
             app_handle.emit_all("showState", snapshot).ok();
@@ -172,6 +243,28 @@ fn main() {
         }
 
         app_handle.emit_all("showTimerState", timer).ok();
+        app_handle.emit_all("showOutputPaused", paused).ok();
+        app_handle.emit_all("showBrokenithmPin", pin).ok();
+        app_handle.emit_all("showWorkerStatus", worker_status).ok();
+        app_handle.emit_all("showBrokenithmClients", clients).ok();
+      });
+
+      // Paired devices query event
+      let app_handle = app.handle();
+      app.listen_global("queryPairedDevices", move |_| {
+        show_paired_devices(&app_handle);
+      });
+
+      // Paired device revoke event
+      let app_handle = app.handle();
+      let manager_clone = Arc::clone(&manager);
+      app.listen_global("revokePairedDevice", move |event| {
+        if let Some(id) = event.payload() {
+          manager_clone
+            .lock()
+            .revoke_paired_device(id.trim_matches('"'));
+        }
+        show_paired_devices(&app_handle);
       });
 
       // Config set event